@@ -0,0 +1,141 @@
+//! Measures how steadily the LED animator ticks while a scanner thread feeds the tracker, on the
+//! host. The animator either locks the tracker for every frame (`locked`) or reads the snapshots
+//! the tracker publishes (`snapshots`).
+//!
+//! `cargo run --release --example tick_jitter -- [locked|snapshots] [seconds]`
+
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use bracer_core::{
+    address::{AddressType, BleAddress},
+    ble_device_mgr::{DeviceTracker, FavoriteId, Pairing},
+    color_store::{ColorStore, MemoryBackend},
+    light_mgr::LightMgr,
+    messages::DisplaySortMode,
+    pixel_sink::{Color, PixelSink},
+    time::{Clock, Instant},
+    utils::JitterStats,
+};
+
+/// A busy room, each device is heard once per scan window
+const DEVICES: u8 = 100;
+
+/// The firmware's scan window
+const SCAN_WINDOW: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy)]
+struct HostClock(std::time::Instant);
+
+impl Clock for HostClock {
+    fn now(&self) -> Instant {
+        Instant::from_micros(self.0.elapsed().as_micros() as u64)
+    }
+}
+
+/// Throws frames away, only the timing matters
+struct NullSink;
+
+impl PixelSink for NullSink {
+    type Error = core::convert::Infallible;
+
+    fn update(&mut self, _colors: &[Color]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Flags, a name and manufacturer data, about what a phone advertises
+fn payload(i: u8) -> Vec<u8> {
+    [
+        &[0x02, 0x01, 0x06][..],
+        &[0x05, 0x09, b'D', b'e', b'v', b'0' + i % 10],
+        &[0x06, 0xff, 0x4c, 0x00, 0x10, 0x02, i],
+        &[0x02, 0x0a, 0x08],
+    ]
+    .concat()
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let locked = match args.next().as_deref() {
+        Some("locked") => true,
+        Some("snapshots") | None => false,
+        Some(mode) => panic!("unknown mode {mode}, expected locked or snapshots"),
+    };
+    let run_for = Duration::from_secs(args.next().map_or(10, |secs| secs.parse().unwrap()));
+
+    let clock = HostClock(std::time::Instant::now());
+    let pairing = Pairing {
+        id: FavoriteId::new("jitter").unwrap(),
+        hue: 0,
+    };
+    let tracker = Arc::new(Mutex::new(DeviceTracker::new(
+        pairing,
+        Default::default(),
+        clock,
+    )));
+    let mut snapshots = tracker.lock().unwrap().publish_snapshots();
+
+    // scan callbacks spread over each window, then the tracker expires devices as the firmware does
+    let scanner_tracker = tracker.clone();
+    let scanner = thread::spawn(move || {
+        let mut updates = 0u32;
+        let mut total = Duration::ZERO;
+        let mut longest = Duration::ZERO;
+        while clock.0.elapsed() < run_for {
+            for i in 0..DEVICES {
+                let start = std::time::Instant::now();
+                scanner_tracker.lock().unwrap().update(
+                    BleAddress([i, 0x22, 0x33, 0x44, 0x55, 0x66]),
+                    AddressType::Random,
+                    &payload(i),
+                    -40 - (updates % 50) as i32,
+                );
+                let elapsed = start.elapsed();
+                total += elapsed;
+                longest = longest.max(elapsed);
+                updates += 1;
+                thread::sleep((SCAN_WINDOW / DEVICES as u32).saturating_sub(elapsed));
+            }
+            scanner_tracker.lock().unwrap().expire();
+        }
+        (total / updates, longest)
+    });
+
+    let mut light_mgr = LightMgr::new(
+        DisplaySortMode::Ordered,
+        ColorStore::new(Box::<MemoryBackend>::default(), 32),
+        NullSink,
+        0,
+        clock,
+    );
+    let interval = light_mgr.get_tick_interval();
+    let mut jitter = JitterStats::new(interval);
+    while clock.0.elapsed() < run_for {
+        let start = std::time::Instant::now();
+        jitter.record(clock.now());
+        if locked {
+            light_mgr.update_devices(&mut tracker.lock().unwrap());
+        } else {
+            light_mgr.update_from_snapshot(snapshots.latest());
+        }
+        light_mgr.tick();
+        thread::sleep(interval.saturating_sub(start.elapsed()));
+    }
+
+    let (update_mean, update_max) = scanner.join().unwrap();
+    println!(
+        "{}: {} ticks of {:?}, jitter mean {:?}, max {:?}, {} missed; scan callbacks mean {:?}, max {:?}",
+        if locked { "locked" } else { "snapshots" },
+        jitter.ticks(),
+        interval,
+        jitter.mean(),
+        jitter.max,
+        jitter.missed,
+        update_mean,
+        update_max,
+    );
+}
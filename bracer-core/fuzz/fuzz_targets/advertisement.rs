@@ -0,0 +1,21 @@
+//! Scan results come from anyone in range, so parsing them must never panic, whatever the bytes.
+//!
+//! `cargo +nightly fuzz run advertisement` from `bracer-core`
+
+#![no_main]
+
+use bracer_core::{
+    advertisement::{self, AdvertisementSummary},
+    device_category::DeviceCategory,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|payload: &[u8]| {
+    let _ = advertisement::parse(payload).count();
+    let mut summary = AdvertisementSummary::from_payload(payload);
+    // the second half as a scan response from the same device
+    summary.merge(&AdvertisementSummary::from_payload(
+        &payload[payload.len() / 2..],
+    ));
+    let _ = DeviceCategory::classify(&summary);
+});
@@ -0,0 +1,102 @@
+use core::{fmt, str::FromStr};
+
+/// Bluetooth device address, bytes in display order (most significant first)
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BleAddress(pub [u8; 6]);
+
+/// Whether an address is the device's registered public address or one it made up itself. Only
+/// random addresses can rotate.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AddressType {
+    Public,
+    #[default]
+    Random,
+}
+
+impl BleAddress {
+    /// Random address that rotates, e.g. every 15 minutes on most phones. Public addresses can
+    /// start with the same bits, so the type the address was received with is needed too.
+    pub fn is_resolvable_private(&self, address_type: AddressType) -> bool {
+        address_type == AddressType::Random && self.0[0] >> 6 == 0b01
+    }
+}
+
+impl fmt::Display for BleAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a, b, c, d, e, g
+        )
+    }
+}
+
+impl fmt::Debug for BleAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseAddressError;
+
+impl FromStr for BleAddress {
+    type Err = ParseAddressError;
+
+    /// Parse the display form, e.g. "AA:BB:CC:DD:EE:FF"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut addr = [0; 6];
+        let mut parts = s.split(':');
+        for byte in addr.iter_mut() {
+            let part = parts.next().ok_or(ParseAddressError)?;
+            if part.len() != 2 {
+                return Err(ParseAddressError);
+            }
+            *byte = u8::from_str_radix(part, 16).map_err(|_| ParseAddressError)?;
+        }
+
+        if parts.next().is_some() {
+            return Err(ParseAddressError);
+        }
+
+        Ok(Self(addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn address_round_trip() {
+        let addr = BleAddress([0xAA, 0xBB, 0xCC, 0x01, 0x02, 0x03]);
+        assert_eq!(addr.to_string(), "AA:BB:CC:01:02:03");
+        assert_eq!("AA:BB:CC:01:02:03".parse(), Ok(addr));
+        assert_eq!("aa:bb:cc:01:02:03".parse(), Ok(addr));
+
+        assert_eq!(
+            "AA:BB:CC:01:02".parse::<BleAddress>(),
+            Err(ParseAddressError)
+        );
+        assert_eq!(
+            "AA:BB:CC:01:02:03:04".parse::<BleAddress>(),
+            Err(ParseAddressError)
+        );
+        assert_eq!(
+            "AA:BB:CC:01:02:0G".parse::<BleAddress>(),
+            Err(ParseAddressError)
+        );
+    }
+
+    #[test]
+    fn resolvable_private() {
+        let random =
+            |first| BleAddress([first, 0, 0, 0, 0, 0]).is_resolvable_private(AddressType::Random);
+        assert!(random(0x5A));
+        assert!(!random(0xDA));
+        assert!(!random(0x1A));
+        assert!(!BleAddress([0x5A, 0, 0, 0, 0, 0]).is_resolvable_private(AddressType::Public));
+    }
+}
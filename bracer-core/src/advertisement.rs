@@ -0,0 +1,502 @@
+//! Decoding of raw BLE advertising data, a sequence of length-prefixed AD structures.
+//!
+//! Payloads come straight off the air, so parsing never panics on malformed input: bad structures
+//! are reported as errors and everything after them is skipped.
+
+pub mod vendor;
+
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use self::vendor::VendorInfo;
+
+const AD_TYPE_FLAGS: u8 = 0x01;
+const AD_TYPE_UUID16_INCOMPLETE: u8 = 0x02;
+const AD_TYPE_UUID16_COMPLETE: u8 = 0x03;
+const AD_TYPE_UUID32_INCOMPLETE: u8 = 0x04;
+const AD_TYPE_UUID32_COMPLETE: u8 = 0x05;
+const AD_TYPE_UUID128_INCOMPLETE: u8 = 0x06;
+const AD_TYPE_UUID128_COMPLETE: u8 = 0x07;
+const AD_TYPE_NAME_SHORT: u8 = 0x08;
+const AD_TYPE_NAME_COMPLETE: u8 = 0x09;
+const AD_TYPE_TX_POWER: u8 = 0x0a;
+const AD_TYPE_SERVICE_DATA16: u8 = 0x16;
+const AD_TYPE_APPEARANCE: u8 = 0x19;
+const AD_TYPE_SERVICE_DATA32: u8 = 0x20;
+const AD_TYPE_SERVICE_DATA128: u8 = 0x21;
+const AD_TYPE_MANUFACTURER_DATA: u8 = 0xff;
+
+/// How many service UUIDs an [`AdvertisementSummary`] keeps
+pub const MAX_SUMMARY_SERVICE_UUIDS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Uuid {
+    Uuid16(u16),
+    Uuid32(u32),
+    Uuid128(u128),
+}
+
+/// Only needed for fixed capacity storage
+impl Default for Uuid {
+    fn default() -> Self {
+        Uuid::Uuid16(0)
+    }
+}
+
+impl Uuid {
+    /// Size in bytes over the air
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            Uuid::Uuid16(_) => 2,
+            Uuid::Uuid32(_) => 4,
+            Uuid::Uuid128(_) => 16,
+        }
+    }
+
+    /// Little endian, `data` must be 2, 4 or 16 bytes
+    pub(crate) fn from_le_slice(data: &[u8]) -> Option<Self> {
+        match data.len() {
+            2 => Some(Uuid::Uuid16(u16::from_le_bytes(data.try_into().ok()?))),
+            4 => Some(Uuid::Uuid32(u32::from_le_bytes(data.try_into().ok()?))),
+            16 => Some(Uuid::Uuid128(u128::from_le_bytes(data.try_into().ok()?))),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Uuid::Uuid16(uuid) => out.extend_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid32(uuid) => out.extend_from_slice(&uuid.to_le_bytes()),
+            Uuid::Uuid128(uuid) => out.extend_from_slice(&uuid.to_le_bytes()),
+        }
+    }
+}
+
+/// A list of service UUIDs, all the same size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServiceUuids<'a> {
+    uuid_size: usize,
+    data: &'a [u8],
+    /// The list holds every service the device has
+    pub complete: bool,
+}
+
+impl<'a> ServiceUuids<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = Uuid> + 'a {
+        self.data
+            .chunks_exact(self.uuid_size)
+            .filter_map(Uuid::from_le_slice)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdStructure<'a> {
+    Flags(u8),
+    ServiceUuids(ServiceUuids<'a>),
+    /// Raw bytes, not necessarily valid UTF-8
+    LocalName(&'a [u8]),
+    /// dBm
+    TxPower(i8),
+    /// GAP appearance, the top 10 bits are the category
+    Appearance(u16),
+    ServiceData {
+        uuid: Uuid,
+        data: &'a [u8],
+    },
+    /// Includes the little endian company ID, at least 2 bytes
+    ManufacturerData(&'a [u8]),
+    /// Any type not decoded above
+    Other {
+        ad_type: u8,
+        data: &'a [u8],
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// A structure's length runs past the end of the payload
+    Truncated,
+    /// A structure is too short, or the wrong size, for its type
+    Malformed { ad_type: u8 },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Truncated => f.write_str("truncated AD structure"),
+            ParseError::Malformed { ad_type } => {
+                write!(f, "malformed AD structure of type {:#04x}", ad_type)
+            }
+        }
+    }
+}
+
+impl<'a> AdStructure<'a> {
+    fn decode(ad_type: u8, data: &'a [u8]) -> Result<Self, ParseError> {
+        let malformed = ParseError::Malformed { ad_type };
+        let service_uuids = |uuid_size: usize, complete: bool| {
+            if !data.chunks_exact(uuid_size).remainder().is_empty() {
+                return Err(malformed);
+            }
+            Ok(AdStructure::ServiceUuids(ServiceUuids {
+                uuid_size,
+                data,
+                complete,
+            }))
+        };
+        let service_data = |uuid_size: usize| {
+            if data.len() < uuid_size {
+                return Err(malformed);
+            }
+            let (uuid, data) = data.split_at(uuid_size);
+            Ok(AdStructure::ServiceData {
+                uuid: Uuid::from_le_slice(uuid).ok_or(malformed)?,
+                data,
+            })
+        };
+
+        match ad_type {
+            AD_TYPE_FLAGS => match *data {
+                [flags] => Ok(AdStructure::Flags(flags)),
+                _ => Err(malformed),
+            },
+            AD_TYPE_UUID16_INCOMPLETE => service_uuids(2, false),
+            AD_TYPE_UUID16_COMPLETE => service_uuids(2, true),
+            AD_TYPE_UUID32_INCOMPLETE => service_uuids(4, false),
+            AD_TYPE_UUID32_COMPLETE => service_uuids(4, true),
+            AD_TYPE_UUID128_INCOMPLETE => service_uuids(16, false),
+            AD_TYPE_UUID128_COMPLETE => service_uuids(16, true),
+            AD_TYPE_NAME_SHORT | AD_TYPE_NAME_COMPLETE => Ok(AdStructure::LocalName(data)),
+            AD_TYPE_TX_POWER => match *data {
+                [tx_power] => Ok(AdStructure::TxPower(tx_power as i8)),
+                _ => Err(malformed),
+            },
+            AD_TYPE_APPEARANCE => match *data {
+                [low, high] => Ok(AdStructure::Appearance(u16::from_le_bytes([low, high]))),
+                _ => Err(malformed),
+            },
+            AD_TYPE_SERVICE_DATA16 => service_data(2),
+            AD_TYPE_SERVICE_DATA32 => service_data(4),
+            AD_TYPE_SERVICE_DATA128 => service_data(16),
+            AD_TYPE_MANUFACTURER_DATA if data.len() >= 2 => Ok(AdStructure::ManufacturerData(data)),
+            AD_TYPE_MANUFACTURER_DATA => Err(malformed),
+            _ => Ok(AdStructure::Other { ad_type, data }),
+        }
+    }
+
+    /// Append as a length-prefixed AD structure, structures over 254 bytes are truncated
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let start = out.len();
+        out.push(0);
+        match *self {
+            AdStructure::Flags(flags) => out.extend_from_slice(&[AD_TYPE_FLAGS, flags]),
+            AdStructure::ServiceUuids(uuids) => {
+                let ad_type = match (uuids.uuid_size, uuids.complete) {
+                    (2, false) => AD_TYPE_UUID16_INCOMPLETE,
+                    (2, true) => AD_TYPE_UUID16_COMPLETE,
+                    (4, false) => AD_TYPE_UUID32_INCOMPLETE,
+                    (4, true) => AD_TYPE_UUID32_COMPLETE,
+                    (_, false) => AD_TYPE_UUID128_INCOMPLETE,
+                    (_, true) => AD_TYPE_UUID128_COMPLETE,
+                };
+                out.push(ad_type);
+                out.extend_from_slice(uuids.data);
+            }
+            AdStructure::LocalName(name) => {
+                out.push(AD_TYPE_NAME_COMPLETE);
+                out.extend_from_slice(name);
+            }
+            AdStructure::TxPower(tx_power) => {
+                out.extend_from_slice(&[AD_TYPE_TX_POWER, tx_power as u8])
+            }
+            AdStructure::Appearance(appearance) => {
+                out.push(AD_TYPE_APPEARANCE);
+                out.extend_from_slice(&appearance.to_le_bytes());
+            }
+            AdStructure::ServiceData { uuid, data } => {
+                out.push(match uuid {
+                    Uuid::Uuid16(_) => AD_TYPE_SERVICE_DATA16,
+                    Uuid::Uuid32(_) => AD_TYPE_SERVICE_DATA32,
+                    Uuid::Uuid128(_) => AD_TYPE_SERVICE_DATA128,
+                });
+                uuid.encode(out);
+                out.extend_from_slice(data);
+            }
+            AdStructure::ManufacturerData(data) => {
+                out.push(AD_TYPE_MANUFACTURER_DATA);
+                out.extend_from_slice(data);
+            }
+            AdStructure::Other { ad_type, data } => {
+                out.push(ad_type);
+                out.extend_from_slice(data);
+            }
+        }
+        out.truncate(start + 1 + u8::MAX as usize);
+        out[start] = (out.len() - start - 1) as u8;
+    }
+}
+
+/// Encode a list of service UUIDs, all of `uuids` must be the same size as the first
+pub fn encode_service_uuids(uuids: &[Uuid], complete: bool, out: &mut Vec<u8>) {
+    let Some(uuid_size) = uuids.first().map(Uuid::size) else {
+        return;
+    };
+    let mut data = Vec::new();
+    for uuid in uuids.iter().filter(|uuid| uuid.size() == uuid_size) {
+        uuid.encode(&mut data);
+    }
+    AdStructure::ServiceUuids(ServiceUuids {
+        uuid_size,
+        data: &data,
+        complete,
+    })
+    .encode(out);
+}
+
+/// Iterator over the AD structures in a payload, see [`parse`]
+#[derive(Debug, Clone)]
+pub struct AdStructures<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for AdStructures<'a> {
+    type Item = Result<AdStructure<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&len, rest) = self.remaining.split_first()?;
+        // a zero length marks the start of padding
+        if len == 0 {
+            self.remaining = &[];
+            return None;
+        }
+        let Some(structure) = rest.get(..len as usize) else {
+            self.remaining = &[];
+            return Some(Err(ParseError::Truncated));
+        };
+        self.remaining = &rest[len as usize..];
+
+        let (&ad_type, data) = structure.split_first()?;
+        let decoded = AdStructure::decode(ad_type, data);
+        if decoded.is_err() {
+            self.remaining = &[];
+        }
+        Some(decoded)
+    }
+}
+
+/// Decode an advertising or scan response payload, stops after the first error
+pub fn parse(payload: &[u8]) -> AdStructures<'_> {
+    AdStructures { remaining: payload }
+}
+
+/// What a device has told us about itself, small enough to keep for every tracked device
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AdvertisementSummary {
+    pub flags: Option<u8>,
+    pub tx_power: Option<i8>,
+    pub appearance: Option<u16>,
+    /// From the manufacturer data
+    pub company_id: Option<u16>,
+    /// The first few advertised services
+    pub service_uuids: tinyvec::ArrayVec<[Uuid; MAX_SUMMARY_SERVICE_UUIDS]>,
+    pub vendor: Option<VendorInfo>,
+}
+
+impl AdvertisementSummary {
+    /// Summarize the well formed structures of a payload
+    pub fn from_payload(payload: &[u8]) -> Self {
+        let mut summary = Self::default();
+        for structure in parse(payload).flatten() {
+            match structure {
+                AdStructure::Flags(flags) => summary.flags = Some(flags),
+                AdStructure::TxPower(tx_power) => summary.tx_power = Some(tx_power),
+                AdStructure::Appearance(appearance) => summary.appearance = Some(appearance),
+                AdStructure::ServiceUuids(uuids) => {
+                    for uuid in uuids.iter() {
+                        if summary.service_uuids.try_push(uuid).is_some() {
+                            break;
+                        }
+                    }
+                }
+                AdStructure::ServiceData { uuid, data } => {
+                    summary.vendor = summary
+                        .vendor
+                        .or_else(|| vendor::decode_service_data(uuid, data));
+                }
+                AdStructure::ManufacturerData(data) => {
+                    let company_id = u16::from_le_bytes([data[0], data[1]]);
+                    summary.company_id = Some(company_id);
+                    summary.vendor = summary
+                        .vendor
+                        .or_else(|| vendor::decode_manufacturer_data(company_id, &data[2..]));
+                }
+                AdStructure::LocalName(_) | AdStructure::Other { .. } => {}
+            }
+        }
+        summary
+    }
+
+    /// Advertisements and scan responses carry different fields, keep the latest of each
+    pub fn merge(&mut self, newer: &Self) {
+        self.flags = newer.flags.or(self.flags);
+        self.tx_power = newer.tx_power.or(self.tx_power);
+        self.appearance = newer.appearance.or(self.appearance);
+        self.company_id = newer.company_id.or(self.company_id);
+        if !newer.service_uuids.is_empty() {
+            self.service_uuids = newer.service_uuids;
+        }
+        self.vendor = newer.vendor.or(self.vendor);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Recorded payloads, see the vendor module tests for what they decode to
+    pub const IBEACON: &[u8] = &[
+        0x02, 0x01, 0x06, 0x1a, 0xff, 0x4c, 0x00, 0x02, 0x15, 0xe2, 0xc5, 0x6d, 0xb5, 0xdf, 0xfb,
+        0x48, 0xd2, 0xb0, 0x60, 0xd0, 0xf5, 0xa7, 0x10, 0x96, 0xe0, 0x00, 0x01, 0x00, 0x02, 0xc5,
+    ];
+    pub const EDDYSTONE_URL: &[u8] = &[
+        0x02, 0x01, 0x06, 0x03, 0x03, 0xaa, 0xfe, 0x0d, 0x16, 0xaa, 0xfe, 0x10, 0xf8, 0x03, 0x67,
+        0x6f, 0x6f, 0x67, 0x6c, 0x65, 0x07,
+    ];
+    pub const APPLE_NEARBY_INFO: &[u8] = &[
+        0x02, 0x01, 0x1a, 0x02, 0x0a, 0x0c, 0x0a, 0xff, 0x4c, 0x00, 0x10, 0x05, 0x0b, 0x1c, 0x6f,
+        0x3e, 0x1a,
+    ];
+    pub const MICROSOFT_CDP: &[u8] = &[
+        0x1e, 0xff, 0x06, 0x00, 0x01, 0x09, 0x20, 0x02, 0x6c, 0x3d, 0x8e, 0x2f, 0x81, 0x5c, 0xb2,
+        0x1a, 0xd4, 0x47, 0x13, 0x90, 0x0e, 0x6b, 0x29, 0xf4, 0x55, 0x8a, 0x3c, 0x77, 0x01, 0xe9,
+        0x4d,
+    ];
+    pub const FAST_PAIR: &[u8] = &[
+        0x03, 0x03, 0x2c, 0xfe, 0x06, 0x16, 0x2c, 0xfe, 0x00, 0xb7, 0x27, 0x02, 0x0a, 0xf4,
+    ];
+    pub const AIRTAG_SEPARATED: &[u8] = &[
+        0x1e, 0xff, 0x4c, 0x00, 0x12, 0x19, 0x10, 0x6e, 0x2b, 0x91, 0xd4, 0x07, 0x5f, 0xc3, 0x18,
+        0xa2, 0x4e, 0x90, 0x3d, 0xb6, 0x71, 0x0c, 0xe8, 0x52, 0x2f, 0x9a, 0x44, 0x61, 0xd7, 0x02,
+        0x8b,
+    ];
+    pub const TILE: &[u8] = &[
+        0x02, 0x01, 0x06, 0x03, 0x03, 0xed, 0xfe, 0x0b, 0x16, 0xed, 0xfe, 0x02, 0x00, 0x9c, 0x41,
+        0x3e, 0x57, 0x0a, 0xb8,
+    ];
+    pub const SMART_TAG: &[u8] = &[
+        0x02, 0x01, 0x06, 0x03, 0x03, 0x5a, 0xfd, 0x13, 0x16, 0x5a, 0xfd, 0x10, 0x42, 0x0e, 0x7c,
+        0xa1, 0x33, 0x58, 0xd0, 0x09, 0x00, 0x00, 0x00, 0x00, 0x1f, 0x6b, 0xc2,
+    ];
+
+    #[test]
+    fn parse_structures() {
+        let structures: Vec<_> = parse(FAST_PAIR).collect();
+        assert_eq!(structures.len(), 3);
+        match structures[0] {
+            Ok(AdStructure::ServiceUuids(uuids)) => {
+                assert!(uuids.complete);
+                assert!(uuids.iter().eq([Uuid::Uuid16(0xfe2c)]));
+            }
+            ref other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            structures[1],
+            Ok(AdStructure::ServiceData {
+                uuid: Uuid::Uuid16(0xfe2c),
+                data: &[0x00, 0xb7, 0x27],
+            })
+        );
+        assert_eq!(structures[2], Ok(AdStructure::TxPower(-12)));
+    }
+
+    #[test]
+    fn parse_stops_at_errors_and_padding() {
+        // truncated length
+        assert!(parse(&[0x02, 0x01, 0x06, 0x05, 0xff, 0x4c])
+            .eq([Ok(AdStructure::Flags(0x06)), Err(ParseError::Truncated)]));
+
+        // manufacturer data without a company ID, later structures are skipped
+        assert!(parse(&[0x02, 0xff, 0x4c, 0x02, 0x01, 0x06])
+            .eq([Err(ParseError::Malformed { ad_type: 0xff })]));
+
+        // zero padding after the last structure
+        assert!(parse(&[0x02, 0x01, 0x06, 0x00, 0x00, 0x00]).eq([Ok(AdStructure::Flags(0x06))]));
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        for payload in [
+            IBEACON,
+            EDDYSTONE_URL,
+            APPLE_NEARBY_INFO,
+            MICROSOFT_CDP,
+            FAST_PAIR,
+            AIRTAG_SEPARATED,
+            TILE,
+            SMART_TAG,
+        ] {
+            let mut encoded = Vec::new();
+            for structure in parse(payload) {
+                structure.unwrap().encode(&mut encoded);
+            }
+            assert_eq!(encoded, payload);
+        }
+
+        let mut encoded = Vec::new();
+        encode_service_uuids(
+            &[Uuid::Uuid16(0xfeaa), Uuid::Uuid128(1), Uuid::Uuid16(0xfe2c)],
+            true,
+            &mut encoded,
+        );
+        assert_eq!(encoded, [0x05, 0x03, 0xaa, 0xfe, 0x2c, 0xfe]);
+    }
+
+    #[test]
+    fn summary_merges_scan_responses() {
+        let mut summary = AdvertisementSummary::from_payload(FAST_PAIR);
+        assert_eq!(summary.tx_power, Some(-12));
+        assert_eq!(summary.service_uuids.as_slice(), [Uuid::Uuid16(0xfe2c)]);
+        assert_eq!(
+            summary.vendor,
+            Some(VendorInfo::FastPair(vendor::FastPair::ModelId(0x00b727)))
+        );
+
+        summary.merge(&AdvertisementSummary::from_payload(&[
+            0x02, 0x01, 0x06, 0x03, 0x19, 0xc1, 0x00,
+        ]));
+        assert_eq!(summary.flags, Some(0x06));
+        assert_eq!(summary.appearance, Some(0x00c1));
+        assert_eq!(summary.tx_power, Some(-12));
+        assert!(summary.vendor.is_some());
+    }
+
+    /// Poor man's fuzzing, mutations of recorded payloads must never panic
+    #[test]
+    fn mutated_payloads_dont_panic() {
+        use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+        let mut rng = SmallRng::seed_from_u64(0);
+        for payload in [
+            IBEACON,
+            EDDYSTONE_URL,
+            APPLE_NEARBY_INFO,
+            MICROSOFT_CDP,
+            FAST_PAIR,
+            AIRTAG_SEPARATED,
+            TILE,
+            SMART_TAG,
+        ] {
+            for _ in 0..2000 {
+                let mut mutated = payload.to_vec();
+                for _ in 0..rng.gen_range(1..4) {
+                    let idx = rng.gen_range(0..mutated.len());
+                    mutated[idx] = rng.gen();
+                }
+                mutated.truncate(rng.gen_range(0..=mutated.len()));
+                let _ = parse(&mutated).count();
+                let _ = AdvertisementSummary::from_payload(&mutated);
+            }
+        }
+    }
+}
@@ -0,0 +1,358 @@
+//! Decoders for well known vendor formats found in manufacturer and service data.
+//!
+//! Only enough is decoded to tell what kind of device is advertising, payloads that don't match a
+//! known layout are ignored rather than reported.
+
+use alloc::string::String;
+
+use super::Uuid;
+
+const COMPANY_MICROSOFT: u16 = 0x0006;
+const COMPANY_APPLE: u16 = 0x004c;
+
+const SERVICE_EDDYSTONE: u16 = 0xfeaa;
+const SERVICE_FAST_PAIR: u16 = 0xfe2c;
+
+const APPLE_TYPE_IBEACON: u8 = 0x02;
+const IBEACON_LEN: u8 = 0x15;
+
+/// Find My payloads this long are the full public key broadcast while away from the owner
+const FIND_MY_SEPARATED_LEN: u8 = 0x19;
+
+const CDP_SCENARIO_BEACON: u8 = 0x01;
+
+/// Scheme byte plus the longest URL an Eddystone frame can carry
+pub const MAX_EDDYSTONE_URL_LEN: usize = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VendorInfo {
+    IBeacon(IBeacon),
+    Eddystone(Eddystone),
+    /// Type of the first message in an Apple Continuity payload
+    AppleContinuity(ContinuityType),
+    /// Microsoft Connected Devices Platform, e.g. a Windows PC or Xbox
+    MicrosoftCdp {
+        device_type: u8,
+    },
+    FastPair(FastPair),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IBeacon {
+    pub uuid: u128,
+    pub major: u16,
+    pub minor: u16,
+    /// Calibrated RSSI at one meter
+    pub measured_power: i8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eddystone {
+    Uid {
+        /// Calibrated RSSI at zero meters
+        tx_power: i8,
+        namespace: [u8; 10],
+        instance: [u8; 6],
+    },
+    Url {
+        tx_power: i8,
+        /// Compressed as sent, see [`Eddystone::url`]
+        encoded: tinyvec::ArrayVec<[u8; MAX_EDDYSTONE_URL_LEN]>,
+    },
+    /// Unencrypted telemetry
+    Tlm {
+        battery_mv: u16,
+        /// 8.8 fixed point degrees Celsius
+        temperature: i16,
+        advertisement_count: u32,
+        /// Tenths of a second since boot
+        uptime: u32,
+    },
+    Eid {
+        tx_power: i8,
+        eid: [u8; 8],
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContinuityType {
+    AirPrint,
+    AirDrop,
+    HomeKit,
+    /// AirPods and Beats status
+    ProximityPairing,
+    HeySiri,
+    AirPlayTarget,
+    AirPlaySource,
+    MagicSwitch,
+    Handoff,
+    TetheringTarget,
+    TetheringSource,
+    NearbyAction,
+    NearbyInfo,
+    /// AirTags and lost devices, `separated` once away from the owner's devices
+    FindMy {
+        separated: bool,
+    },
+    Other(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FastPair {
+    /// Sent while discoverable, a 24 bit model ID
+    ModelId(u32),
+    /// Sent while not discoverable, the account key filter isn't decoded
+    AccountData,
+}
+
+impl ContinuityType {
+    fn from_message(message_type: u8, len: u8) -> Self {
+        match message_type {
+            0x03 => Self::AirPrint,
+            0x05 => Self::AirDrop,
+            0x06 => Self::HomeKit,
+            0x07 => Self::ProximityPairing,
+            0x08 => Self::HeySiri,
+            0x09 => Self::AirPlayTarget,
+            0x0a => Self::AirPlaySource,
+            0x0b => Self::MagicSwitch,
+            0x0c => Self::Handoff,
+            0x0d => Self::TetheringTarget,
+            0x0e => Self::TetheringSource,
+            0x0f => Self::NearbyAction,
+            0x10 => Self::NearbyInfo,
+            0x12 => Self::FindMy {
+                separated: len >= FIND_MY_SEPARATED_LEN,
+            },
+            other => Self::Other(other),
+        }
+    }
+}
+
+impl Eddystone {
+    /// Expanded URL of a URL frame
+    pub fn url(&self) -> Option<String> {
+        let Eddystone::Url { encoded, .. } = self else {
+            return None;
+        };
+        let (&scheme, rest) = encoded.split_first()?;
+        let mut url = String::from(
+            *["http://www.", "https://www.", "http://", "https://"].get(scheme as usize)?,
+        );
+        for &byte in rest {
+            match byte {
+                0x00 => url.push_str(".com/"),
+                0x01 => url.push_str(".org/"),
+                0x02 => url.push_str(".edu/"),
+                0x03 => url.push_str(".net/"),
+                0x04 => url.push_str(".info/"),
+                0x05 => url.push_str(".biz/"),
+                0x06 => url.push_str(".gov/"),
+                0x07 => url.push_str(".com"),
+                0x08 => url.push_str(".org"),
+                0x09 => url.push_str(".edu"),
+                0x0a => url.push_str(".net"),
+                0x0b => url.push_str(".info"),
+                0x0c => url.push_str(".biz"),
+                0x0d => url.push_str(".gov"),
+                0x21..=0x7e => url.push(byte as char),
+                _ => return None,
+            }
+        }
+        Some(url)
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        let (&frame_type, frame) = data.split_first()?;
+        match frame_type {
+            0x00 => {
+                // two reserved bytes at the end are optional in practice
+                let tx_power = *frame.first()? as i8;
+                Some(Eddystone::Uid {
+                    tx_power,
+                    namespace: frame.get(1..11)?.try_into().ok()?,
+                    instance: frame.get(11..17)?.try_into().ok()?,
+                })
+            }
+            0x10 => {
+                let (&tx_power, url) = frame.split_first()?;
+                if url.is_empty() || url.len() > MAX_EDDYSTONE_URL_LEN {
+                    return None;
+                }
+                let mut encoded = tinyvec::ArrayVec::new();
+                encoded.extend_from_slice(url);
+                Some(Eddystone::Url {
+                    tx_power: tx_power as i8,
+                    encoded,
+                })
+            }
+            0x20 => {
+                // only the unencrypted version 0 is readable
+                let frame: &[u8; 13] = frame.try_into().ok()?;
+                if frame[0] != 0 {
+                    return None;
+                }
+                Some(Eddystone::Tlm {
+                    battery_mv: u16::from_be_bytes([frame[1], frame[2]]),
+                    temperature: i16::from_be_bytes([frame[3], frame[4]]),
+                    advertisement_count: u32::from_be_bytes(frame[5..9].try_into().ok()?),
+                    uptime: u32::from_be_bytes(frame[9..13].try_into().ok()?),
+                })
+            }
+            0x30 => {
+                let frame: &[u8; 9] = frame.try_into().ok()?;
+                Some(Eddystone::Eid {
+                    tx_power: frame[0] as i8,
+                    eid: frame[1..].try_into().ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Decode manufacturer data, `data` excludes the company ID
+pub fn decode_manufacturer_data(company_id: u16, data: &[u8]) -> Option<VendorInfo> {
+    match company_id {
+        COMPANY_APPLE => match *data {
+            [APPLE_TYPE_IBEACON, IBEACON_LEN, ref beacon @ ..] => {
+                let beacon: &[u8; 21] = beacon.try_into().ok()?;
+                Some(VendorInfo::IBeacon(IBeacon {
+                    uuid: u128::from_be_bytes(beacon[0..16].try_into().ok()?),
+                    major: u16::from_be_bytes([beacon[16], beacon[17]]),
+                    minor: u16::from_be_bytes([beacon[18], beacon[19]]),
+                    measured_power: beacon[20] as i8,
+                }))
+            }
+            [message_type, len, ref rest @ ..] if rest.len() >= len as usize => Some(
+                VendorInfo::AppleContinuity(ContinuityType::from_message(message_type, len)),
+            ),
+            _ => None,
+        },
+        COMPANY_MICROSOFT => match *data {
+            [CDP_SCENARIO_BEACON, version_and_type, ..] => Some(VendorInfo::MicrosoftCdp {
+                device_type: version_and_type & 0x1f,
+            }),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Decode service data, `data` excludes the UUID
+pub fn decode_service_data(uuid: Uuid, data: &[u8]) -> Option<VendorInfo> {
+    match uuid {
+        Uuid::Uuid16(SERVICE_EDDYSTONE) => Eddystone::decode(data).map(VendorInfo::Eddystone),
+        Uuid::Uuid16(SERVICE_FAST_PAIR) => match *data {
+            [] => None,
+            [a, b, c] => Some(VendorInfo::FastPair(FastPair::ModelId(u32::from_be_bytes(
+                [0, a, b, c],
+            )))),
+            _ => Some(VendorInfo::FastPair(FastPair::AccountData)),
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::*, AdvertisementSummary};
+    use super::*;
+
+    fn vendor(payload: &[u8]) -> Option<VendorInfo> {
+        AdvertisementSummary::from_payload(payload).vendor
+    }
+
+    #[test]
+    fn recorded_payloads() {
+        assert_eq!(
+            vendor(IBEACON),
+            Some(VendorInfo::IBeacon(IBeacon {
+                uuid: 0xe2c56db5_dffb_48d2_b060_d0f5a71096e0,
+                major: 1,
+                minor: 2,
+                measured_power: -59,
+            }))
+        );
+
+        let Some(VendorInfo::Eddystone(eddystone)) = vendor(EDDYSTONE_URL) else {
+            panic!("not an Eddystone payload");
+        };
+        assert!(matches!(eddystone, Eddystone::Url { tx_power: -8, .. }));
+        assert_eq!(eddystone.url().as_deref(), Some("https://google.com"));
+
+        assert_eq!(
+            vendor(APPLE_NEARBY_INFO),
+            Some(VendorInfo::AppleContinuity(ContinuityType::NearbyInfo))
+        );
+        assert_eq!(
+            vendor(MICROSOFT_CDP),
+            Some(VendorInfo::MicrosoftCdp { device_type: 9 })
+        );
+        assert_eq!(
+            vendor(FAST_PAIR),
+            Some(VendorInfo::FastPair(FastPair::ModelId(0x00b727)))
+        );
+        assert_eq!(
+            vendor(AIRTAG_SEPARATED),
+            Some(VendorInfo::AppleContinuity(ContinuityType::FindMy {
+                separated: true
+            }))
+        );
+        assert_eq!(
+            decode_manufacturer_data(COMPANY_APPLE, &[0x12, 0x02, 0x24, 0x01]),
+            Some(VendorInfo::AppleContinuity(ContinuityType::FindMy {
+                separated: false
+            }))
+        );
+    }
+
+    #[test]
+    fn eddystone_frames() {
+        let tlm = [
+            0x20, 0x00, 0x0b, 0xb8, 0x17, 0x80, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00,
+        ];
+        assert_eq!(
+            Eddystone::decode(&tlm),
+            Some(Eddystone::Tlm {
+                battery_mv: 3000,
+                temperature: 0x1780,
+                advertisement_count: 256,
+                uptime: 512,
+            })
+        );
+
+        let mut uid = [0; 18];
+        uid[1] = 0xee;
+        uid[17] = 0x42;
+        assert!(matches!(
+            Eddystone::decode(&uid[..]),
+            Some(Eddystone::Uid {
+                tx_power: -18,
+                instance: [0, 0, 0, 0, 0, 0x42],
+                ..
+            })
+        ));
+        assert_eq!(Eddystone::decode(&uid[..10]), None);
+
+        // unknown URL scheme
+        let url = Eddystone::decode(&[0x10, 0x00, 0x04, b'a']).unwrap();
+        assert_eq!(url.url(), None);
+    }
+
+    #[test]
+    fn unknown_payloads_are_ignored() {
+        // iBeacon prefix with the wrong length
+        assert_eq!(
+            decode_manufacturer_data(COMPANY_APPLE, &[0x02, 0x15, 0x00]),
+            None
+        );
+        // Continuity message longer than the payload
+        assert_eq!(
+            decode_manufacturer_data(COMPANY_APPLE, &[0x12, 0x19, 0x00]),
+            None
+        );
+        assert_eq!(decode_manufacturer_data(0xffff, &[0x12, 0x00]), None);
+        assert_eq!(decode_service_data(Uuid::Uuid16(0x180f), &[0x64]), None);
+    }
+}
@@ -0,0 +1,1285 @@
+use core::time::Duration;
+
+use alloc::{boxed::Box, vec::Vec};
+use log::{debug, info};
+#[cfg(not(test))]
+use num::Float;
+use palette::RgbHue;
+
+use crate::{
+    address::{AddressType, BleAddress},
+    advertisement::{self, AdStructure, AdvertisementSummary},
+    config::{Config, SignalFilterKind},
+    device_events::{DeviceEvent, DeviceEventSink},
+    distance::Distance,
+    favorite_payload::{FavoritePayload, PayloadError},
+    follow_detector::{self, FollowDetector},
+    identity::Sighting,
+    light_mgr::DEFAULT_BRIGHTNESS,
+    messages::DisplaySortMode,
+    presence::PresenceStats,
+    rules::{RuleAction, Rules, ScannedDevice},
+    signal_filter::{ConfiguredFilter, SignalFilter},
+    spam_detector::SpamDetector,
+    time::{Clock, Instant},
+    trend::{Trend, TrendEstimator},
+    triple_buffer::{self, Publisher, Reader},
+    utils::AlphaBetaFilter,
+};
+
+/// How much to trust each new sample over the current estimate
+const SIGNAL_FILTER_ALPHA: f32 = 0.5;
+
+/// How quickly to adjust the rate of change of signal strength
+const SIGNAL_FILTER_BETA: f32 = 0.1;
+
+/// Stop predicting signal strength this long after the last sample
+const SIGNAL_MAX_EXTRAPOLATION: Duration = Duration::from_secs(1);
+
+/// Longest name kept for a device, all a legacy advertisement has room for
+const MAX_NAME_LEN: usize = 29;
+
+// type BLEAddressStr = String;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Device {
+    /// First address the device was seen with, kept when it rotates to a new one so the device
+    /// keeps its color and slot
+    pub address: BleAddress,
+    /// Latest payload from a device in our group
+    pub favorite: Option<FavoritePayload>,
+    /// Everything else the device advertised, merged across advertisements and scan responses
+    pub advertisement: AdvertisementSummary,
+    /// Name the device last advertised, it may only come in the scan response
+    pub name: tinyvec::ArrayVec<[u8; MAX_NAME_LEN]>,
+    /// Action of the rule the device matched on its last advertisement. Rules see everything it
+    /// has advertised, so a name match holds between scan responses, and the latest signal
+    /// strength, so a signal range match doesn't.
+    pub rule: Option<RuleAction>,
+    /// Smoothed by the filter [`Config::signal_filter`] picks, or the device's rule does
+    pub signal_strength: ConfiguredFilter,
+    signal_filter: AlphaBetaFilter,
+    /// Recent raw signal strengths, for whether the device is approaching or receding
+    trend: TrendEstimator,
+    /// How often and how steadily the device has been heard, for whether it is really around
+    pub presence: PresenceStats,
+    /// When the device was first heard, under any address
+    pub first_seen: Instant,
+    /// Latest advertisement, from the address the device currently uses
+    last_seen: Sighting,
+    /// Quiet for longer than [`Config::decay_delay`], as of the last [`DeviceTracker::expire`]
+    decaying: bool,
+}
+
+/// Pairing ID shared with the favorite device, advertised as a [`FavoritePayload::group_id`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FavoriteId {
+    bytes: [u8; FavoriteId::MAX_LEN],
+    len: u8,
+}
+
+impl FavoriteId {
+    pub const MAX_LEN: usize = 16;
+
+    /// `None` if empty or too long
+    pub fn new(id: &str) -> Option<Self> {
+        if id.is_empty() || id.len() > Self::MAX_LEN {
+            return None;
+        }
+        let mut bytes = [0; Self::MAX_LEN];
+        bytes[..id.len()].copy_from_slice(id.as_bytes());
+        Some(Self {
+            bytes,
+            len: id.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ever built from a str
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// FNV-1a hash of the ID, what actually goes over the air. It is unsalted and never changes,
+    /// so anyone who records it can replay it and be shown as a favorite. It keeps the ID itself
+    /// out of the air, nothing more.
+    pub fn group_id(&self) -> u32 {
+        self.as_str().bytes().fold(0x811c_9dc5, |hash, byte| {
+            (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+        })
+    }
+}
+
+/// How we find, and are found by, the favorite device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Pairing {
+    pub id: FavoriteId,
+    /// Hue in degrees the favorite device should draw us with
+    pub hue: u16,
+}
+
+impl Pairing {
+    /// Bump whenever the serialized layout changes, older pairings are rejected
+    const VERSION: u8 = 1;
+
+    /// Size of the largest pairing in persistent storage
+    pub const MAX_SERIALIZED_LEN: usize = 3 + FavoriteId::MAX_LEN;
+
+    /// Version, hue as a little endian `u16`, then the ID
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = alloc::vec![Self::VERSION];
+        out.extend_from_slice(&self.hue.to_le_bytes());
+        out.extend_from_slice(self.id.as_str().as_bytes());
+        out
+    }
+
+    /// `None` if `data` isn't a pairing of this version
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let [Self::VERSION, low, high, ref id @ ..] = *data else {
+            return None;
+        };
+        let hue = u16::from_le_bytes([low, high]);
+        if hue >= 360 {
+            return None;
+        }
+        Some(Self {
+            id: core::str::from_utf8(id).ok().and_then(FavoriteId::new)?,
+            hue,
+        })
+    }
+}
+
+impl Device {
+    /// Address the device is advertising with now, see [`Self::address`]
+    pub fn current_address(&self) -> BleAddress {
+        self.last_seen.address
+    }
+
+    /// In our group, or marked as a favorite by a rule
+    pub fn is_favorite(&self) -> bool {
+        self.favorite_color().is_some()
+    }
+
+    pub fn favorite_color(&self) -> Option<RgbHue> {
+        let hue = match (self.favorite, self.rule) {
+            (Some(favorite), _) => favorite.hue,
+            (None, Some(RuleAction::Favorite(hue))) => hue,
+            _ => return None,
+        };
+        Some(RgbHue::from_degrees(hue as f32))
+    }
+
+    /// Color a rule says to draw the device with
+    pub fn pinned_color(&self) -> Option<RgbHue> {
+        match self.rule {
+            Some(RuleAction::PinColor(hue)) => Some(RgbHue::from_degrees(hue as f32)),
+            _ => None,
+        }
+    }
+
+    pub fn is_always_shown(&self) -> bool {
+        self.rule == Some(RuleAction::AlwaysShow)
+    }
+
+    /// Filter a rule picks for the device, otherwise the configured one
+    fn signal_filter_kind(&self, config: &Config) -> SignalFilterKind {
+        match self.rule {
+            Some(RuleAction::SignalFilter(kind)) => kind,
+            _ => config.signal_filter,
+        }
+    }
+
+    /// Switch to a new rule, and to the filter it picks
+    fn set_rule(&mut self, rule: Option<RuleAction>, config: &Config) {
+        let kind = self.signal_filter_kind(config);
+        self.rule = rule;
+        if self.signal_filter_kind(config) != kind {
+            self.signal_strength.reconfigure(
+                self.signal_filter_kind(config),
+                config.signal_moving_avg_window,
+            );
+        }
+    }
+
+    /// Averaged signal strength, decayed by how long the device has been quiet
+    pub fn signal_strength_at(&self, now: Instant, config: &Config) -> i32 {
+        let quiet = now.duration_since(self.last_seen.at);
+        config.decayed_signal(self.signal_strength.estimate(), quiet) as i32
+    }
+
+    /// Devices only advertise periodically, but we want to show them moving smoothly, so this
+    /// predicts the smoothed signal strength between advertisements, then decays it
+    pub fn interpolated_signal_strength(&self, now: Instant, config: &Config) -> i32 {
+        let quiet = now.duration_since(self.last_seen.at);
+        config
+            .decayed_signal(self.signal_filter.predict(now), quiet)
+            .round() as i32
+    }
+
+    /// How quickly the device is approaching (positive) or receding (negative), over the last
+    /// [`Config::trend_window`]
+    pub fn trend(&self, now: Instant, config: &Config) -> Trend {
+        self.trend.trend(config.trend_window, now)
+    }
+
+    /// How far away the device is, from its decayed signal strength and advertised TX power
+    pub fn distance(&self, now: Instant, config: &Config) -> Distance {
+        let quiet = now.duration_since(self.last_seen.at);
+        config.distance.estimate(
+            config.decayed_signal(self.signal_strength.estimate(), quiet),
+            self.signal_strength.variance(),
+            &self.advertisement,
+        )
+    }
+}
+
+/// What the lights need to know about a tracked device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceSnapshot {
+    pub address: BleAddress,
+    pub favorite_color: Option<RgbHue>,
+    pub pinned_color: Option<RgbHue>,
+    pub always_shown: bool,
+    pub advertisement: AdvertisementSummary,
+    signal_filter: AlphaBetaFilter,
+    /// How uncertain the device's signal strength is, see [`SignalFilter::variance`]
+    signal_variance: f32,
+    last_heard: Instant,
+    /// See [`Device::trend`], as of when the snapshot was taken
+    pub trend: Trend,
+    /// See [`PresenceStats::confidence`], as of when the snapshot was taken
+    pub presence: f32,
+    pub first_seen: Instant,
+}
+
+impl DeviceSnapshot {
+    fn interpolated_signal(&self, now: Instant, config: &Config) -> f32 {
+        let quiet = now.duration_since(self.last_heard);
+        config.decayed_signal(self.signal_filter.predict(now), quiet)
+    }
+
+    /// See [`Device::interpolated_signal_strength`], keeps moving and decaying after the snapshot
+    /// is taken
+    pub fn interpolated_signal_strength(&self, now: Instant, config: &Config) -> i32 {
+        self.interpolated_signal(now, config).round() as i32
+    }
+
+    /// See [`Device::distance`], from the interpolated signal strength
+    pub fn distance(&self, now: Instant, config: &Config) -> Distance {
+        config.distance.estimate(
+            self.interpolated_signal(now, config),
+            self.signal_variance,
+            &self.advertisement,
+        )
+    }
+
+    /// What devices are ranked by, their interpolated signal strength or, when
+    /// [`Config::rank_by_distance`] is set, the signal strength a typical device would have at
+    /// their distance
+    pub fn proximity(&self, now: Instant, config: &Config) -> i32 {
+        if config.rank_by_distance {
+            let meters = self.distance(now, config).meters;
+            config.distance.equivalent_signal(meters).round() as i32
+        } else {
+            self.interpolated_signal_strength(now, config)
+        }
+    }
+}
+
+/// Immutable view of the tracker, handed to the lights without holding up the tracker
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackerSnapshot {
+    /// Ranked: devices a rule always shows first, then closest first as of when it was taken, see
+    /// [`DeviceSnapshot::proximity`], then those seen first
+    pub devices: Vec<DeviceSnapshot>,
+    pub config: Config,
+    /// An item tracker is following us
+    pub followed: bool,
+    /// Addresses blocked so far, while a spam attack is going on
+    pub spam_blocked: Option<u32>,
+}
+
+pub struct DeviceTracker<C: Clock> {
+    /// Snapshot of everything tracked, for consumers that poll rather than subscribe
+    pub devices: Vec<Device>,
+
+    pairing: Pairing,
+    /// What we're showing, shared with the favorite device in our advertisement
+    brightness_level: u8,
+    mode: DisplaySortMode,
+    config: Config,
+    clock: C,
+    follow_detector: FollowDetector,
+    spam_detector: SpamDetector,
+    rules: Rules,
+    subscribers: Vec<Box<dyn DeviceEventSink>>,
+    snapshot_publisher: Option<Publisher<TrackerSnapshot>>,
+}
+
+impl<C: Clock> DeviceTracker<C> {
+    pub fn new(pairing: Pairing, config: Config, clock: C) -> Self {
+        Self {
+            devices: Vec::new(),
+            pairing,
+            brightness_level: DEFAULT_BRIGHTNESS,
+            mode: DisplaySortMode::Ordered,
+            config,
+            clock,
+            follow_detector: FollowDetector::new(),
+            spam_detector: SpamDetector::new(),
+            rules: Rules::new(),
+            subscribers: Vec::new(),
+            snapshot_publisher: None,
+        }
+    }
+
+    pub fn pairing(&self) -> &Pairing {
+        &self.pairing
+    }
+
+    /// Devices already matched as favorites stay favorites until they are forgotten
+    pub fn set_pairing(&mut self, pairing: Pairing) {
+        self.pairing = pairing;
+    }
+
+    pub fn set_display_state(&mut self, brightness_level: u8, mode: DisplaySortMode) {
+        self.brightness_level = brightness_level;
+        self.mode = mode;
+    }
+
+    /// Manufacturer data payload we advertise so our favorites can find us
+    pub fn advertisement(&self) -> FavoritePayload {
+        FavoritePayload {
+            group_id: self.pairing.id.group_id(),
+            hue: self.pairing.hue,
+            brightness: self.brightness_level,
+            mode: self.mode,
+        }
+    }
+
+    /// Find a payload from our group in the manufacturer data of an advertisement
+    fn find_favorite(&self, advertisement: &[u8]) -> Option<FavoritePayload> {
+        advertisement::parse(advertisement)
+            .flatten()
+            .find_map(|structure| match structure {
+                AdStructure::ManufacturerData(data) => self.decode_favorite(data),
+                _ => None,
+            })
+    }
+
+    /// Advertised name, if the advertisement has one
+    fn find_name(advertisement: &[u8]) -> Option<&[u8]> {
+        advertisement::parse(advertisement)
+            .flatten()
+            .find_map(|structure| match structure {
+                AdStructure::LocalName(name) => Some(name),
+                _ => None,
+            })
+    }
+
+    /// As much of `name` as a device keeps
+    fn keep_name(name: &[u8]) -> tinyvec::ArrayVec<[u8; MAX_NAME_LEN]> {
+        name.iter().copied().take(MAX_NAME_LEN).collect()
+    }
+
+    /// Decode manufacturer data, keeping it only if it is from our group
+    fn decode_favorite(&self, manufacturer_data: &[u8]) -> Option<FavoritePayload> {
+        match FavoritePayload::decode(manufacturer_data) {
+            Ok(payload) if payload.group_id == self.pairing.id.group_id() => Some(payload),
+            Ok(_) | Err(PayloadError::NotFavorite) => None,
+            Err(err) => {
+                debug!("Ignoring favorite payload: {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Apply new parameters to all tracked devices, takes effect on the next update or tick
+    pub fn set_config(&mut self, config: Config) {
+        for device in self.devices.iter_mut() {
+            let kind = device.signal_filter_kind(&config);
+            device
+                .signal_strength
+                .reconfigure(kind, config.signal_moving_avg_window);
+        }
+        self.config = config;
+        self.publish();
+    }
+
+    pub fn rules(&self) -> &Rules {
+        &self.rules
+    }
+
+    /// Replace the rules, tracked devices pick up their new rule on their next advertisement
+    pub fn set_rules(&mut self, rules: Rules) {
+        let mut lost_favorites = Vec::new();
+        for device in self.devices.iter_mut() {
+            let was_favorite = device.is_favorite();
+            device.set_rule(None, &self.config);
+            if was_favorite && !device.is_favorite() {
+                lost_favorites.push(device.address);
+            }
+        }
+        for address in lost_favorites {
+            self.emit(DeviceEvent::FavoriteLost { address });
+        }
+        self.rules = rules;
+        self.publish();
+    }
+
+    /// Publish a [`TrackerSnapshot`] after each scan window and settings change from now on,
+    /// replacing any earlier reader
+    pub fn publish_snapshots(&mut self) -> Reader<TrackerSnapshot> {
+        let (publisher, reader) = triple_buffer::triple_buffer(self.snapshot());
+        self.snapshot_publisher = Some(publisher);
+        reader
+    }
+
+    pub fn snapshot(&self) -> TrackerSnapshot {
+        let mut snapshot = TrackerSnapshot::default();
+        self.write_snapshot(&mut snapshot);
+        snapshot
+    }
+
+    /// Overwrite `snapshot`, reusing its allocation
+    fn write_snapshot(&self, snapshot: &mut TrackerSnapshot) {
+        let now = self.clock.now();
+        snapshot.devices.clear();
+        snapshot
+            .devices
+            .extend(self.devices.iter().map(|device| DeviceSnapshot {
+                address: device.address,
+                favorite_color: device.favorite_color(),
+                pinned_color: device.pinned_color(),
+                always_shown: device.is_always_shown(),
+                advertisement: device.advertisement,
+                signal_filter: device.signal_filter,
+                signal_variance: device.signal_strength.variance(),
+                last_heard: device.last_seen.at,
+                trend: device.trend(now, &self.config),
+                presence: device.presence.confidence(now),
+                first_seen: device.first_seen,
+            }));
+        snapshot.devices.sort_by_key(|device| {
+            (
+                core::cmp::Reverse((device.always_shown, device.proximity(now, &self.config))),
+                device.first_seen,
+            )
+        });
+        snapshot.config = self.config;
+        snapshot.followed = self.follow_detector.is_followed();
+        snapshot.spam_blocked = self
+            .spam_detector
+            .under_attack()
+            .then(|| self.spam_detector.blocked_count());
+    }
+
+    fn publish(&mut self) {
+        if let Some(mut publisher) = self.snapshot_publisher.take() {
+            publisher.publish_with(|snapshot| self.write_snapshot(snapshot));
+            self.snapshot_publisher = Some(publisher);
+        }
+    }
+
+    /// Send every change to the tracked devices to `sink` from now on
+    pub fn subscribe(&mut self, sink: Box<dyn DeviceEventSink>) {
+        self.subscribers.push(sink);
+    }
+
+    fn emit(&mut self, event: DeviceEvent) {
+        for subscriber in self.subscribers.iter_mut() {
+            subscriber.send(event);
+        }
+    }
+
+    /// Stop tracking the devices `keep` returns `false` for
+    fn remove_devices(&mut self, mut keep: impl FnMut(&Device) -> bool) {
+        let mut lost = Vec::new();
+        self.devices.retain(|device| {
+            let kept = keep(device);
+            if !kept {
+                lost.push((device.address, device.is_favorite()));
+            }
+            kept
+        });
+        for (address, favorite) in lost {
+            self.emit(DeviceEvent::Lost { address });
+            if favorite {
+                self.emit(DeviceEvent::FavoriteLost { address });
+            }
+        }
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// Item trackers that have stayed with us, see [`Config::follow_duration`]
+    pub fn follow_detector(&self) -> &FollowDetector {
+        &self.follow_detector
+    }
+
+    /// Floods of spoofed advertisements, devices that are part of one aren't tracked
+    pub fn spam_detector(&self) -> &SpamDetector {
+        &self.spam_detector
+    }
+
+    /// `advertisement` is the raw advertising or scan response payload. Nothing is published
+    /// until [`Self::expire`], so scan callbacks stay short.
+    pub fn update(
+        &mut self,
+        addr: BleAddress,
+        addr_type: AddressType,
+        advertisement: &[u8],
+        signal_strength: i32,
+    ) {
+        let now = self.clock.now();
+        let favorite = self.find_favorite(advertisement);
+        let summary = AdvertisementSummary::from_payload(advertisement);
+        // trackers on us are too close to be tracked as devices, so they're checked first
+        if follow_detector::is_item_tracker(&summary) {
+            self.follow_detector.record(
+                addr,
+                addr_type,
+                &summary,
+                signal_strength,
+                now,
+                &self.config,
+            );
+        }
+        // check if device already exists, possibly under an address it has since rotated away from
+        let existing = self
+            .devices
+            .iter()
+            .position(|d| d.current_address() == addr)
+            .or_else(|| {
+                let rotated = self
+                    .devices
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, d)| {
+                        d.last_seen.is_rotation(
+                            &d.advertisement,
+                            &addr,
+                            addr_type,
+                            &summary,
+                            signal_strength,
+                            now,
+                        )
+                    })
+                    .min_by_key(|(_, d)| (d.last_seen.rssi - signal_strength).abs())
+                    .map(|(idx, _)| idx)?;
+                info!(
+                    "{} rotated to {}",
+                    self.devices[rotated].current_address(),
+                    addr
+                );
+                Some(rotated)
+            });
+        // after follow detection, which is too important for a broad rule to switch off
+        let name = Self::find_name(advertisement);
+        let rule = {
+            let tracked = existing.map(|idx| &self.devices[idx]);
+            let mut merged = tracked.map(|d| d.advertisement).unwrap_or_default();
+            merged.merge(&summary);
+            self.rules.evaluate(&ScannedDevice {
+                address: addr,
+                name: name.or_else(|| tracked.map(|d| &d.name[..]).filter(|n| !n.is_empty())),
+                advertisement: &merged,
+                rssi: signal_strength,
+            })
+        };
+        if rule == Some(RuleAction::Ignore) {
+            let ignored = existing.map(|idx| self.devices[idx].address);
+            self.remove_devices(|d| Some(d.address) != ignored);
+            return;
+        }
+        if let Some(device) = existing.map(|idx| &mut self.devices[idx]) {
+            let was_favorite = device.is_favorite();
+            // hue and brightness can change, but a favorite stays one until forgotten
+            if favorite.is_some() {
+                device.favorite = favorite;
+            }
+            if let Some(name) = name {
+                device.name = Self::keep_name(name);
+            }
+            device.set_rule(rule, &self.config);
+            device.advertisement.merge(&summary);
+            device.last_seen.next(addr, addr_type, signal_strength, now);
+            device.signal_strength.push(signal_strength);
+            // interpolate between filtered estimates, so the filter's smoothing isn't bypassed
+            device
+                .signal_filter
+                .update(device.signal_strength.estimate(), now);
+            device.trend.push(signal_strength, now);
+            device.presence.record(signal_strength, now);
+            device.decaying = false;
+
+            let (address, favorite_color) = (device.address, device.favorite_color());
+            self.emit(DeviceEvent::Updated {
+                address,
+                rssi: signal_strength,
+            });
+            if let Some(color) = favorite_color.filter(|_| !was_favorite) {
+                self.emit(DeviceEvent::FavoriteFound { address, color });
+            }
+        } else {
+            // spam is counted whatever its signal strength, favorites and devices a rule
+            // explicitly shows are never spam
+            let shown_by_rule =
+                matches!(rule, Some(RuleAction::AlwaysShow | RuleAction::Favorite(_)));
+            if favorite.is_none() && !shown_by_rule {
+                let under_attack = self.spam_detector.under_attack();
+                let blocked = self.spam_detector.check(addr, &summary, now);
+                if !under_attack && self.spam_detector.under_attack() {
+                    let suspects: Vec<_> = self
+                        .devices
+                        .iter()
+                        .filter(|d| {
+                            !d.is_favorite() && self.spam_detector.is_suspect(&d.current_address())
+                        })
+                        .map(|d| d.address)
+                        .collect();
+                    self.remove_devices(|d| !suspects.contains(&d.address));
+                }
+                if blocked {
+                    return;
+                }
+            }
+
+            // add new device if in range, favorite or shown by a rule
+            if favorite.is_none()
+                && !shown_by_rule
+                && !self.config.signal_allow_range().contains(&signal_strength)
+            {
+                return;
+            }
+
+            let kind = match rule {
+                Some(RuleAction::SignalFilter(kind)) => kind,
+                _ => self.config.signal_filter,
+            };
+            let mut signal_strengths =
+                ConfiguredFilter::new(kind, self.config.signal_moving_avg_window);
+            signal_strengths.push(signal_strength);
+            let mut trend = TrendEstimator::new();
+            trend.push(signal_strength, now);
+
+            let device = Device {
+                address: addr,
+                favorite,
+                advertisement: summary,
+                name: name.map(Self::keep_name).unwrap_or_default(),
+                rule,
+                signal_strength: signal_strengths,
+                signal_filter: AlphaBetaFilter::new(
+                    SIGNAL_FILTER_ALPHA,
+                    SIGNAL_FILTER_BETA,
+                    SIGNAL_MAX_EXTRAPOLATION,
+                    signal_strength as f32,
+                    now,
+                ),
+                trend,
+                presence: PresenceStats::new(signal_strength, now),
+                first_seen: now,
+                last_seen: Sighting::new(addr, addr_type, signal_strength, now),
+                decaying: false,
+            };
+            let favorite_color = device.favorite_color();
+            self.devices.push(device);
+            self.emit(DeviceEvent::Appeared {
+                address: addr,
+                rssi: signal_strength,
+            });
+            if let Some(color) = favorite_color {
+                self.emit(DeviceEvent::FavoriteFound {
+                    address: addr,
+                    color,
+                });
+            }
+        }
+    }
+
+    /// Signal strength decays on read, this only sends decay events and forgets devices that have
+    /// faded out, then publishes what was heard since the last call. Call it after each scan
+    /// window.
+    pub fn expire(&mut self) {
+        let now = self.clock.now();
+        let config = self.config;
+        self.follow_detector.prune(now, &self.config);
+        self.spam_detector.tick(now);
+
+        let mut started_decaying = Vec::new();
+        for device in self.devices.iter_mut() {
+            if !device.decaying && now.duration_since(device.last_seen.at) > config.decay_delay {
+                device.decaying = true;
+                started_decaying.push(device.address);
+            }
+        }
+        for address in started_decaying {
+            self.emit(DeviceEvent::StartedDecaying { address });
+        }
+
+        // retain devices above the ignore below threshold, or shown by a rule until they go quiet
+        self.remove_devices(|device| {
+            device.signal_strength_at(now, &config) > config.signal_ignore_below
+                || (device.is_always_shown() && !device.decaying)
+        });
+        self.publish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::MockClock;
+
+    fn pairing() -> Pairing {
+        Pairing {
+            id: FavoriteId::new("PARTY_TIME").unwrap(),
+            hue: 0,
+        }
+    }
+
+    /// An advertisement carrying only `data`
+    fn manufacturer_data(data: &[u8]) -> Vec<u8> {
+        let mut advertisement = Vec::new();
+        AdStructure::ManufacturerData(data).encode(&mut advertisement);
+        advertisement
+    }
+
+    fn addr(i: u8) -> BleAddress {
+        BleAddress([i, 0x22, 0x33, 0x44, 0x55, 0x66])
+    }
+
+    #[test]
+    fn pairing_round_trip() {
+        let pairing = Pairing {
+            id: FavoriteId::new("0123456789ABCDEF").unwrap(),
+            hue: 359,
+        };
+        let data = pairing.to_bytes();
+        assert_eq!(data.len(), Pairing::MAX_SERIALIZED_LEN);
+        assert_eq!(Pairing::from_bytes(&data), Some(pairing));
+
+        assert_eq!(Pairing::from_bytes(&data[..3]), None);
+        assert_eq!(Pairing::from_bytes(&[0, 0, 0, b'A']), None);
+        assert_eq!(Pairing::from_bytes(&[1, 0x68, 0x01, b'A']), None);
+        assert_eq!(Pairing::from_bytes(&[1, 0, 0, 0xff]), None);
+    }
+
+    #[test]
+    fn update_filters_and_favorites() {
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), MockClock::new());
+
+        tracker.update(addr(0), AddressType::Random, &[], -60);
+        let config = *tracker.config();
+        tracker.update(
+            addr(1),
+            AddressType::Random,
+            &[],
+            config.signal_ignore_above + 1,
+        );
+        tracker.update(
+            addr(2),
+            AddressType::Random,
+            &[],
+            config.signal_ignore_below - 1,
+        );
+        let favorite = FavoritePayload {
+            hue: 120,
+            ..tracker.advertisement()
+        };
+        tracker.update(
+            addr(3),
+            AddressType::Random,
+            &manufacturer_data(&favorite.encode()),
+            -90,
+        );
+
+        assert_eq!(tracker.devices.len(), 2);
+        assert!(!tracker.devices[0].is_favorite());
+        assert!(tracker.devices[1].is_favorite());
+        assert_eq!(
+            tracker.devices[1].favorite_color(),
+            Some(RgbHue::from_degrees(120.0))
+        );
+    }
+
+    #[test]
+    fn malformed_favorites_are_ignored() {
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), MockClock::new());
+        let ours = tracker.advertisement().encode();
+
+        // other groups, truncated and corrupted payloads are treated as ordinary devices
+        let other_group = FavoritePayload {
+            group_id: tracker.advertisement().group_id + 1,
+            ..tracker.advertisement()
+        };
+        let mut bad_hue = ours;
+        bad_hue[9] = 0xff;
+        bad_hue[10] = 0xff;
+        tracker.update(
+            addr(0),
+            AddressType::Random,
+            &manufacturer_data(&other_group.encode()),
+            -90,
+        );
+        tracker.update(
+            addr(1),
+            AddressType::Random,
+            &manufacturer_data(&ours[..5]),
+            -60,
+        );
+        tracker.update(
+            addr(2),
+            AddressType::Random,
+            &manufacturer_data(&bad_hue),
+            -60,
+        );
+        tracker.update(
+            addr(3),
+            AddressType::Random,
+            &manufacturer_data(b"PARTY_TIME"),
+            -60,
+        );
+        assert_eq!(tracker.devices.len(), 3);
+        assert!(!tracker.devices.iter().any(Device::is_favorite));
+
+        // a device can become a favorite, and stays one
+        tracker.update(addr(1), AddressType::Random, &manufacturer_data(&ours), -60);
+        tracker.update(addr(1), AddressType::Random, &[], -60);
+        assert!(tracker.devices[0].is_favorite());
+    }
+
+    #[test]
+    fn advertisement_is_summarized() {
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), MockClock::new());
+
+        // a favorite's payload can share an advertisement with other structures
+        let mut advertisement = alloc::vec![0x02, 0x01, 0x06];
+        AdStructure::TxPower(-4).encode(&mut advertisement);
+        advertisement.extend(tracker.advertisement().to_advertisement());
+        tracker.update(addr(0), AddressType::Random, &advertisement, -60);
+        assert!(tracker.devices[0].is_favorite());
+        assert_eq!(tracker.devices[0].advertisement.company_id, Some(0xffff));
+
+        // the scan response adds to what the advertisement told us
+        tracker.update(
+            addr(0),
+            AddressType::Random,
+            &[0x05, 0x09, b'b', b'r', b'a', b'c'],
+            -60,
+        );
+        let summary = &tracker.devices[0].advertisement;
+        assert_eq!(summary.flags, Some(0x06));
+        assert_eq!(summary.tx_power, Some(-4));
+    }
+
+    #[test]
+    fn favorite_id_validation() {
+        assert_eq!(
+            FavoriteId::new("PARTY_TIME").unwrap().as_str(),
+            "PARTY_TIME"
+        );
+        assert!(FavoriteId::new("").is_none());
+        assert!(FavoriteId::new("A_VERY_LONG_PAIRING_ID").is_none());
+        assert_ne!(
+            FavoriteId::new("PARTY_TIME").unwrap().group_id(),
+            FavoriteId::new("PARTY_TIMF").unwrap().group_id()
+        );
+    }
+
+    #[test]
+    fn silent_device_returns() {
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+        tracker.update(addr(0), AddressType::Random, &[], -60);
+
+        // quiet, but not long enough to decay
+        for _ in 0..tracker.config().decay_delay.as_secs() {
+            clock.advance(Duration::from_secs(1));
+            tracker.expire();
+        }
+        assert!(!tracker.devices[0].decaying);
+        assert_eq!(tracker.devices[0].signal_strength.estimate(), -60.0);
+
+        // silent for 7s
+        for _ in 0..2 {
+            clock.advance(Duration::from_secs(1));
+            tracker.expire();
+        }
+        assert!(tracker.devices[0].decaying);
+        assert!(tracker.devices[0].signal_strength_at(clock.now(), tracker.config()) < -60);
+        // decay is computed, not added to the samples
+        assert_eq!(tracker.devices[0].signal_strength.estimate(), -60.0);
+
+        // hearing from it again stops the decay
+        tracker.update(addr(0), AddressType::Random, &[], -60);
+        clock.advance(Duration::from_secs(1));
+        tracker.expire();
+        assert_eq!(tracker.devices.len(), 1);
+        assert!(!tracker.devices[0].decaying);
+    }
+
+    #[test]
+    fn config_applies_live() {
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+        tracker.update(addr(0), AddressType::Random, &[], -60);
+        tracker.update(addr(0), AddressType::Random, &[], -70);
+        assert_eq!(tracker.devices[0].signal_strength.estimate(), -65.0);
+
+        tracker.set_config(Config {
+            decay_delay: Duration::from_secs(1),
+            signal_ignore_below: -60,
+            signal_moving_avg_window: 1,
+            ..Default::default()
+        });
+        assert_eq!(tracker.devices[0].signal_strength.estimate(), -70.0);
+
+        // new devices must meet the tighter threshold
+        tracker.update(addr(1), AddressType::Random, &[], -65);
+        assert_eq!(tracker.devices.len(), 1);
+
+        // the first decay step drops it below the threshold
+        clock.advance(Duration::from_secs(2));
+        tracker.expire();
+        assert!(tracker.devices.is_empty());
+    }
+
+    #[test]
+    fn silent_device_is_removed() {
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+        tracker.update(addr(0), AddressType::Random, &[], -60);
+
+        for _ in 0..30 {
+            clock.advance(Duration::from_secs(1));
+            tracker.expire();
+        }
+        assert!(tracker.devices.is_empty());
+    }
+
+    #[test]
+    fn rules_apply_on_update() {
+        use crate::rules::{NamePattern, Rule, RuleMatch};
+
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+        let mut rules = Rules::new();
+        for rule in [
+            Rule {
+                matcher: RuleMatch::Address(addr(0)),
+                action: RuleAction::AlwaysShow,
+            },
+            Rule {
+                matcher: RuleMatch::Name(NamePattern::new("Buddy*").unwrap()),
+                action: RuleAction::Favorite(240),
+            },
+            Rule {
+                matcher: RuleMatch::CompanyId(0x004c),
+                action: RuleAction::Ignore,
+            },
+        ] {
+            rules.push(rule).unwrap();
+        }
+        tracker.set_rules(rules);
+
+        // right next to us, and far away
+        tracker.update(addr(0), AddressType::Random, &[], -30);
+        assert!(tracker.devices[0].is_always_shown());
+        for _ in 0..5 {
+            tracker.update(addr(0), AddressType::Random, &[], -95);
+        }
+        clock.advance(Duration::from_secs(1));
+        tracker.expire();
+        assert_eq!(tracker.devices[0].signal_strength.estimate(), -95.0);
+
+        // the name only comes in the scan response, and the match holds between them
+        tracker.update(addr(1), AddressType::Random, &[], -60);
+        assert!(!tracker.devices[1].is_favorite());
+        tracker.update(
+            addr(1),
+            AddressType::Random,
+            &[0x06, 0x09, b'b', b'u', b'd', b'd', b'y'],
+            -60,
+        );
+        tracker.update(addr(1), AddressType::Random, &[], -60);
+        assert_eq!(
+            tracker.devices[1].favorite_color(),
+            Some(RgbHue::from_degrees(240.0))
+        );
+
+        // an ignored device is dropped even if it was already tracked
+        tracker.update(addr(2), AddressType::Random, &[], -60);
+        tracker.update(
+            addr(2),
+            AddressType::Random,
+            crate::advertisement::tests::APPLE_NEARBY_INFO,
+            -60,
+        );
+        assert_eq!(tracker.devices.len(), 2);
+
+        // without the rules, devices go back to normal
+        tracker.set_rules(Rules::new());
+        tracker.update(addr(0), AddressType::Random, &[], -30);
+        assert!(!tracker
+            .devices
+            .iter()
+            .any(|d| d.is_favorite() || d.is_always_shown()));
+        tracker.update(
+            addr(2),
+            AddressType::Random,
+            crate::advertisement::tests::APPLE_NEARBY_INFO,
+            -60,
+        );
+        assert_eq!(tracker.devices.len(), 3);
+    }
+
+    #[test]
+    fn signal_rules_follow_the_signal() {
+        use crate::rules::{Rule, RuleMatch};
+
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), MockClock::new());
+        let mut rules = Rules::new();
+        rules
+            .push(Rule {
+                matcher: RuleMatch::SignalRange { min: -40, max: -20 },
+                action: RuleAction::AlwaysShow,
+            })
+            .unwrap();
+        tracker.set_rules(rules);
+
+        tracker.update(addr(0), AddressType::Random, &[], -30);
+        assert!(tracker.devices[0].is_always_shown());
+        tracker.update(addr(0), AddressType::Random, &[], -95);
+        assert!(!tracker.devices[0].is_always_shown());
+        tracker.update(addr(0), AddressType::Random, &[], -35);
+        assert!(tracker.devices[0].is_always_shown());
+    }
+
+    /// Forwards events over a channel, like the firmware does
+    struct ChannelSink(std::sync::mpsc::Sender<DeviceEvent>);
+
+    impl DeviceEventSink for ChannelSink {
+        fn send(&mut self, event: DeviceEvent) {
+            self.0.send(event).unwrap();
+        }
+    }
+
+    #[test]
+    fn events_follow_device_lifecycle() {
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+        let (logger, log_events) = std::sync::mpsc::channel();
+        let (animator, animator_events) = std::sync::mpsc::channel();
+        tracker.subscribe(Box::new(ChannelSink(logger)));
+        tracker.subscribe(Box::new(ChannelSink(animator)));
+
+        tracker.update(addr(0), AddressType::Random, &[], -60);
+        tracker.update(addr(0), AddressType::Random, &[], -62);
+        // out of range devices aren't tracked, so nothing is sent
+        tracker.update(addr(2), AddressType::Random, &[], -95);
+        let favorite = tracker.advertisement().to_advertisement();
+        tracker.update(addr(1), AddressType::Random, &favorite, -70);
+        assert_eq!(
+            log_events.try_iter().collect::<Vec<_>>(),
+            [
+                DeviceEvent::Appeared {
+                    address: addr(0),
+                    rssi: -60
+                },
+                DeviceEvent::Updated {
+                    address: addr(0),
+                    rssi: -62
+                },
+                DeviceEvent::Appeared {
+                    address: addr(1),
+                    rssi: -70
+                },
+                DeviceEvent::FavoriteFound {
+                    address: addr(1),
+                    color: RgbHue::from_degrees(0.0)
+                },
+            ]
+        );
+
+        // both go quiet, decay and are lost
+        for _ in 0..30 {
+            clock.advance(Duration::from_secs(1));
+            tracker.expire();
+        }
+        assert!(tracker.devices.is_empty());
+        assert_eq!(
+            log_events.try_iter().collect::<Vec<_>>(),
+            [
+                DeviceEvent::StartedDecaying { address: addr(0) },
+                DeviceEvent::StartedDecaying { address: addr(1) },
+                DeviceEvent::Lost { address: addr(1) },
+                DeviceEvent::FavoriteLost { address: addr(1) },
+                DeviceEvent::Lost { address: addr(0) },
+            ]
+        );
+
+        // every subscriber gets every event
+        assert_eq!(animator_events.try_iter().count(), 9);
+    }
+
+    #[test]
+    fn spam_is_not_tracked() {
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+        tracker.update(addr(0), AddressType::Random, &[], -60);
+        clock.advance(Duration::from_secs(10));
+
+        // Fast Pair spam, every popup from a new address
+        for i in 1..=40 {
+            tracker.update(
+                addr(i),
+                AddressType::Random,
+                crate::advertisement::tests::FAST_PAIR,
+                -60,
+            );
+            clock.advance(Duration::from_millis(100));
+        }
+        assert!(tracker.spam_detector().under_attack());
+        assert_eq!(tracker.spam_detector().blocked_count(), 40);
+        // the ones added before the attack was noticed are gone again
+        assert!(tracker.devices.iter().map(|d| d.address).eq([addr(0)]));
+
+        // favorites get through
+        tracker.update(
+            addr(50),
+            AddressType::Random,
+            &tracker.advertisement().to_advertisement(),
+            -60,
+        );
+        assert_eq!(tracker.devices.len(), 2);
+    }
+
+    #[test]
+    fn interpolation_follows_clock() {
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+
+        // a device approaching at 10dB/s, advertising every 200ms
+        for i in 0..10 {
+            tracker.update(addr(0), AddressType::Random, &[], -75 + i * 2);
+            clock.advance(Duration::from_millis(200));
+        }
+
+        // halfway to the next advertisement, the estimate has kept moving
+        let config = *tracker.config();
+        let before = tracker.devices[0].interpolated_signal_strength(clock.now(), &config);
+        clock.advance(Duration::from_millis(500));
+        assert!(tracker.devices[0].interpolated_signal_strength(clock.now(), &config) > before);
+    }
+
+    #[test]
+    fn interpolation_uses_filtered_signal() {
+        let clock = MockClock::new();
+        let config = Config {
+            signal_filter: SignalFilterKind::Median,
+            ..Default::default()
+        };
+        let mut tracker = DeviceTracker::new(pairing(), config, clock.clone());
+        for _ in 0..5 {
+            tracker.update(addr(0), AddressType::Random, &[], -70);
+            clock.advance(Duration::from_millis(200));
+        }
+
+        // the median ignores a one-off spike, so the interpolation shouldn't chase it either
+        tracker.update(addr(0), AddressType::Random, &[], -50);
+        let signal = tracker.devices[0].interpolated_signal_strength(clock.now(), &config);
+        assert_eq!(signal, -70);
+    }
+
+    #[test]
+    fn rules_pick_signal_filters() {
+        use crate::rules::{Rule, RuleMatch};
+
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), MockClock::new());
+        let mut rules = Rules::new();
+        rules
+            .push(Rule {
+                matcher: RuleMatch::Address(addr(1)),
+                action: RuleAction::SignalFilter(SignalFilterKind::Median),
+            })
+            .unwrap();
+        tracker.set_rules(rules);
+        for signal in [-70, -70, -40] {
+            tracker.update(addr(0), AddressType::Random, &[], signal);
+            tracker.update(addr(1), AddressType::Random, &[], signal);
+        }
+        assert!(matches!(
+            tracker.devices[0].signal_strength,
+            ConfiguredFilter::MovingAvg(_)
+        ));
+        assert_eq!(tracker.devices[1].signal_strength.estimate(), -70.0);
+
+        // back to the configured filter once the rule is gone
+        tracker.set_rules(Rules::new());
+        assert!(matches!(
+            tracker.devices[1].signal_strength,
+            ConfiguredFilter::MovingAvg(_)
+        ));
+    }
+
+    #[test]
+    fn snapshots_are_published() {
+        use crate::rules::{Rule, RuleMatch};
+
+        let clock = MockClock::new();
+        let mut tracker = DeviceTracker::new(pairing(), Config::default(), clock.clone());
+        tracker.update(addr(0), AddressType::Random, &[], -70);
+        let mut snapshots = tracker.publish_snapshots();
+        assert_eq!(snapshots.latest().devices.len(), 1);
+
+        tracker.update(addr(1), AddressType::Random, &[], -50);
+        let mut rules = Rules::new();
+        rules
+            .push(Rule {
+                matcher: RuleMatch::Address(addr(2)),
+                action: RuleAction::AlwaysShow,
+            })
+            .unwrap();
+        tracker.set_rules(rules);
+        tracker.update(addr(2), AddressType::Random, &[], -80);
+        tracker.update(
+            addr(3),
+            AddressType::Random,
+            &tracker.advertisement().to_advertisement(),
+            -75,
+        );
+
+        // advertisements are published together at the end of the scan window
+        assert_eq!(snapshots.latest().devices.len(), 2);
+        tracker.expire();
+
+        // ranked, and each device's signal keeps moving after it was taken
+        let snapshot = snapshots.latest().clone();
+        let ranked: Vec<_> = snapshot.devices.iter().map(|d| d.address).collect();
+        assert_eq!(ranked, [addr(2), addr(1), addr(0), addr(3)]);
+        assert!(snapshot.devices[3].favorite_color.is_some());
+        assert!(snapshot.devices[0].always_shown);
+        assert_eq!(
+            snapshot.devices[1].interpolated_signal_strength(clock.now(), &snapshot.config),
+            -50
+        );
+
+        // decay and config changes are published too
+        tracker.set_config(Config {
+            signal_ignore_below: -60,
+            ..Config::default()
+        });
+        assert_eq!(snapshots.latest().config.signal_ignore_below, -60);
+        for _ in 0..30 {
+            clock.advance(Duration::from_secs(1));
+            tracker.expire();
+        }
+        assert!(snapshots.latest().devices.is_empty());
+    }
+}
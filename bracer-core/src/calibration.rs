@@ -0,0 +1,249 @@
+//! Guided calibration of the favorite signal range, for boards whose transmit power or antennas
+//! differ from the defaults. The favorite is sampled from 1 m away, then from 5 m away, and its bar
+//! is then full at the first signal strength and empty at the second.
+
+use core::{fmt, time::Duration};
+
+use alloc::vec::Vec;
+
+use crate::time::Instant;
+
+/// How far apart to stand for each step
+pub const NEAR_METERS: u8 = 1;
+pub const FAR_METERS: u8 = 5;
+
+/// How long each step samples for
+const SAMPLE_DURATION: Duration = Duration::from_secs(10);
+
+/// The favorite is heard every tick, but its signal only changes with each advertisement
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Too few samples and the favorite probably wasn't around
+const MIN_SAMPLES: usize = 20;
+
+/// How much weaker the far signal must be than the near one, any closer and the steps were
+/// probably mixed up or the favorite didn't move
+const MIN_SPREAD: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationState {
+    /// Waiting for the user to get `meters` apart and confirm
+    Positioning { meters: u8 },
+    /// Sampling from `meters` apart
+    Sampling { meters: u8, progress: Progress },
+}
+
+/// Fraction of a step done, in thousandths so the state stays comparable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Progress(u16);
+
+impl Progress {
+    pub fn fraction(&self) -> f32 {
+        self.0 as f32 / 1000.0
+    }
+}
+
+/// Signal strengths to map the favorite bar between
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FavoriteSignalRange {
+    /// Drawn at full brightness, measured at [`NEAR_METERS`]
+    pub max: i32,
+    /// Drawn at minimum brightness, measured at [`FAR_METERS`]
+    pub min: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CalibrationError {
+    /// The favorite was barely heard during a step
+    NoFavorite { meters: u8 },
+    /// The far signal wasn't clearly weaker than the near one
+    NoSpread { near: i32, far: i32 },
+}
+
+impl fmt::Display for CalibrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CalibrationError::NoFavorite { meters } => {
+                write!(f, "favorite not heard from {}m away", meters)
+            }
+            CalibrationError::NoSpread { near, far } => write!(
+                f,
+                "favorite signal at {}m ({}) not clearly weaker than at {}m ({})",
+                FAR_METERS, far, NEAR_METERS, near
+            ),
+        }
+    }
+}
+
+pub struct FavoriteCalibration {
+    /// Median signal strength from the near step, once it is done
+    near: Option<i32>,
+    sampling_since: Option<Instant>,
+    last_sample: Option<Instant>,
+    samples: Vec<i32>,
+}
+
+impl Default for FavoriteCalibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FavoriteCalibration {
+    pub fn new() -> Self {
+        Self {
+            near: None,
+            sampling_since: None,
+            last_sample: None,
+            samples: Vec::new(),
+        }
+    }
+
+    fn meters(&self) -> u8 {
+        match self.near {
+            None => NEAR_METERS,
+            Some(_) => FAR_METERS,
+        }
+    }
+
+    pub fn state(&self, now: Instant) -> CalibrationState {
+        let meters = self.meters();
+        match self.sampling_since {
+            None => CalibrationState::Positioning { meters },
+            Some(since) => {
+                let elapsed = now.duration_since(since).min(SAMPLE_DURATION);
+                let progress = elapsed.as_millis() * 1000 / SAMPLE_DURATION.as_millis();
+                CalibrationState::Sampling {
+                    meters,
+                    progress: Progress(progress as u16),
+                }
+            }
+        }
+    }
+
+    /// The user is in position, start sampling. Confirming again restarts the step.
+    pub fn confirm(&mut self, now: Instant) {
+        self.sampling_since = Some(now);
+        self.last_sample = None;
+        self.samples.clear();
+    }
+
+    /// Feed the favorite's current signal strength, `None` if it isn't being heard. Returns the
+    /// outcome once the last step is done.
+    pub fn record(
+        &mut self,
+        signal: Option<i32>,
+        now: Instant,
+    ) -> Option<Result<FavoriteSignalRange, CalibrationError>> {
+        let since = self.sampling_since?;
+        let due = match self.last_sample {
+            Some(last) => now.duration_since(last) >= SAMPLE_INTERVAL,
+            None => true,
+        };
+        if let Some(signal) = signal.filter(|_| due) {
+            self.samples.push(signal);
+            self.last_sample = Some(now);
+        }
+        if now.duration_since(since) < SAMPLE_DURATION {
+            return None;
+        }
+
+        let meters = self.meters();
+        self.sampling_since = None;
+        if self.samples.len() < MIN_SAMPLES {
+            return Some(Err(CalibrationError::NoFavorite { meters }));
+        }
+        // the median, so someone walking between us for a moment doesn't skew it
+        self.samples.sort_unstable();
+        let median = self.samples[self.samples.len() / 2];
+        let Some(near) = self.near else {
+            self.near = Some(median);
+            return None;
+        };
+        if near - median < MIN_SPREAD {
+            return Some(Err(CalibrationError::NoSpread { near, far: median }));
+        }
+        Some(Ok(FavoriteSignalRange {
+            max: near,
+            min: median,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sample the favorite at `signal` for a whole step
+    fn run_step(
+        calibration: &mut FavoriteCalibration,
+        now: &mut Instant,
+        signal: Option<i32>,
+    ) -> Option<Result<FavoriteSignalRange, CalibrationError>> {
+        calibration.confirm(*now);
+        loop {
+            *now += Duration::from_millis(50);
+            // a spike every second, as from someone walking past
+            let spiked = signal.map(|signal| match now.as_micros() % 1_000_000 {
+                0 => signal + 20,
+                _ => signal,
+            });
+            let outcome = calibration.record(spiked, *now);
+            if outcome.is_some() || calibration.sampling_since.is_none() {
+                return outcome;
+            }
+        }
+    }
+
+    #[test]
+    fn near_then_far() {
+        let mut calibration = FavoriteCalibration::new();
+        let mut now = Instant::default();
+        assert_eq!(
+            calibration.state(now),
+            CalibrationState::Positioning { meters: 1 }
+        );
+
+        calibration.confirm(now);
+        calibration.record(Some(-52), now + Duration::from_secs(5));
+        assert_eq!(
+            calibration.state(now + Duration::from_secs(5)),
+            CalibrationState::Sampling {
+                meters: 1,
+                progress: Progress(500)
+            }
+        );
+
+        assert_eq!(run_step(&mut calibration, &mut now, Some(-52)), None);
+        assert_eq!(
+            calibration.state(now),
+            CalibrationState::Positioning { meters: 5 }
+        );
+        assert_eq!(
+            run_step(&mut calibration, &mut now, Some(-68)),
+            Some(Ok(FavoriteSignalRange { max: -52, min: -68 }))
+        );
+    }
+
+    #[test]
+    fn bad_calibrations() {
+        let mut now = Instant::default();
+
+        let mut calibration = FavoriteCalibration::new();
+        assert_eq!(
+            run_step(&mut calibration, &mut now, None),
+            Some(Err(CalibrationError::NoFavorite { meters: 1 }))
+        );
+        // the step can be retried
+        assert_eq!(run_step(&mut calibration, &mut now, Some(-55)), None);
+
+        // didn't move
+        assert_eq!(
+            run_step(&mut calibration, &mut now, Some(-56)),
+            Some(Err(CalibrationError::NoSpread {
+                near: -55,
+                far: -56
+            }))
+        );
+    }
+}
@@ -0,0 +1,239 @@
+use core::time::Duration;
+
+use alloc::{boxed::Box, vec, vec::Vec};
+
+use log::{info, warn};
+use palette::RgbHue;
+
+use crate::{address::BleAddress, time::Instant};
+
+/// Bump if the serialized layout changes, old data is discarded rather than misread
+const FORMAT_VERSION: u8 = 1;
+
+/// Address bytes followed by the hue in degrees as a little endian f32
+const ENTRY_SIZE: usize = 6 + 4;
+
+/// How often changes are written out at most, every write wears the flash
+const FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Persistent storage for the serialized color store. Persistence is best effort, so backends
+/// handle (and log) their own errors.
+pub trait ColorStoreBackend: Send {
+    /// Read previously saved data into `buf`, returning how many bytes were read
+    fn load(&mut self, buf: &mut [u8]) -> Option<usize>;
+
+    fn save(&mut self, data: &[u8]);
+}
+
+/// Keeps everything in RAM, forgotten on reboot
+#[derive(Debug, Default)]
+pub struct MemoryBackend {
+    pub data: Vec<u8>,
+}
+
+impl ColorStoreBackend for MemoryBackend {
+    fn load(&mut self, buf: &mut [u8]) -> Option<usize> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let len = self.data.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.data[..len]);
+        Some(len)
+    }
+
+    fn save(&mut self, data: &[u8]) {
+        self.data = data.to_vec();
+    }
+}
+
+/// Remembers which color each device was given, so it gets the same one when seen again.
+/// Once full, the least recently seen device is forgotten. Changes are kept in RAM and written
+/// out by [`Self::flush_if_due`].
+pub struct ColorStore {
+    backend: Box<dyn ColorStoreBackend>,
+    capacity: usize,
+
+    /// Most recently used first
+    entries: Vec<(BleAddress, RgbHue)>,
+    /// Entries changed since they were last written out
+    dirty: bool,
+    last_flush: Option<Instant>,
+}
+
+impl ColorStore {
+    pub fn new(mut backend: Box<dyn ColorStoreBackend>, capacity: usize) -> Self {
+        let mut buf = vec![0; 1 + capacity * ENTRY_SIZE];
+        let mut entries = Vec::with_capacity(capacity);
+
+        match backend.load(&mut buf) {
+            Some(len) if len > 0 && buf[0] == FORMAT_VERSION => {
+                for entry in buf[1..len].chunks_exact(ENTRY_SIZE).take(capacity) {
+                    let mut addr = BleAddress::default();
+                    addr.0.copy_from_slice(&entry[..6]);
+                    let degrees = f32::from_le_bytes([entry[6], entry[7], entry[8], entry[9]]);
+                    entries.push((addr, RgbHue::from_degrees(degrees)));
+                }
+                info!("Loaded {} saved device colors", entries.len());
+            }
+            Some(_) => warn!("Discarding saved device colors with unknown format"),
+            None => {}
+        }
+
+        Self {
+            backend,
+            capacity,
+            entries,
+            dirty: false,
+            last_flush: None,
+        }
+    }
+
+    /// Saved color for a device, marks it as recently used
+    pub fn get(&mut self, addr: &BleAddress) -> Option<RgbHue> {
+        let idx = self.entries.iter().position(|(a, _)| a == addr)?;
+        let entry = self.entries.remove(idx);
+        self.entries.insert(0, entry);
+        self.dirty |= idx > 0;
+        Some(entry.1)
+    }
+
+    pub fn insert(&mut self, addr: &BleAddress, color: RgbHue) {
+        self.entries.retain(|(a, _)| a != addr);
+        self.entries.insert(0, (*addr, color));
+        self.entries.truncate(self.capacity);
+        self.dirty = true;
+    }
+
+    /// Write out changes, unless they were last written less than [`FLUSH_INTERVAL`] ago. The
+    /// first change since boot is written right away.
+    pub fn flush_if_due(&mut self, now: Instant) {
+        let due = match self.last_flush {
+            Some(last) => now.duration_since(last) >= FLUSH_INTERVAL,
+            None => true,
+        };
+        if self.dirty && due {
+            self.flush();
+            self.last_flush = Some(now);
+        }
+    }
+
+    /// Write out changes now
+    pub fn flush(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+
+        let mut data = Vec::with_capacity(1 + self.entries.len() * ENTRY_SIZE);
+        data.push(FORMAT_VERSION);
+        for (addr, color) in self.entries.iter() {
+            data.extend_from_slice(&addr.0);
+            data.extend_from_slice(&color.to_degrees().to_le_bytes());
+        }
+        self.backend.save(&data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+
+    /// Shares its data with the test, so a "reboot" can be simulated
+    struct SharedBackend(std::sync::Arc<std::sync::Mutex<MemoryBackend>>);
+
+    impl ColorStoreBackend for SharedBackend {
+        fn load(&mut self, buf: &mut [u8]) -> Option<usize> {
+            self.0.lock().unwrap().load(buf)
+        }
+
+        fn save(&mut self, data: &[u8]) {
+            self.0.lock().unwrap().save(data)
+        }
+    }
+
+    fn addr(i: u8) -> BleAddress {
+        BleAddress([i, 0x22, 0x33, 0x44, 0x55, 0x66])
+    }
+
+    #[test]
+    fn color_store_lru() {
+        let mut store = ColorStore::new(Box::<MemoryBackend>::default(), 2);
+        store.insert(&addr(1), RgbHue::from_degrees(10.0));
+        store.insert(&addr(2), RgbHue::from_degrees(20.0));
+
+        // touching 1 makes 2 the least recently used
+        assert_eq!(store.get(&addr(1)), Some(RgbHue::from_degrees(10.0)));
+        store.insert(&addr(3), RgbHue::from_degrees(30.0));
+
+        assert_eq!(store.get(&addr(2)), None);
+        assert_eq!(store.get(&addr(1)), Some(RgbHue::from_degrees(10.0)));
+        assert_eq!(store.get(&addr(3)), Some(RgbHue::from_degrees(30.0)));
+    }
+
+    #[test]
+    fn color_store_reload() {
+        let data = std::sync::Arc::new(std::sync::Mutex::new(MemoryBackend::default()));
+
+        let mut store = ColorStore::new(Box::new(SharedBackend(data.clone())), 4);
+        store.insert(&addr(1), RgbHue::from_degrees(10.0));
+        store.insert(&addr(2), RgbHue::from_degrees(20.0));
+        store.flush();
+        drop(store);
+
+        let mut store = ColorStore::new(Box::new(SharedBackend(data)), 4);
+        assert_eq!(store.get(&addr(1)), Some(RgbHue::from_degrees(10.0)));
+        assert_eq!(store.get(&addr(2)), Some(RgbHue::from_degrees(20.0)));
+        assert_eq!(store.get(&addr(3)), None);
+    }
+
+    #[test]
+    fn writes_are_batched() {
+        struct CountingBackend(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+        impl ColorStoreBackend for CountingBackend {
+            fn load(&mut self, _buf: &mut [u8]) -> Option<usize> {
+                None
+            }
+
+            fn save(&mut self, _data: &[u8]) {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+
+        let saves = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let saved = || saves.load(std::sync::atomic::Ordering::Relaxed);
+        let mut store = ColorStore::new(Box::new(CountingBackend(saves.clone())), 4);
+        let mut now = Instant::default();
+
+        // nothing to write
+        store.flush_if_due(now);
+        assert_eq!(saved(), 0);
+
+        // the first change goes out right away, then changes wait for the interval
+        store.insert(&addr(1), RgbHue::from_degrees(10.0));
+        store.flush_if_due(now);
+        assert_eq!(saved(), 1);
+        for i in 0..120 {
+            now += Duration::from_millis(100);
+            store.insert(&addr(i % 4), RgbHue::from_degrees(i as f32));
+            store.get(&addr((i + 1) % 4));
+            store.flush_if_due(now);
+        }
+        assert_eq!(saved(), 1);
+        now += FLUSH_INTERVAL;
+        store.flush_if_due(now);
+        assert_eq!(saved(), 2);
+
+        // looking up the most recent device changes nothing
+        store.get(&addr(3));
+        store.get(&addr(3));
+        now += FLUSH_INTERVAL;
+        store.flush_if_due(now);
+        store.get(&addr(3));
+        now += FLUSH_INTERVAL;
+        store.flush_if_due(now);
+        assert_eq!(saved(), 3);
+    }
+}
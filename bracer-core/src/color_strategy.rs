@@ -0,0 +1,339 @@
+//! How new devices are given a color. Saved colors are handled by the caller, strategies only
+//! pick colors for devices seen for the first time.
+
+use alloc::vec::Vec;
+
+use palette::RgbHue;
+use rand::{rngs::SmallRng, seq::SliceRandom};
+
+use crate::{advertisement::AdvertisementSummary, device_category::DeviceCategory};
+
+pub trait ColorStrategy: Send {
+    /// Pick a color for a device seen for the first time
+    fn allocate(&mut self, advertisement: &AdvertisementSummary, rng: &mut SmallRng) -> RgbHue;
+
+    /// A device came back with a color it was given before
+    fn reuse(&mut self, color: RgbHue);
+
+    /// A device that had `color` is no longer shown
+    fn release(&mut self, color: RgbHue);
+
+    /// Whether `color` is still one this strategy would give the device, e.g. not once its
+    /// category is known and the color is from another family
+    fn fits(&self, color: RgbHue, advertisement: &AdvertisementSummary) -> bool;
+}
+
+/// Fixed set of colors, each tracking how many shown devices have it
+struct Palette {
+    colors: Vec<RgbHue>,
+    in_use: Vec<u8>,
+}
+
+impl Palette {
+    fn new(colors: Vec<RgbHue>) -> Self {
+        let in_use = alloc::vec![0; colors.len()];
+        Self { colors, in_use }
+    }
+
+    /// Randomly pick one of the least used colors
+    fn take_least_used(&mut self, rng: &mut SmallRng) -> Option<RgbHue> {
+        let least_used = *self.in_use.iter().min()?;
+        let candidates: Vec<_> = (0..self.colors.len())
+            .filter(|&i| self.in_use[i] == least_used)
+            .collect();
+        let idx = *candidates.choose(rng)?;
+        self.in_use[idx] += 1;
+        Some(self.colors[idx])
+    }
+
+    /// `false` if the color isn't in this palette, e.g. saved by an older palette
+    fn reuse(&mut self, color: RgbHue) -> bool {
+        match self.colors.iter().position(|c| *c == color) {
+            Some(idx) => {
+                self.in_use[idx] += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn release(&mut self, color: RgbHue) -> bool {
+        match self.colors.iter().position(|c| *c == color) {
+            Some(idx) => {
+                self.in_use[idx] = self.in_use[idx].saturating_sub(1);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn contains(&self, color: RgbHue) -> bool {
+        self.colors.contains(&color)
+    }
+}
+
+/// Evenly spaced hues around the color wheel, so nearby devices are easy to tell apart
+pub struct RandomColors {
+    palette: Palette,
+}
+
+impl RandomColors {
+    pub fn new(num_colors: usize) -> Self {
+        let colors = (0..num_colors.max(1))
+            .map(|i| RgbHue::from_degrees(360.0 / num_colors.max(1) as f32 * i as f32))
+            .collect();
+        Self {
+            palette: Palette::new(colors),
+        }
+    }
+}
+
+impl ColorStrategy for RandomColors {
+    fn allocate(&mut self, _advertisement: &AdvertisementSummary, rng: &mut SmallRng) -> RgbHue {
+        self.palette.take_least_used(rng).unwrap_or_default()
+    }
+
+    fn reuse(&mut self, color: RgbHue) {
+        self.palette.reuse(color);
+    }
+
+    fn release(&mut self, color: RgbHue) {
+        self.palette.release(color);
+    }
+
+    fn fits(&self, color: RgbHue, _advertisement: &AdvertisementSummary) -> bool {
+        self.palette.contains(color)
+    }
+}
+
+/// Range of hues used for one category of device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HueFamily {
+    /// Degrees
+    pub center: f32,
+    /// Degrees between the first and last shade
+    pub spread: f32,
+}
+
+impl HueFamily {
+    /// `count` distinct shades, evenly spread across the family
+    fn shades(&self, count: usize) -> impl Iterator<Item = RgbHue> + '_ {
+        let step = if count > 1 {
+            self.spread / (count - 1) as f32
+        } else {
+            0.0
+        };
+        (0..count)
+            .map(move |i| RgbHue::from_degrees(self.center - self.spread / 2.0 + step * i as f32))
+    }
+}
+
+/// Hue families for each [`DeviceCategory`], so the strip shows what kind of devices are around
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CategoryHues {
+    pub phone: HueFamily,
+    pub wearable: HueFamily,
+    pub audio: HueFamily,
+    pub tracker_tag: HueFamily,
+    pub laptop: HueFamily,
+    pub beacon: HueFamily,
+    pub unknown: HueFamily,
+}
+
+impl Default for CategoryHues {
+    fn default() -> Self {
+        let family = |center| HueFamily {
+            center,
+            spread: 24.0,
+        };
+        Self {
+            phone: family(220.0),
+            wearable: family(120.0),
+            audio: family(285.0),
+            tracker_tag: family(0.0),
+            laptop: family(35.0),
+            beacon: family(180.0),
+            unknown: family(70.0),
+        }
+    }
+}
+
+impl CategoryHues {
+    pub fn get(&self, category: DeviceCategory) -> HueFamily {
+        match category {
+            DeviceCategory::Phone => self.phone,
+            DeviceCategory::Wearable => self.wearable,
+            DeviceCategory::Audio => self.audio,
+            DeviceCategory::TrackerTag => self.tracker_tag,
+            DeviceCategory::Laptop => self.laptop,
+            DeviceCategory::Beacon => self.beacon,
+            DeviceCategory::Unknown => self.unknown,
+        }
+    }
+
+    pub fn get_mut(&mut self, category: DeviceCategory) -> &mut HueFamily {
+        match category {
+            DeviceCategory::Phone => &mut self.phone,
+            DeviceCategory::Wearable => &mut self.wearable,
+            DeviceCategory::Audio => &mut self.audio,
+            DeviceCategory::TrackerTag => &mut self.tracker_tag,
+            DeviceCategory::Laptop => &mut self.laptop,
+            DeviceCategory::Beacon => &mut self.beacon,
+            DeviceCategory::Unknown => &mut self.unknown,
+        }
+    }
+}
+
+/// Colors devices by their [`DeviceCategory`], devices in the same category get distinct shades
+pub struct CategoryColors {
+    /// Indexed like [`DeviceCategory::ALL`]
+    palettes: Vec<Palette>,
+}
+
+impl CategoryColors {
+    /// `shades_per_category` distinct colors are used within each family before any repeat
+    pub fn new(hues: CategoryHues, shades_per_category: usize) -> Self {
+        let palettes = DeviceCategory::ALL
+            .iter()
+            .map(|&category| {
+                Palette::new(
+                    hues.get(category)
+                        .shades(shades_per_category.max(1))
+                        .collect(),
+                )
+            })
+            .collect();
+        Self { palettes }
+    }
+
+    fn palette(&self, category: DeviceCategory) -> &Palette {
+        &self.palettes[Self::palette_index(category)]
+    }
+
+    fn palette_mut(&mut self, category: DeviceCategory) -> &mut Palette {
+        &mut self.palettes[Self::palette_index(category)]
+    }
+
+    fn palette_index(category: DeviceCategory) -> usize {
+        DeviceCategory::ALL
+            .iter()
+            .position(|c| *c == category)
+            .unwrap_or_default()
+    }
+}
+
+impl ColorStrategy for CategoryColors {
+    fn allocate(&mut self, advertisement: &AdvertisementSummary, rng: &mut SmallRng) -> RgbHue {
+        let category = DeviceCategory::classify(advertisement);
+        self.palette_mut(category)
+            .take_least_used(rng)
+            .unwrap_or_default()
+    }
+
+    fn reuse(&mut self, color: RgbHue) {
+        // families may overlap, the first one with the color counts it
+        for palette in self.palettes.iter_mut() {
+            if palette.reuse(color) {
+                break;
+            }
+        }
+    }
+
+    fn release(&mut self, color: RgbHue) {
+        for palette in self.palettes.iter_mut() {
+            if palette.release(color) {
+                break;
+            }
+        }
+    }
+
+    fn fits(&self, color: RgbHue, advertisement: &AdvertisementSummary) -> bool {
+        match DeviceCategory::classify(advertisement) {
+            // often only the scan response tells, keep a color saved once the category was known
+            DeviceCategory::Unknown => self.palettes.iter().any(|palette| palette.contains(color)),
+            category => self.palette(category).contains(color),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::advertisement::tests::{FAST_PAIR, IBEACON};
+
+    #[test]
+    fn random_colors_are_spread_out() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut strategy = RandomColors::new(4);
+        let summary = AdvertisementSummary::default();
+
+        let mut colors: Vec<_> = (0..4)
+            .map(|_| strategy.allocate(&summary, &mut rng).to_positive_degrees())
+            .collect();
+        colors.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(colors, [0.0, 90.0, 180.0, 270.0]);
+
+        // once released, a color is the only least used one again
+        strategy.release(RgbHue::from_degrees(90.0));
+        assert_eq!(
+            strategy.allocate(&summary, &mut rng),
+            RgbHue::from_degrees(90.0)
+        );
+    }
+
+    #[test]
+    fn category_colors_use_families() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let hues = CategoryHues::default();
+        let mut strategy = CategoryColors::new(hues, 3);
+        let audio = AdvertisementSummary::from_payload(FAST_PAIR);
+        let beacon = AdvertisementSummary::from_payload(IBEACON);
+
+        let in_family = |color: RgbHue, family: HueFamily| {
+            (color - RgbHue::from_degrees(family.center))
+                .to_degrees()
+                .abs()
+                <= family.spread / 2.0 + 0.01
+        };
+
+        // distinct shades until the family runs out
+        let mut shades = Vec::new();
+        for _ in 0..3 {
+            let color = strategy.allocate(&audio, &mut rng);
+            assert!(in_family(color, hues.audio));
+            assert!(!shades.contains(&color));
+            shades.push(color);
+        }
+        assert!(shades.contains(&strategy.allocate(&audio, &mut rng)));
+
+        assert!(in_family(strategy.allocate(&beacon, &mut rng), hues.beacon));
+        assert!(in_family(
+            strategy.allocate(&AdvertisementSummary::default(), &mut rng),
+            hues.unknown
+        ));
+    }
+
+    #[test]
+    fn colors_fit_once_category_is_known() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut strategy = CategoryColors::new(CategoryHues::default(), 3);
+        let unknown = AdvertisementSummary::default();
+        let audio = AdvertisementSummary::from_payload(FAST_PAIR);
+
+        // heard before its scan response, the color is only a placeholder
+        let placeholder = strategy.allocate(&unknown, &mut rng);
+        assert!(strategy.fits(placeholder, &unknown));
+        assert!(!strategy.fits(placeholder, &audio));
+
+        // a color saved once the category was known still fits before it is known again
+        let color = strategy.allocate(&audio, &mut rng);
+        assert!(strategy.fits(color, &audio));
+        assert!(strategy.fits(color, &unknown));
+
+        // colors from another strategy never fit
+        assert!(!strategy.fits(RgbHue::from_degrees(123.0), &unknown));
+        assert!(!RandomColors::new(4).fits(RgbHue::from_degrees(45.0), &unknown));
+    }
+}
@@ -0,0 +1,772 @@
+use core::{fmt, ops::RangeInclusive, time::Duration};
+
+use crate::{
+    color_strategy::{CategoryHues, HueFamily},
+    device_category::DeviceCategory,
+    distance::DistanceModel,
+};
+
+#[cfg(not(test))]
+use num::Float;
+
+/// Largest signal moving average window the tracker can be configured with
+pub const MAX_SIGNAL_MOVING_AVG_WINDOW: usize = 16;
+
+/// Bump only if stored fields change meaning, older blobs are then rejected and defaults used
+/// instead. New fields go at the end, blobs stored before they were added are still read, and
+/// the fields they don't have take their default.
+const CONFIG_VERSION: u8 = 1;
+/// Size of a config in persistent storage
+pub const SERIALIZED_LEN: usize = 120;
+/// Size of the first stored configs, every config has at least these fields
+const MIN_SERIALIZED_LEN: usize = 22;
+
+/// Longest transition allowed, anything slower looks frozen
+const MAX_TRANSITION: Duration = Duration::from_secs(60);
+
+/// Longest trend window allowed, older samples no longer say where a device is heading
+pub const MAX_TREND_WINDOW: Duration = Duration::from_secs(10);
+
+/// Largest rank margin allowed, in dB, any more and devices would hardly ever trade places
+const MAX_RANK_MARGIN: u8 = 20;
+
+/// Longest rank dwell allowed, any longer and the order lags well behind where devices are
+const MAX_RANK_DWELL: Duration = Duration::from_secs(10);
+
+/// How a quiet device's signal strength fades once [`Config::decay_delay`] has passed, as a
+/// function of how long it has been decaying
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DecayCurve {
+    /// Lose this many dB per second
+    Linear { db_per_sec: f32 },
+    /// Fall towards the weakest signal strength we can store, by `1 - e^-rate` of the way left
+    /// every second, so devices fade at the same rate however strong they were
+    Exponential { rate: f32 },
+    /// Keep the last signal strength for this long, then drop out entirely
+    HoldThenDrop { hold: Duration },
+}
+
+impl DecayCurve {
+    /// Stored as a tag byte followed by the parameter
+    fn tag(&self) -> u8 {
+        match self {
+            DecayCurve::Linear { .. } => 0,
+            DecayCurve::Exponential { .. } => 1,
+            DecayCurve::HoldThenDrop { .. } => 2,
+        }
+    }
+
+    fn param_bytes(&self) -> [u8; 4] {
+        match *self {
+            DecayCurve::Linear { db_per_sec } => db_per_sec.to_le_bytes(),
+            DecayCurve::Exponential { rate } => rate.to_le_bytes(),
+            DecayCurve::HoldThenDrop { hold } => (hold.as_millis() as u32).to_le_bytes(),
+        }
+    }
+
+    fn from_bytes(tag: u8, param: [u8; 4]) -> Result<Self, ConfigError> {
+        match tag {
+            0 => Ok(DecayCurve::Linear {
+                db_per_sec: f32::from_le_bytes(param),
+            }),
+            1 => Ok(DecayCurve::Exponential {
+                rate: f32::from_le_bytes(param),
+            }),
+            2 => Ok(DecayCurve::HoldThenDrop {
+                hold: Duration::from_millis(u32::from_le_bytes(param) as u64),
+            }),
+            _ => Err(ConfigError::DecayCurve),
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let valid = match *self {
+            DecayCurve::Linear { db_per_sec } => db_per_sec > 0.0 && db_per_sec <= 127.0,
+            DecayCurve::Exponential { rate } => rate > 0.0 && rate <= 1.0,
+            DecayCurve::HoldThenDrop { hold } => hold.as_millis() <= u32::MAX as u128,
+        };
+        valid.then_some(()).ok_or(ConfigError::DecayCurve)
+    }
+
+    /// `signal` after decaying for `elapsed`, never weaker than the weakest signal we can store
+    pub fn apply(&self, signal: f32, elapsed: Duration) -> f32 {
+        let secs = elapsed.as_secs_f32();
+        let decayed = match *self {
+            DecayCurve::Linear { db_per_sec } => signal - db_per_sec * secs,
+            DecayCurve::Exponential { rate } => {
+                let floor = *SIGNAL_RANGE.start() as f32;
+                floor + (signal - floor) * (-rate * secs).exp()
+            }
+            DecayCurve::HoldThenDrop { hold } if elapsed < hold => signal,
+            DecayCurve::HoldThenDrop { .. } => f32::NEG_INFINITY,
+        };
+        decayed.max(*SIGNAL_RANGE.start() as f32)
+    }
+}
+
+/// Which [`SignalFilter`](crate::signal_filter::SignalFilter) smooths each device's signal
+/// strength. Windowed filters use [`Config::signal_moving_avg_window`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalFilterKind {
+    MovingAvg,
+    /// Exponential moving average, `alpha` is the weight of each new sample
+    Ema {
+        alpha: f32,
+    },
+    Median,
+    /// Mean after dropping the `trim` highest and lowest samples
+    TrimmedMean {
+        trim: u8,
+    },
+    /// Noise parameters are variances, in dB²
+    Kalman {
+        process_noise: f32,
+        measurement_noise: f32,
+    },
+}
+
+impl SignalFilterKind {
+    /// Stored as a tag byte followed by two parameters
+    pub(crate) fn to_bytes(self) -> [u8; 9] {
+        let (tag, first, second) = match self {
+            SignalFilterKind::MovingAvg => (0, [0; 4], [0; 4]),
+            SignalFilterKind::Ema { alpha } => (1, alpha.to_le_bytes(), [0; 4]),
+            SignalFilterKind::Median => (2, [0; 4], [0; 4]),
+            SignalFilterKind::TrimmedMean { trim } => (3, [trim, 0, 0, 0], [0; 4]),
+            SignalFilterKind::Kalman {
+                process_noise,
+                measurement_noise,
+            } => (
+                4,
+                process_noise.to_le_bytes(),
+                measurement_noise.to_le_bytes(),
+            ),
+        };
+        let mut buf = [0; 9];
+        buf[0] = tag;
+        buf[1..5].copy_from_slice(&first);
+        buf[5..9].copy_from_slice(&second);
+        buf
+    }
+
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self, ConfigError> {
+        let f32_at = |i: usize| f32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        match data[0] {
+            0 => Ok(SignalFilterKind::MovingAvg),
+            1 => Ok(SignalFilterKind::Ema { alpha: f32_at(1) }),
+            2 => Ok(SignalFilterKind::Median),
+            3 => Ok(SignalFilterKind::TrimmedMean { trim: data[1] }),
+            4 => Ok(SignalFilterKind::Kalman {
+                process_noise: f32_at(1),
+                measurement_noise: f32_at(5),
+            }),
+            _ => Err(ConfigError::SignalFilter),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        let positive = |value: f32| value > 0.0 && value.is_finite();
+        let valid = match *self {
+            SignalFilterKind::MovingAvg | SignalFilterKind::Median => true,
+            SignalFilterKind::Ema { alpha } => alpha > 0.0 && alpha <= 1.0,
+            SignalFilterKind::TrimmedMean { trim } => {
+                (trim as usize) < MAX_SIGNAL_MOVING_AVG_WINDOW / 2
+            }
+            SignalFilterKind::Kalman {
+                process_noise,
+                measurement_noise,
+            } => positive(process_noise) && positive(measurement_noise),
+        };
+        valid.then_some(()).ok_or(ConfigError::SignalFilter)
+    }
+}
+
+/// How devices are given colors when first shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    /// Evenly spaced hues, picked at random
+    Random,
+    /// A shade from each [`DeviceCategory`]'s family of hues
+    Category(CategoryHues),
+}
+
+impl ColorMode {
+    /// Stored as a tag byte followed by each category's hue family, zeroed when random
+    fn to_bytes(self) -> [u8; 57] {
+        let mut buf = [0; 57];
+        if let ColorMode::Category(hues) = self {
+            buf[0] = 1;
+            for (i, &category) in DeviceCategory::ALL.iter().enumerate() {
+                let family = hues.get(category);
+                buf[1 + i * 8..5 + i * 8].copy_from_slice(&family.center.to_le_bytes());
+                buf[5 + i * 8..9 + i * 8].copy_from_slice(&family.spread.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    fn from_bytes(data: &[u8]) -> Result<Self, ConfigError> {
+        let f32_at = |i: usize| f32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        match data[0] {
+            0 => Ok(ColorMode::Random),
+            1 => {
+                let mut hues = CategoryHues::default();
+                for (i, &category) in DeviceCategory::ALL.iter().enumerate() {
+                    *hues.get_mut(category) = HueFamily {
+                        center: f32_at(1 + i * 8),
+                        spread: f32_at(5 + i * 8),
+                    };
+                }
+                Ok(ColorMode::Category(hues))
+            }
+            _ => Err(ConfigError::ColorMode),
+        }
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let valid = match self {
+            ColorMode::Random => true,
+            ColorMode::Category(hues) => DeviceCategory::ALL.iter().all(|&category| {
+                let family = hues.get(category);
+                (0.0..360.0).contains(&family.center) && (0.0..=360.0).contains(&family.spread)
+            }),
+        };
+        valid.then_some(()).ok_or(ConfigError::ColorMode)
+    }
+}
+
+/// Tuning parameters for device tracking and display, adjustable at runtime
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    /// How long until device signal strength begins to decay
+    pub decay_delay: Duration,
+
+    /// Once device begins to decay, how its signal strength fades
+    pub decay_curve: DecayCurve,
+
+    /// Cutoff signal strength for devices likely to be physically on us
+    pub signal_ignore_above: i32,
+
+    /// Cutoff signal strength for devices too far to be relevant
+    pub signal_ignore_below: i32,
+
+    /// How signal strength samples are smoothed
+    pub signal_filter: SignalFilterKind,
+
+    /// How many samples the moving average, median and trimmed mean filters look at
+    pub signal_moving_avg_window: usize,
+
+    /// As you move away from the center light, what is the brightness of each subsequent light
+    pub fall_off_rate: f32,
+
+    /// How long a device takes to move between slots
+    pub transition: Duration,
+
+    /// Favorite signal strength drawn at minimum brightness, less tolerant so that as the other
+    /// device hits the noise floor, we don't flicker in and out
+    pub favorite_signal_min: i32,
+
+    /// Favorite signal strength drawn at full brightness, a bit more tolerant since our transmit
+    /// power is low
+    pub favorite_signal_max: i32,
+
+    /// How long an item tracker has to stay with us before it is flagged as following us
+    pub follow_duration: Duration,
+
+    /// Weakest signal an item tracker counts as being with us, any further and it could be
+    /// anyone's
+    pub follow_signal_min: i32,
+
+    /// How long an item tracker can go unheard, or heard weaker than the minimum, and still count
+    /// as having stayed with us
+    pub follow_max_gap: Duration,
+
+    /// Turns signal strength into distance
+    pub distance: DistanceModel,
+
+    /// Rank devices by estimated distance rather than signal strength, so loud devices far away
+    /// don't outrank quiet ones close by
+    pub rank_by_distance: bool,
+
+    /// How far back a device's signal strength is looked at to tell whether it is approaching or
+    /// receding
+    pub trend_window: Duration,
+
+    /// Presence confidence a device needs before it is shown, in `0.0..=1.0`, so devices passing
+    /// by don't take slots from those staying around us
+    pub presence_min: f32,
+
+    /// How many dB a device must be ahead of the one ranked above it to overtake it
+    pub rank_margin: u8,
+
+    /// How long a device must stay ahead by the margin before it overtakes
+    pub rank_dwell: Duration,
+
+    /// How new devices are colored
+    pub color_mode: ColorMode,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            decay_delay: Duration::from_secs(5),
+            decay_curve: DecayCurve::Linear { db_per_sec: 6.0 },
+            signal_ignore_above: -45,
+            signal_ignore_below: -80,
+            signal_filter: SignalFilterKind::MovingAvg,
+            signal_moving_avg_window: 5,
+            fall_off_rate: 0.5,
+            transition: Duration::from_secs(3),
+            favorite_signal_min: -70,
+            favorite_signal_max: -55,
+            follow_duration: Duration::from_secs(15 * 60),
+            follow_signal_min: -75,
+            follow_max_gap: Duration::from_secs(120),
+            distance: DistanceModel::default(),
+            rank_by_distance: false,
+            trend_window: Duration::from_secs(3),
+            presence_min: 0.3,
+            rank_margin: 3,
+            rank_dwell: Duration::from_secs(1),
+            color_mode: ColorMode::Random,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// Serialized config has the wrong length or version
+    Malformed,
+    DecayDelay,
+    DecayCurve,
+    SignalThresholds,
+    MovingAvgWindow,
+    SignalFilter,
+    FallOffRate,
+    Transition,
+    FavoriteSignalRange,
+    FollowWindow,
+    DistanceModel,
+    TrendWindow,
+    PresenceMin,
+    RankStability,
+    ColorMode,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            ConfigError::Malformed => "malformed config",
+            ConfigError::DecayDelay => "decay delay too long",
+            ConfigError::DecayCurve => "unknown decay curve or parameter out of range",
+            ConfigError::SignalThresholds => {
+                "signal thresholds must be in -127..=0 with ignore below < ignore above"
+            }
+            ConfigError::MovingAvgWindow => "moving average window out of range",
+            ConfigError::SignalFilter => "unknown signal filter or parameter out of range",
+            ConfigError::FallOffRate => "fall off rate must be in (0, 1]",
+            ConfigError::Transition => "transition must be between 1ms and 60s",
+            ConfigError::FavoriteSignalRange => {
+                "favorite signal range must be in -127..=0 with min < max"
+            }
+            ConfigError::FollowWindow => {
+                "follow duration and gap must be at least 1s and follow signal min in -127..=0"
+            }
+            ConfigError::DistanceModel => {
+                "path loss exponent must be in 1..=6 and distance calibration within 30dB"
+            }
+            ConfigError::TrendWindow => "trend window must be between 1s and 10s",
+            ConfigError::PresenceMin => "presence threshold must be in 0..=1",
+            ConfigError::RankStability => "rank margin must be at most 20dB and dwell at most 10s",
+            ConfigError::ColorMode => {
+                "unknown color mode, or a hue family centered outside 0..360 or wider than 360"
+            }
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Signal strengths are stored as a single signed byte
+const SIGNAL_RANGE: RangeInclusive<i32> = -127..=0;
+
+impl Config {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.decay_delay.as_millis() > u32::MAX as u128 {
+            return Err(ConfigError::DecayDelay);
+        }
+        self.decay_curve.validate()?;
+        if !SIGNAL_RANGE.contains(&self.signal_ignore_above)
+            || !SIGNAL_RANGE.contains(&self.signal_ignore_below)
+            || self.signal_ignore_below >= self.signal_ignore_above
+        {
+            return Err(ConfigError::SignalThresholds);
+        }
+        if !(1..=MAX_SIGNAL_MOVING_AVG_WINDOW).contains(&self.signal_moving_avg_window) {
+            return Err(ConfigError::MovingAvgWindow);
+        }
+        self.signal_filter.validate()?;
+        if !(self.fall_off_rate > 0.0 && self.fall_off_rate <= 1.0) {
+            return Err(ConfigError::FallOffRate);
+        }
+        if self.transition < Duration::from_millis(1) || self.transition > MAX_TRANSITION {
+            return Err(ConfigError::Transition);
+        }
+        if !SIGNAL_RANGE.contains(&self.favorite_signal_min)
+            || !SIGNAL_RANGE.contains(&self.favorite_signal_max)
+            || self.favorite_signal_min >= self.favorite_signal_max
+        {
+            return Err(ConfigError::FavoriteSignalRange);
+        }
+        if self.follow_duration < Duration::from_secs(1)
+            || self.follow_duration.as_millis() > u32::MAX as u128
+            || !SIGNAL_RANGE.contains(&self.follow_signal_min)
+            || self.follow_max_gap < Duration::from_secs(1)
+            || self.follow_max_gap.as_millis() > u32::MAX as u128
+        {
+            return Err(ConfigError::FollowWindow);
+        }
+        if !(1.0..=6.0).contains(&self.distance.path_loss_exponent)
+            || !(-30.0..=30.0).contains(&self.distance.calibration)
+        {
+            return Err(ConfigError::DistanceModel);
+        }
+        if self.trend_window < Duration::from_secs(1) || self.trend_window > MAX_TREND_WINDOW {
+            return Err(ConfigError::TrendWindow);
+        }
+        if !(0.0..=1.0).contains(&self.presence_min) {
+            return Err(ConfigError::PresenceMin);
+        }
+        if self.rank_margin > MAX_RANK_MARGIN || self.rank_dwell > MAX_RANK_DWELL {
+            return Err(ConfigError::RankStability);
+        }
+        self.color_mode.validate()?;
+        Ok(())
+    }
+
+    /// Signal strengths a new device must be within to be tracked
+    pub fn signal_allow_range(&self) -> RangeInclusive<i32> {
+        self.signal_ignore_below..=self.signal_ignore_above
+    }
+
+    /// `signal` of a device that has been quiet for `quiet`, decayed once the delay has passed
+    pub fn decayed_signal(&self, signal: f32, quiet: Duration) -> f32 {
+        match quiet.checked_sub(self.decay_delay) {
+            Some(decaying) if !decaying.is_zero() => self.decay_curve.apply(signal, decaying),
+            _ => signal,
+        }
+    }
+
+    /// Serialize for persistent storage, only valid configs should be stored
+    pub fn to_bytes(&self) -> [u8; SERIALIZED_LEN] {
+        let mut buf = [0; SERIALIZED_LEN];
+        buf[0] = CONFIG_VERSION;
+        buf[1..5].copy_from_slice(&(self.decay_delay.as_millis() as u32).to_le_bytes());
+        buf[5..9].copy_from_slice(&self.decay_curve.param_bytes());
+        buf[9] = self.signal_ignore_above as i8 as u8;
+        buf[10] = self.signal_ignore_below as i8 as u8;
+        buf[11] = self.signal_moving_avg_window as u8;
+        buf[12..16].copy_from_slice(&self.fall_off_rate.to_le_bytes());
+        buf[16..20].copy_from_slice(&(self.transition.as_millis() as u32).to_le_bytes());
+        buf[20] = self.favorite_signal_min as i8 as u8;
+        buf[21] = self.favorite_signal_max as i8 as u8;
+        buf[22..26].copy_from_slice(&(self.follow_duration.as_millis() as u32).to_le_bytes());
+        buf[26] = self.follow_signal_min as i8 as u8;
+        buf[27] = self.decay_curve.tag();
+        buf[28..37].copy_from_slice(&self.signal_filter.to_bytes());
+        buf[37..41].copy_from_slice(&self.distance.path_loss_exponent.to_le_bytes());
+        buf[41..45].copy_from_slice(&self.distance.calibration.to_le_bytes());
+        buf[45] = self.rank_by_distance as u8;
+        buf[46..50].copy_from_slice(&(self.trend_window.as_millis() as u32).to_le_bytes());
+        buf[50..54].copy_from_slice(&self.presence_min.to_le_bytes());
+        buf[54] = self.rank_margin;
+        buf[55..59].copy_from_slice(&(self.rank_dwell.as_millis() as u32).to_le_bytes());
+        buf[59..116].copy_from_slice(&self.color_mode.to_bytes());
+        buf[116..120].copy_from_slice(&(self.follow_max_gap.as_millis() as u32).to_le_bytes());
+        buf
+    }
+
+    /// Parse and validate a stored config, fields added after it was stored take their default
+    pub fn from_bytes(data: &[u8]) -> Result<Self, ConfigError> {
+        if data.len() < MIN_SERIALIZED_LEN || data[0] != CONFIG_VERSION {
+            return Err(ConfigError::Malformed);
+        }
+        let defaults = Self::default();
+        let u8_at = |i: usize| data.get(i).copied();
+        let u32_at = |i: usize| {
+            data.get(i..i + 4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let f32_at = |i: usize| u32_at(i).map(f32::from_bits);
+        let millis_at = |i: usize| u32_at(i).map(|millis| Duration::from_millis(millis as u64));
+        let signal_at = |i: usize| u8_at(i).map(|signal| signal as i8 as i32);
+
+        // the curve's parameter was stored before its tag, on its own it can't be read
+        let decay_curve = match u8_at(27) {
+            Some(tag) => DecayCurve::from_bytes(tag, data[5..9].try_into().unwrap())?,
+            None => defaults.decay_curve,
+        };
+        let signal_filter = match data.get(28..37) {
+            Some(bytes) => SignalFilterKind::from_bytes(bytes)?,
+            None => defaults.signal_filter,
+        };
+        let distance = match (f32_at(37), f32_at(41)) {
+            (Some(path_loss_exponent), Some(calibration)) => DistanceModel {
+                path_loss_exponent,
+                calibration,
+            },
+            _ => defaults.distance,
+        };
+        let color_mode = match data.get(59..116) {
+            Some(bytes) => ColorMode::from_bytes(bytes)?,
+            None => defaults.color_mode,
+        };
+        let config = Self {
+            decay_delay: millis_at(1).unwrap_or(defaults.decay_delay),
+            decay_curve,
+            signal_ignore_above: signal_at(9).unwrap_or(defaults.signal_ignore_above),
+            signal_ignore_below: signal_at(10).unwrap_or(defaults.signal_ignore_below),
+            signal_filter,
+            signal_moving_avg_window: u8_at(11)
+                .map_or(defaults.signal_moving_avg_window, |window| window as usize),
+            fall_off_rate: f32_at(12).unwrap_or(defaults.fall_off_rate),
+            transition: millis_at(16).unwrap_or(defaults.transition),
+            favorite_signal_min: signal_at(20).unwrap_or(defaults.favorite_signal_min),
+            favorite_signal_max: signal_at(21).unwrap_or(defaults.favorite_signal_max),
+            follow_duration: millis_at(22).unwrap_or(defaults.follow_duration),
+            follow_signal_min: signal_at(26).unwrap_or(defaults.follow_signal_min),
+            follow_max_gap: millis_at(116).unwrap_or(defaults.follow_max_gap),
+            distance,
+            rank_by_distance: u8_at(45).map_or(defaults.rank_by_distance, |value| value != 0),
+            trend_window: millis_at(46).unwrap_or(defaults.trend_window),
+            presence_min: f32_at(50).unwrap_or(defaults.presence_min),
+            rank_margin: u8_at(54).unwrap_or(defaults.rank_margin),
+            rank_dwell: millis_at(55).unwrap_or(defaults.rank_dwell),
+            color_mode,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = Config {
+            decay_delay: Duration::from_millis(2500),
+            signal_ignore_above: -50,
+            signal_moving_avg_window: 8,
+            transition: Duration::from_millis(750),
+            follow_duration: Duration::from_secs(90),
+            follow_signal_min: -70,
+            follow_max_gap: Duration::from_secs(45),
+            distance: DistanceModel {
+                path_loss_exponent: 3.0,
+                calibration: -4.5,
+            },
+            rank_by_distance: true,
+            trend_window: Duration::from_millis(4500),
+            presence_min: 0.5,
+            rank_margin: 5,
+            rank_dwell: Duration::from_millis(2500),
+            color_mode: ColorMode::Category(CategoryHues {
+                phone: HueFamily {
+                    center: 300.0,
+                    spread: 40.0,
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(Config::from_bytes(&config.to_bytes()), Ok(config));
+
+        for decay_curve in [
+            DecayCurve::Linear { db_per_sec: 2.5 },
+            DecayCurve::Exponential { rate: 0.25 },
+            DecayCurve::HoldThenDrop {
+                hold: Duration::from_secs(20),
+            },
+        ] {
+            let config = Config {
+                decay_curve,
+                ..Default::default()
+            };
+            assert_eq!(Config::from_bytes(&config.to_bytes()), Ok(config));
+        }
+
+        for signal_filter in [
+            SignalFilterKind::Ema { alpha: 0.25 },
+            SignalFilterKind::Median,
+            SignalFilterKind::TrimmedMean { trim: 2 },
+            SignalFilterKind::Kalman {
+                process_noise: 0.5,
+                measurement_noise: 12.0,
+            },
+        ] {
+            let config = Config {
+                signal_filter,
+                ..Default::default()
+            };
+            assert_eq!(Config::from_bytes(&config.to_bytes()), Ok(config));
+        }
+    }
+
+    #[test]
+    fn older_configs_fill_in_defaults() {
+        let config = Config {
+            decay_delay: Duration::from_millis(2500),
+            signal_ignore_above: -50,
+            favorite_signal_max: -50,
+            follow_duration: Duration::from_secs(90),
+            rank_by_distance: true,
+            trend_window: Duration::from_millis(4500),
+            ..Default::default()
+        };
+        let bytes = config.to_bytes();
+
+        // the first stored configs, before item tracker following
+        let first = Config::from_bytes(&bytes[..MIN_SERIALIZED_LEN]).unwrap();
+        assert_eq!(
+            first,
+            Config {
+                decay_delay: config.decay_delay,
+                signal_ignore_above: -50,
+                favorite_signal_max: -50,
+                ..Default::default()
+            }
+        );
+
+        // before trend windows
+        let before_trends = Config::from_bytes(&bytes[..46]).unwrap();
+        assert_eq!(
+            before_trends,
+            Config {
+                trend_window: Config::default().trend_window,
+                ..config
+            }
+        );
+
+        // written by a newer build, the fields it added are ignored
+        let mut newer = bytes.to_vec();
+        newer.extend_from_slice(&[0xAB; 8]);
+        assert_eq!(Config::from_bytes(&newer), Ok(config));
+    }
+
+    #[test]
+    fn linear_decay() {
+        let curve = DecayCurve::Linear { db_per_sec: 2.0 };
+        assert_eq!(curve.apply(-60.0, Duration::ZERO), -60.0);
+        assert_eq!(curve.apply(-60.0, Duration::from_millis(2500)), -65.0);
+        assert_eq!(curve.apply(-60.0, Duration::from_secs(10)), -80.0);
+        assert_eq!(curve.apply(-60.0, Duration::from_secs(100)), -127.0);
+    }
+
+    #[test]
+    fn exponential_decay() {
+        let curve = DecayCurve::Exponential { rate: 0.1 };
+        assert_eq!(curve.apply(-60.0, Duration::ZERO), -60.0);
+        assert!((curve.apply(-60.0, Duration::from_secs(1)) - -66.38).abs() < 0.01);
+        assert!((curve.apply(-60.0, Duration::from_secs(2)) - -72.14).abs() < 0.01);
+        // strong and weak devices lose the same share of the way to the floor
+        let left =
+            |signal: f32| (curve.apply(signal, Duration::from_secs(3)) + 127.0) / (signal + 127.0);
+        assert!((left(-40.0) - left(-80.0)).abs() < 1e-4);
+        assert!(curve.apply(-60.0, Duration::from_secs(100)) < -126.0);
+    }
+
+    #[test]
+    fn hold_then_drop_decay() {
+        let curve = DecayCurve::HoldThenDrop {
+            hold: Duration::from_secs(5),
+        };
+        assert_eq!(curve.apply(-60.0, Duration::from_millis(4999)), -60.0);
+        assert_eq!(curve.apply(-60.0, Duration::from_secs(5)), -127.0);
+    }
+
+    #[test]
+    fn decay_waits_for_delay() {
+        let config = Config {
+            decay_delay: Duration::from_secs(5),
+            decay_curve: DecayCurve::Linear { db_per_sec: 1.0 },
+            ..Default::default()
+        };
+        assert_eq!(config.decayed_signal(-60.0, Duration::from_secs(5)), -60.0);
+        assert_eq!(config.decayed_signal(-60.0, Duration::from_secs(8)), -63.0);
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        let inverted = Config {
+            signal_ignore_above: -90,
+            ..Default::default()
+        };
+        assert_eq!(inverted.validate(), Err(ConfigError::SignalThresholds));
+
+        let nan = Config {
+            fall_off_rate: f32::NAN,
+            ..Default::default()
+        };
+        assert_eq!(nan.validate(), Err(ConfigError::FallOffRate));
+
+        let instant_follow = Config {
+            follow_duration: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(instant_follow.validate(), Err(ConfigError::FollowWindow));
+
+        let no_gap = Config {
+            follow_max_gap: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(no_gap.validate(), Err(ConfigError::FollowWindow));
+
+        let growing = Config {
+            decay_curve: DecayCurve::Linear { db_per_sec: -1.0 },
+            ..Default::default()
+        };
+        assert_eq!(growing.validate(), Err(ConfigError::DecayCurve));
+
+        let untrusting = Config {
+            signal_filter: SignalFilterKind::Ema { alpha: 0.0 },
+            ..Default::default()
+        };
+        assert_eq!(untrusting.validate(), Err(ConfigError::SignalFilter));
+
+        let off_the_wheel = Config {
+            color_mode: ColorMode::Category(CategoryHues {
+                audio: HueFamily {
+                    center: 400.0,
+                    spread: 24.0,
+                },
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(off_the_wheel.validate(), Err(ConfigError::ColorMode));
+
+        let mut bytes = Config::default().to_bytes();
+        bytes[11] = 0;
+        assert_eq!(
+            Config::from_bytes(&bytes),
+            Err(ConfigError::MovingAvgWindow)
+        );
+        assert_eq!(
+            Config::from_bytes(&bytes[..MIN_SERIALIZED_LEN - 1]),
+            Err(ConfigError::Malformed)
+        );
+
+        let mut bytes = Config::default().to_bytes();
+        bytes[27] = 3;
+        assert_eq!(Config::from_bytes(&bytes), Err(ConfigError::DecayCurve));
+        bytes[27] = 0;
+        bytes[59] = 2;
+        assert_eq!(Config::from_bytes(&bytes), Err(ConfigError::ColorMode));
+    }
+}
@@ -0,0 +1,306 @@
+//! Wire format of the GATT configuration service. The firmware only registers these characteristics
+//! with NimBLE and forwards decoded writes through the [`LightControls`] channel.
+//!
+//! | Characteristic    | Value                                                      |
+//! |-------------------|------------------------------------------------------------|
+//! | Brightness        | `u8` level in `1..=BRIGHTNESS_LEVELS`                      |
+//! | Display mode      | `u8`, 0 = sticky, 1 = ordered                              |
+//! | Signal thresholds | `i8` ignore below, `i8` ignore above, dBm with below < above |
+//! | Favorite ID       | UTF-8, 1 to [`FavoriteId::MAX_LEN`] bytes                  |
+//! | Favorite hue      | `u16` little endian, degrees in `0..360`                   |
+//! | Rules             | `u8` command followed by its argument, see below           |
+//! | Config            | Stored config layout, see [`Config::to_bytes`]              |
+//!
+//! Rule commands are 0 to add a rule (encoded as in [`crate::rules`]) after the existing ones, 1 to
+//! remove the rule at a `u8` index, and 2 to remove all rules. Reading the rules gives the list as
+//! stored, see [`Rules::to_bytes`](crate::rules::Rules::to_bytes), so indexes can be checked
+//! before removing.
+//!
+//! The config covers every tuning parameter, including the signal thresholds. A config written
+//! without the fields added last gives them their defaults, as when reading an older stored one.
+
+use core::fmt;
+
+use alloc::vec::Vec;
+use const_format::assertcp;
+
+use crate::{
+    ble_device_mgr::FavoriteId,
+    config::{Config, ConfigError},
+    light_mgr::BRIGHTNESS_LEVELS,
+    messages::{DisplaySortMode, LightControls},
+    rules::{self, Rule, RuleError},
+};
+
+pub const SERVICE_UUID: u128 = 0xb7ac_0001_5b0a_4e3e_9c1d_7f2e_8a6d_4c10;
+
+/// Longest value a GATT attribute can have
+const MAX_ATTRIBUTE_LEN: usize = 512;
+assertcp!(rules::MAX_SERIALIZED_LEN <= MAX_ATTRIBUTE_LEN);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigCharacteristic {
+    Brightness,
+    DisplayMode,
+    SignalThresholds,
+    FavoriteId,
+    FavoriteHue,
+    Rules,
+    Config,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteError {
+    WrongLength,
+    OutOfRange,
+    InvalidFavoriteId,
+    InvalidRule(RuleError),
+    InvalidConfig(ConfigError),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::WrongLength => f.write_str("wrong length"),
+            WriteError::OutOfRange => f.write_str("value out of range"),
+            WriteError::InvalidFavoriteId => f.write_str("invalid favorite ID"),
+            WriteError::InvalidRule(err) => write!(f, "invalid rule: {}", err),
+            WriteError::InvalidConfig(err) => write!(f, "invalid config: {}", err),
+        }
+    }
+}
+
+impl ConfigCharacteristic {
+    pub const ALL: [Self; 7] = [
+        Self::Brightness,
+        Self::DisplayMode,
+        Self::SignalThresholds,
+        Self::FavoriteId,
+        Self::FavoriteHue,
+        Self::Rules,
+        Self::Config,
+    ];
+
+    pub fn uuid(self) -> u128 {
+        let id = match self {
+            Self::Brightness => 2,
+            Self::DisplayMode => 3,
+            Self::SignalThresholds => 4,
+            Self::FavoriteId => 5,
+            Self::FavoriteHue => 6,
+            Self::Rules => 7,
+            Self::Config => 8,
+        };
+        // characteristics share the service UUID apart from the low half of the first group
+        SERVICE_UUID & !(0xffff_u128 << 96) | (id << 96)
+    }
+
+    /// Parse a write from a client into the matching control message, rejecting invalid values
+    pub fn decode_write(self, data: &[u8]) -> Result<LightControls, WriteError> {
+        match self {
+            Self::Brightness => {
+                let [level] = *data else {
+                    return Err(WriteError::WrongLength);
+                };
+                if !(1..=BRIGHTNESS_LEVELS).contains(&level) {
+                    return Err(WriteError::OutOfRange);
+                }
+                Ok(LightControls::BrightnessChange(level))
+            }
+            Self::DisplayMode => match *data {
+                [0] => Ok(LightControls::ModeChange(DisplaySortMode::Sticky)),
+                [1] => Ok(LightControls::ModeChange(DisplaySortMode::Ordered)),
+                [_] => Err(WriteError::OutOfRange),
+                _ => Err(WriteError::WrongLength),
+            },
+            Self::SignalThresholds => {
+                let [ignore_below, ignore_above] = *data else {
+                    return Err(WriteError::WrongLength);
+                };
+                let (ignore_below, ignore_above) = (ignore_below as i8, ignore_above as i8);
+                if ignore_below == i8::MIN || ignore_above > 0 || ignore_below >= ignore_above {
+                    return Err(WriteError::OutOfRange);
+                }
+                Ok(LightControls::SignalThresholdsChange {
+                    ignore_below,
+                    ignore_above,
+                })
+            }
+            Self::FavoriteId => core::str::from_utf8(data)
+                .ok()
+                .and_then(FavoriteId::new)
+                .map(LightControls::FavoriteIdChange)
+                .ok_or(WriteError::InvalidFavoriteId),
+            Self::FavoriteHue => {
+                let [low, high] = *data else {
+                    return Err(WriteError::WrongLength);
+                };
+                let hue = u16::from_le_bytes([low, high]);
+                if hue >= 360 {
+                    return Err(WriteError::OutOfRange);
+                }
+                Ok(LightControls::FavoriteHueChange(hue))
+            }
+            Self::Rules => match *data {
+                [0, ref rule @ ..] => Rule::decode(rule)
+                    .map(LightControls::RuleAdd)
+                    .map_err(WriteError::InvalidRule),
+                [1, idx] => Ok(LightControls::RuleRemove(idx)),
+                [2] => Ok(LightControls::RulesClear),
+                [] | [0..=2, ..] => Err(WriteError::WrongLength),
+                _ => Err(WriteError::OutOfRange),
+            },
+            Self::Config => Config::from_bytes(data)
+                .map(LightControls::ConfigChange)
+                .map_err(WriteError::InvalidConfig),
+        }
+    }
+
+    /// Characteristic value reflecting `control`, `None` for relative changes like
+    /// [`LightControls::BrightnessIncrease`], rule edits and calibration that have no value of their
+    /// own
+    pub fn encode(control: &LightControls) -> Option<(Self, Vec<u8>)> {
+        let encoded = match *control {
+            LightControls::BrightnessIncrease
+            | LightControls::BrightnessDecrease
+            | LightControls::RuleAdd(_)
+            | LightControls::RuleRemove(_)
+            | LightControls::RulesClear
+            | LightControls::CalibrationStart => return None,
+            LightControls::BrightnessChange(level) => (Self::Brightness, alloc::vec![level]),
+            LightControls::ModeChange(mode) => {
+                let mode = match mode {
+                    DisplaySortMode::Sticky => 0,
+                    DisplaySortMode::Ordered => 1,
+                };
+                (Self::DisplayMode, alloc::vec![mode])
+            }
+            LightControls::SignalThresholdsChange {
+                ignore_below,
+                ignore_above,
+            } => (
+                Self::SignalThresholds,
+                alloc::vec![ignore_below as u8, ignore_above as u8],
+            ),
+            LightControls::ConfigChange(config) => (Self::Config, config.to_bytes().to_vec()),
+            LightControls::FavoriteIdChange(id) => (Self::FavoriteId, id.as_str().into()),
+            LightControls::FavoriteHueChange(hue) => {
+                (Self::FavoriteHue, hue.to_le_bytes().to_vec())
+            }
+        };
+        Some(encoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let controls = [
+            LightControls::BrightnessChange(BRIGHTNESS_LEVELS),
+            LightControls::ModeChange(DisplaySortMode::Sticky),
+            LightControls::SignalThresholdsChange {
+                ignore_below: -85,
+                ignore_above: -40,
+            },
+            LightControls::FavoriteIdChange(FavoriteId::new("PARTY_TIME").unwrap()),
+            LightControls::FavoriteHueChange(359),
+            LightControls::ConfigChange(Config {
+                rank_by_distance: true,
+                presence_min: 0.5,
+                ..Config::default()
+            }),
+        ];
+        for control in controls {
+            let (characteristic, data) = ConfigCharacteristic::encode(&control).unwrap();
+            assert_eq!(characteristic.decode_write(&data), Ok(control));
+        }
+        assert_eq!(
+            ConfigCharacteristic::encode(&LightControls::BrightnessIncrease),
+            None
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_writes() {
+        use ConfigCharacteristic::*;
+
+        assert_eq!(Brightness.decode_write(&[]), Err(WriteError::WrongLength));
+        assert_eq!(Brightness.decode_write(&[0]), Err(WriteError::OutOfRange));
+        assert_eq!(DisplayMode.decode_write(&[2]), Err(WriteError::OutOfRange));
+        assert_eq!(
+            SignalThresholds.decode_write(&[(-40_i8) as u8, (-85_i8) as u8]),
+            Err(WriteError::OutOfRange)
+        );
+        assert_eq!(
+            SignalThresholds.decode_write(&[(-85_i8) as u8, 10]),
+            Err(WriteError::OutOfRange)
+        );
+        assert_eq!(
+            FavoriteId.decode_write(b""),
+            Err(WriteError::InvalidFavoriteId)
+        );
+        assert_eq!(
+            FavoriteId.decode_write(&[0xff, 0xfe]),
+            Err(WriteError::InvalidFavoriteId)
+        );
+        assert_eq!(
+            FavoriteHue.decode_write(&360_u16.to_le_bytes()),
+            Err(WriteError::OutOfRange)
+        );
+        assert_eq!(
+            Config.decode_write(&[]),
+            Err(WriteError::InvalidConfig(ConfigError::Malformed))
+        );
+        let mut config = crate::config::Config::default().to_bytes();
+        config[54] = 255;
+        assert_eq!(
+            Config.decode_write(&config),
+            Err(WriteError::InvalidConfig(ConfigError::RankStability))
+        );
+    }
+
+    #[test]
+    fn rule_edits() {
+        use crate::rules::{RuleAction, RuleMatch};
+        use ConfigCharacteristic::Rules;
+
+        let rule = Rule {
+            matcher: RuleMatch::CompanyId(0x0006),
+            action: RuleAction::Ignore,
+        };
+        let mut add = alloc::vec![0];
+        rule.encode(&mut add);
+        assert_eq!(Rules.decode_write(&add), Ok(LightControls::RuleAdd(rule)));
+        assert_eq!(
+            Rules.decode_write(&[1, 3]),
+            Ok(LightControls::RuleRemove(3))
+        );
+        assert_eq!(Rules.decode_write(&[2]), Ok(LightControls::RulesClear));
+        assert_eq!(
+            ConfigCharacteristic::encode(&LightControls::RulesClear),
+            None
+        );
+
+        assert_eq!(
+            Rules.decode_write(&add[..add.len() - 1]),
+            Err(WriteError::InvalidRule(RuleError::Malformed))
+        );
+        assert_eq!(Rules.decode_write(&[1]), Err(WriteError::WrongLength));
+        assert_eq!(Rules.decode_write(&[2, 0]), Err(WriteError::WrongLength));
+        assert_eq!(Rules.decode_write(&[3]), Err(WriteError::OutOfRange));
+        assert_eq!(Rules.decode_write(&[]), Err(WriteError::WrongLength));
+    }
+
+    #[test]
+    fn uuids_are_unique() {
+        for (i, a) in ConfigCharacteristic::ALL.iter().enumerate() {
+            assert_ne!(a.uuid(), SERVICE_UUID);
+            for b in &ConfigCharacteristic::ALL[i + 1..] {
+                assert_ne!(a.uuid(), b.uuid());
+            }
+        }
+    }
+}
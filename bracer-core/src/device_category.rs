@@ -0,0 +1,137 @@
+//! Rough guess at what kind of device is advertising, from what it tells us about itself.
+
+use crate::advertisement::{
+    vendor::{ContinuityType, VendorInfo},
+    AdvertisementSummary, Uuid,
+};
+
+const COMPANY_GARMIN: u16 = 0x0087;
+const COMPANY_BOSE: u16 = 0x009e;
+
+const SERVICE_HEART_RATE: u16 = 0x180d;
+pub(crate) const SERVICE_SAMSUNG_SMART_TAG: u16 = 0xfd5a;
+pub(crate) const SERVICE_TILE: u16 = 0xfeed;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum DeviceCategory {
+    Phone,
+    Wearable,
+    Audio,
+    TrackerTag,
+    Laptop,
+    Beacon,
+    Unknown,
+}
+
+impl DeviceCategory {
+    pub const ALL: [Self; 7] = [
+        Self::Phone,
+        Self::Wearable,
+        Self::Audio,
+        Self::TrackerTag,
+        Self::Laptop,
+        Self::Beacon,
+        Self::Unknown,
+    ];
+
+    /// Most specific evidence wins: appearance, then vendor payloads, then services and company
+    pub fn classify(summary: &AdvertisementSummary) -> Self {
+        summary
+            .appearance
+            .and_then(Self::from_appearance)
+            .or_else(|| summary.vendor.and_then(Self::from_vendor))
+            .or_else(|| summary.service_uuids.iter().find_map(Self::from_service))
+            .or_else(|| summary.company_id.and_then(Self::from_company))
+            .unwrap_or(Self::Unknown)
+    }
+
+    fn from_appearance(appearance: u16) -> Option<Self> {
+        // only the category is looked at, subcategories are too inconsistently used
+        match appearance >> 6 {
+            0x01 => Some(Self::Phone),
+            0x02 => Some(Self::Laptop),
+            // watch, eye glasses, heart rate sensor, running sensor
+            0x03 | 0x07 | 0x0d | 0x11 => Some(Self::Wearable),
+            // tag, keyring
+            0x08 | 0x09 => Some(Self::TrackerTag),
+            // media player, audio sink, audio source, wearable audio
+            0x0a | 0x21 | 0x22 | 0x25 => Some(Self::Audio),
+            _ => None,
+        }
+    }
+
+    fn from_vendor(vendor: VendorInfo) -> Option<Self> {
+        match vendor {
+            VendorInfo::IBeacon(_) | VendorInfo::Eddystone(_) => Some(Self::Beacon),
+            VendorInfo::AppleContinuity(message) => match message {
+                ContinuityType::FindMy { .. } => Some(Self::TrackerTag),
+                ContinuityType::ProximityPairing => Some(Self::Audio),
+                ContinuityType::NearbyInfo
+                | ContinuityType::NearbyAction
+                | ContinuityType::Handoff
+                | ContinuityType::AirDrop => Some(Self::Phone),
+                _ => None,
+            },
+            VendorInfo::MicrosoftCdp { device_type } => match device_type {
+                // iPhone, Android, Windows phone
+                6 | 8 | 11 => Some(Self::Phone),
+                // Windows desktop, Linux, Windows laptop
+                9 | 12 | 15 => Some(Self::Laptop),
+                _ => None,
+            },
+            // mostly headphones and earbuds
+            VendorInfo::FastPair(_) => Some(Self::Audio),
+        }
+    }
+
+    fn from_service(uuid: &Uuid) -> Option<Self> {
+        match *uuid {
+            Uuid::Uuid16(SERVICE_HEART_RATE) => Some(Self::Wearable),
+            Uuid::Uuid16(SERVICE_SAMSUNG_SMART_TAG | SERVICE_TILE) => Some(Self::TrackerTag),
+            _ => None,
+        }
+    }
+
+    fn from_company(company_id: u16) -> Option<Self> {
+        match company_id {
+            COMPANY_GARMIN => Some(Self::Wearable),
+            COMPANY_BOSE => Some(Self::Audio),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advertisement::tests::*;
+
+    fn classify(payload: &[u8]) -> DeviceCategory {
+        DeviceCategory::classify(&AdvertisementSummary::from_payload(payload))
+    }
+
+    #[test]
+    fn recorded_payloads() {
+        assert_eq!(classify(IBEACON), DeviceCategory::Beacon);
+        assert_eq!(classify(EDDYSTONE_URL), DeviceCategory::Beacon);
+        assert_eq!(classify(APPLE_NEARBY_INFO), DeviceCategory::Phone);
+        assert_eq!(classify(MICROSOFT_CDP), DeviceCategory::Laptop);
+        assert_eq!(classify(FAST_PAIR), DeviceCategory::Audio);
+        assert_eq!(classify(&[]), DeviceCategory::Unknown);
+    }
+
+    #[test]
+    fn appearance_takes_priority() {
+        // a watch that also advertises Fast Pair
+        let mut payload = FAST_PAIR.to_vec();
+        payload.extend_from_slice(&[0x03, 0x19, 0xc1, 0x00]);
+        assert_eq!(classify(&payload), DeviceCategory::Wearable);
+
+        // unknown appearance categories fall through
+        assert_eq!(classify(&[0x03, 0x19, 0x00, 0x00]), DeviceCategory::Unknown);
+        assert_eq!(
+            classify(&[0x03, 0x19, 0x00, 0x00, 0x03, 0x03, 0xed, 0xfe]),
+            DeviceCategory::TrackerTag
+        );
+    }
+}
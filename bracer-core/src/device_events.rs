@@ -0,0 +1,47 @@
+//! Changes to the tracked devices, pushed to subscribers as they happen so consumers don't have to
+//! diff [`DeviceTracker::devices`](crate::ble_device_mgr::DeviceTracker::devices) to find them.
+//! Consumers that need every device, or every advertisement's payload, read the tracker's
+//! snapshots or are fed by it directly instead.
+
+use palette::RgbHue;
+
+use crate::address::BleAddress;
+
+/// Devices are identified by [`Device::address`](crate::ble_device_mgr::Device::address), which
+/// stays the same when the device rotates its address
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DeviceEvent {
+    /// Started tracking a device
+    Appeared { address: BleAddress, rssi: i32 },
+    /// Heard from a tracked device again
+    Updated { address: BleAddress, rssi: i32 },
+    /// Hasn't been heard from for a while, its signal strength is fading out
+    StartedDecaying { address: BleAddress },
+    /// No longer tracked
+    Lost { address: BleAddress },
+    /// A device became a favorite, sent after [`Self::Appeared`] for a new device
+    FavoriteFound { address: BleAddress, color: RgbHue },
+    /// A favorite is no longer tracked, sent after [`Self::Lost`]
+    FavoriteLost { address: BleAddress },
+}
+
+impl DeviceEvent {
+    pub fn address(&self) -> BleAddress {
+        match *self {
+            DeviceEvent::Appeared { address, .. }
+            | DeviceEvent::Updated { address, .. }
+            | DeviceEvent::StartedDecaying { address }
+            | DeviceEvent::Lost { address }
+            | DeviceEvent::FavoriteFound { address, .. }
+            | DeviceEvent::FavoriteLost { address } => address,
+        }
+    }
+}
+
+/// Receives device events, e.g. the sending end of a channel. Events are sent while the tracker
+/// is being updated, so sending must not block. Delivery is best effort: a sink that can't keep up
+/// drops events (and handles, or logs, that itself), and its consumer can resync from the
+/// tracker's devices.
+pub trait DeviceEventSink: Send {
+    fn send(&mut self, event: DeviceEvent);
+}
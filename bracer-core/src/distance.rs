@@ -0,0 +1,167 @@
+//! How far away a device is, from its signal strength and how loudly it transmits. Uses the
+//! log-distance path loss model: signal strength drops by `10 * n` dB for every tenfold increase in
+//! distance, from a reference strength at 1 m.
+
+#[cfg(not(test))]
+use num::Float;
+
+use crate::{advertisement::AdvertisementSummary, device_category::DeviceCategory};
+
+/// Free space loss between the antenna and 1 m away at 2.4 GHz, turns an advertised TX power into
+/// the signal strength expected at 1 m
+const LOSS_AT_1M: f32 = 41.0;
+
+/// Typical spread of signal strength around the model from walls, bodies and reflections, in dB
+const SHADOWING_STD_DEV: f32 = 4.0;
+
+/// Signal strength at 1 m of a typical phone, what [`DistanceModel::equivalent_signal`] compares
+/// devices as
+const TYPICAL_REFERENCE: f32 = -59.0;
+
+/// Rough signal strength at 1 m of a typical device in each category, for devices that don't
+/// advertise their TX power
+fn category_reference(category: DeviceCategory) -> f32 {
+    match category {
+        DeviceCategory::Phone => -59.0,
+        DeviceCategory::Wearable => -65.0,
+        DeviceCategory::Audio => -55.0,
+        DeviceCategory::TrackerTag => -62.0,
+        DeviceCategory::Laptop => -57.0,
+        DeviceCategory::Beacon => -59.0,
+        DeviceCategory::Unknown => -60.0,
+    }
+}
+
+/// Estimated distance, with the range it is likely (about two in three) to be within
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Distance {
+    pub meters: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistanceModel {
+    /// How quickly signal strength falls off with distance, 2 in free space and higher indoors
+    pub path_loss_exponent: f32,
+    /// Added to every reference strength, calibrated against a device at a known distance
+    pub calibration: f32,
+}
+
+impl Default for DistanceModel {
+    fn default() -> Self {
+        Self {
+            path_loss_exponent: 2.5,
+            calibration: 0.0,
+        }
+    }
+}
+
+impl DistanceModel {
+    /// Expected signal strength at 1 m, from the advertised TX power or the kind of device
+    pub fn reference_signal(&self, advertisement: &AdvertisementSummary) -> f32 {
+        let reference = match advertisement.tx_power {
+            Some(tx_power) => tx_power as f32 - LOSS_AT_1M,
+            None => category_reference(DeviceCategory::classify(advertisement)),
+        };
+        reference + self.calibration
+    }
+
+    fn meters(&self, reference: f32, signal: f32) -> f32 {
+        10.0_f32.powf((reference - signal) / (10.0 * self.path_loss_exponent))
+    }
+
+    /// `variance` is how uncertain the signal strength is, e.g. from its
+    /// [`SignalFilter`](crate::signal_filter::SignalFilter)
+    pub fn estimate(
+        &self,
+        signal: f32,
+        variance: f32,
+        advertisement: &AdvertisementSummary,
+    ) -> Distance {
+        let reference = self.reference_signal(advertisement);
+        let std_dev = (variance + SHADOWING_STD_DEV * SHADOWING_STD_DEV).sqrt();
+        Distance {
+            meters: self.meters(reference, signal),
+            min: self.meters(reference, signal + std_dev),
+            max: self.meters(reference, signal - std_dev),
+        }
+    }
+
+    /// Signal strength a typical phone would have at `meters`, so distances can be ranked and
+    /// compared against the signal thresholds
+    pub fn equivalent_signal(&self, meters: f32) -> f32 {
+        TYPICAL_REFERENCE - 10.0 * self.path_loss_exponent * meters.log10()
+    }
+
+    /// Adjust the calibration so that a device measured at `signal` is estimated at `meters`
+    pub fn calibrate(&mut self, signal: f32, meters: f32, advertisement: &AdvertisementSummary) {
+        let uncalibrated = self.reference_signal(advertisement) - self.calibration;
+        let reference = signal + 10.0 * self.path_loss_exponent * meters.log10();
+        self.calibration = reference - uncalibrated;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    #[test]
+    fn path_loss() {
+        let model = DistanceModel {
+            path_loss_exponent: 2.0,
+            calibration: 0.0,
+        };
+        let phone = AdvertisementSummary {
+            appearance: Some(0x01 << 6),
+            ..Default::default()
+        };
+        assert!(close(model.estimate(-59.0, 0.0, &phone).meters, 1.0));
+        assert!(close(model.estimate(-79.0, 0.0, &phone).meters, 10.0));
+
+        // the range widens with a noisier signal
+        let steady = model.estimate(-79.0, 0.0, &phone);
+        let noisy = model.estimate(-79.0, 20.0, &phone);
+        assert!(steady.min < 10.0 && steady.max > 10.0);
+        assert!(noisy.min < steady.min && noisy.max > steady.max);
+
+        // ranks like the distance it stands for
+        assert!(close(model.equivalent_signal(10.0), -79.0));
+    }
+
+    #[test]
+    fn tx_power_tells_loud_from_quiet() {
+        let model = DistanceModel::default();
+        let speaker = AdvertisementSummary {
+            tx_power: Some(8),
+            ..Default::default()
+        };
+        let wearable = AdvertisementSummary {
+            tx_power: Some(-20),
+            ..Default::default()
+        };
+
+        // heard equally strongly, but the speaker is shouting from much further away
+        let speaker = model.estimate(-65.0, 0.0, &speaker).meters;
+        let wearable = model.estimate(-65.0, 0.0, &wearable).meters;
+        assert!(speaker > 10.0);
+        assert!(wearable < 2.0);
+    }
+
+    #[test]
+    fn calibration() {
+        let mut model = DistanceModel::default();
+        let device = AdvertisementSummary::default();
+        model.calibrate(-70.0, 2.0, &device);
+        assert!(close(model.estimate(-70.0, 0.0, &device).meters, 2.0));
+
+        // calibrating again replaces rather than adds to the last calibration
+        model.calibrate(-70.0, 1.0, &device);
+        assert!(close(model.estimate(-70.0, 0.0, &device).meters, 1.0));
+        assert!(close(model.reference_signal(&device), -70.0));
+    }
+}
@@ -0,0 +1,191 @@
+//! Manufacturer specific data advertised by bracers so they can find their favorites.
+//!
+//! | Bytes | Field                                                   |
+//! |-------|---------------------------------------------------------|
+//! | 0..2  | Company ID `0xFFFF` (reserved for testing), little endian |
+//! | 2..4  | Magic `"BR"`                                            |
+//! | 4     | Protocol version                                        |
+//! | 5..9  | Group ID, `u32` little endian                           |
+//! | 9..11 | Hue in degrees, `u16` little endian                     |
+//! | 11    | Brightness level                                        |
+//! | 12    | Mode flags, unknown bits are ignored                    |
+
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use crate::{advertisement::AdStructure, messages::DisplaySortMode};
+
+const COMPANY_ID: u16 = 0xffff;
+const MAGIC: [u8; 2] = *b"BR";
+
+/// Bump whenever the layout changes, payloads with other versions are rejected
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const PAYLOAD_LEN: usize = 13;
+
+/// Set when the sender is showing devices in [`DisplaySortMode::Sticky`]
+const FLAG_STICKY: u8 = 1 << 0;
+
+/// Sent in the clear and not authenticated. A device replaying a recorded payload is taken for the
+/// favorite it copied, bracers have no shared clock to check a rotating token against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FavoritePayload {
+    /// Bracers with the same group ID are each other's favorites
+    pub group_id: u32,
+    /// Hue in degrees the receiver should draw the sender with
+    pub hue: u16,
+    pub brightness: u8,
+    pub mode: DisplaySortMode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadError {
+    /// Some other manufacturer's data, the common case
+    NotFavorite,
+    UnsupportedVersion(u8),
+    WrongLength,
+    HueOutOfRange,
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::NotFavorite => f.write_str("not a favorite payload"),
+            PayloadError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            PayloadError::WrongLength => f.write_str("wrong length"),
+            PayloadError::HueOutOfRange => f.write_str("hue out of range"),
+        }
+    }
+}
+
+impl FavoritePayload {
+    /// Manufacturer data to advertise, including the company ID
+    pub fn encode(&self) -> [u8; PAYLOAD_LEN] {
+        let mut buf = [0; PAYLOAD_LEN];
+        buf[0..2].copy_from_slice(&COMPANY_ID.to_le_bytes());
+        buf[2..4].copy_from_slice(&MAGIC);
+        buf[4] = PROTOCOL_VERSION;
+        buf[5..9].copy_from_slice(&self.group_id.to_le_bytes());
+        buf[9..11].copy_from_slice(&self.hue.to_le_bytes());
+        buf[11] = self.brightness;
+        buf[12] = match self.mode {
+            DisplaySortMode::Sticky => FLAG_STICKY,
+            DisplaySortMode::Ordered => 0,
+        };
+        buf
+    }
+
+    /// Advertising payload carrying only this manufacturer data, as a scanner would see it
+    pub fn to_advertisement(&self) -> Vec<u8> {
+        let mut advertisement = Vec::new();
+        AdStructure::ManufacturerData(&self.encode()).encode(&mut advertisement);
+        advertisement
+    }
+
+    /// Parse manufacturer data from a scan result, including the company ID
+    pub fn decode(data: &[u8]) -> Result<Self, PayloadError> {
+        if data.get(0..2) != Some(&COMPANY_ID.to_le_bytes()[..])
+            || data.get(2..4) != Some(&MAGIC[..])
+        {
+            return Err(PayloadError::NotFavorite);
+        }
+        match data.get(4) {
+            Some(&PROTOCOL_VERSION) => {}
+            Some(&version) => return Err(PayloadError::UnsupportedVersion(version)),
+            None => return Err(PayloadError::WrongLength),
+        }
+        let data: &[u8; PAYLOAD_LEN] = data.try_into().map_err(|_| PayloadError::WrongLength)?;
+
+        let hue = u16::from_le_bytes([data[9], data[10]]);
+        if hue >= 360 {
+            return Err(PayloadError::HueOutOfRange);
+        }
+
+        Ok(Self {
+            group_id: u32::from_le_bytes([data[5], data[6], data[7], data[8]]),
+            hue,
+            brightness: data[11],
+            mode: if data[12] & FLAG_STICKY != 0 {
+                DisplaySortMode::Sticky
+            } else {
+                DisplaySortMode::Ordered
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: FavoritePayload = FavoritePayload {
+        group_id: 0xdead_beef,
+        hue: 240,
+        brightness: 3,
+        mode: DisplaySortMode::Sticky,
+    };
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(FavoritePayload::decode(&PAYLOAD.encode()), Ok(PAYLOAD));
+    }
+
+    #[test]
+    fn advertisement_round_trip() {
+        let advertisement = PAYLOAD.to_advertisement();
+        assert_eq!(
+            crate::advertisement::parse(&advertisement).next(),
+            Some(Ok(AdStructure::ManufacturerData(&PAYLOAD.encode())))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        let encoded = PAYLOAD.encode();
+
+        // every truncation is rejected without panicking
+        for len in 0..PAYLOAD_LEN {
+            assert!(FavoritePayload::decode(&encoded[..len]).is_err());
+        }
+
+        let mut apple = encoded;
+        apple[0..2].copy_from_slice(&0x004c_u16.to_le_bytes());
+        assert_eq!(
+            FavoritePayload::decode(&apple),
+            Err(PayloadError::NotFavorite)
+        );
+
+        let mut future = encoded;
+        future[4] = PROTOCOL_VERSION + 1;
+        assert_eq!(
+            FavoritePayload::decode(&future),
+            Err(PayloadError::UnsupportedVersion(PROTOCOL_VERSION + 1))
+        );
+
+        let mut bad_hue = encoded;
+        bad_hue[9..11].copy_from_slice(&360_u16.to_le_bytes());
+        assert_eq!(
+            FavoritePayload::decode(&bad_hue),
+            Err(PayloadError::HueOutOfRange)
+        );
+
+        let mut long = [0; PAYLOAD_LEN + 1];
+        long[..PAYLOAD_LEN].copy_from_slice(&encoded);
+        assert_eq!(
+            FavoritePayload::decode(&long),
+            Err(PayloadError::WrongLength)
+        );
+    }
+
+    #[test]
+    fn unknown_flags_are_ignored() {
+        let mut encoded = PAYLOAD.encode();
+        encoded[12] = 0xfe;
+        assert_eq!(
+            FavoritePayload::decode(&encoded).unwrap().mode,
+            DisplaySortMode::Ordered
+        );
+    }
+}
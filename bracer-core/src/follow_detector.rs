@@ -0,0 +1,251 @@
+//! Spotting item trackers (AirTags, Tiles, SmartTags) that stay with us, e.g. one slipped into a
+//! bag. The device tracker only knows what is around right now, so trackers get their own history.
+
+use alloc::vec::Vec;
+use log::warn;
+
+use crate::{
+    address::{AddressType, BleAddress},
+    advertisement::{
+        vendor::{ContinuityType, VendorInfo},
+        AdvertisementSummary, Uuid,
+    },
+    config::Config,
+    device_category::{SERVICE_SAMSUNG_SMART_TAG, SERVICE_TILE},
+    identity::Sighting,
+    time::Instant,
+};
+
+/// Trackers that aren't near their owner, a Find My accessory with its owner isn't a concern
+pub fn is_item_tracker(advertisement: &AdvertisementSummary) -> bool {
+    let find_my_separated = matches!(
+        advertisement.vendor,
+        Some(VendorInfo::AppleContinuity(ContinuityType::FindMy {
+            separated: true
+        }))
+    );
+    let tag_service = advertisement
+        .service_uuids
+        .iter()
+        .any(|uuid| matches!(uuid, Uuid::Uuid16(SERVICE_TILE | SERVICE_SAMSUNG_SMART_TAG)));
+    find_my_separated || tag_service
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Follower {
+    /// Trackers rotate their address too, so they are linked the same way as tracked devices
+    last_seen: Sighting,
+    advertisement: AdvertisementSummary,
+    /// Start of the current unbroken stretch of close sightings
+    since: Instant,
+    following: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FollowDetector {
+    trackers: Vec<Follower>,
+}
+
+impl FollowDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an advertisement from an item tracker, returns `true` the moment it is flagged as
+    /// following us. Sightings weaker than [`Config::follow_signal_min`] count as not being heard,
+    /// so a tracker that stays that weak for longer than [`Config::follow_max_gap`] starts over.
+    pub fn record(
+        &mut self,
+        address: BleAddress,
+        address_type: AddressType,
+        advertisement: &AdvertisementSummary,
+        rssi: i32,
+        now: Instant,
+        config: &Config,
+    ) -> bool {
+        if rssi < config.follow_signal_min {
+            return false;
+        }
+
+        let existing = self
+            .trackers
+            .iter()
+            .position(|tracker| tracker.last_seen.address == address)
+            .or_else(|| {
+                self.trackers.iter().position(|tracker| {
+                    tracker.last_seen.is_rotation(
+                        &tracker.advertisement,
+                        &address,
+                        address_type,
+                        advertisement,
+                        rssi,
+                        now,
+                    )
+                })
+            });
+        let tracker = match existing {
+            Some(idx) => {
+                let tracker = &mut self.trackers[idx];
+                if now.duration_since(tracker.last_seen.at) > config.follow_max_gap {
+                    tracker.since = now;
+                    tracker.following = false;
+                }
+                tracker.last_seen.next(address, address_type, rssi, now);
+                tracker.advertisement.merge(advertisement);
+                tracker
+            }
+            None => {
+                self.trackers.push(Follower {
+                    last_seen: Sighting::new(address, address_type, rssi, now),
+                    advertisement: *advertisement,
+                    since: now,
+                    following: false,
+                });
+                self.trackers.last_mut().unwrap()
+            }
+        };
+
+        if tracker.following || now.duration_since(tracker.since) < config.follow_duration {
+            return false;
+        }
+        warn!(
+            "Item tracker {} has been following us for {}s",
+            address,
+            now.duration_since(tracker.since).as_secs()
+        );
+        tracker.following = true;
+        true
+    }
+
+    /// Forget trackers that haven't been close for longer than [`Config::follow_max_gap`]
+    pub fn prune(&mut self, now: Instant, config: &Config) {
+        self.trackers
+            .retain(|tracker| now.duration_since(tracker.last_seen.at) <= config.follow_max_gap);
+    }
+
+    /// Current addresses of the trackers following us
+    pub fn followers(&self) -> impl Iterator<Item = BleAddress> + '_ {
+        self.trackers
+            .iter()
+            .filter(|tracker| tracker.following)
+            .map(|tracker| tracker.last_seen.address)
+    }
+
+    pub fn is_followed(&self) -> bool {
+        self.followers().next().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::advertisement::tests::{AIRTAG_SEPARATED, APPLE_NEARBY_INFO, SMART_TAG, TILE};
+
+    const AIRTAG: BleAddress = BleAddress([0x4E, 0x01, 0x02, 0x03, 0x04, 0x05]);
+    const AIRTAG_ROTATED: BleAddress = BleAddress([0x71, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E]);
+
+    fn config() -> Config {
+        Config {
+            follow_duration: Duration::from_secs(60),
+            follow_max_gap: Duration::from_secs(30),
+            ..Default::default()
+        }
+    }
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_micros(secs * 1_000_000)
+    }
+
+    /// Feed one advertisement every `interval_secs` from `start` until `end`, returns when the
+    /// tracker was first flagged
+    fn timeline(
+        detector: &mut FollowDetector,
+        address: BleAddress,
+        rssi: impl Fn(u64) -> i32,
+        (start, end, interval_secs): (u64, u64, u64),
+    ) -> Option<u64> {
+        let advertisement = AdvertisementSummary::from_payload(AIRTAG_SEPARATED);
+        let mut flagged = None;
+        for secs in (start..end).step_by(interval_secs as usize) {
+            if detector.record(
+                address,
+                AddressType::Random,
+                &advertisement,
+                rssi(secs),
+                at(secs),
+                &config(),
+            ) {
+                flagged = flagged.or(Some(secs));
+            }
+            detector.prune(at(secs), &config());
+        }
+        flagged
+    }
+
+    #[test]
+    fn recognizes_item_trackers() {
+        let tracker = |payload| is_item_tracker(&AdvertisementSummary::from_payload(payload));
+        assert!(tracker(AIRTAG_SEPARATED));
+        assert!(tracker(TILE));
+        assert!(tracker(SMART_TAG));
+        assert!(!tracker(APPLE_NEARBY_INFO));
+    }
+
+    #[test]
+    fn tracker_staying_close_is_flagged() {
+        let mut detector = FollowDetector::new();
+        assert_eq!(
+            timeline(&mut detector, AIRTAG, |_| -60, (0, 120, 2)),
+            Some(60)
+        );
+        assert!(detector.followers().eq([AIRTAG]));
+    }
+
+    #[test]
+    fn passing_tracker_is_not_flagged() {
+        let mut detector = FollowDetector::new();
+
+        // close for 40s then out of range, comes back after the gap and starts over
+        let rssi = |secs| if secs < 40 { -60 } else { -90 };
+        assert_eq!(timeline(&mut detector, AIRTAG, rssi, (0, 200, 2)), None);
+        assert_eq!(
+            timeline(&mut detector, AIRTAG, |_| -60, (200, 250, 2)),
+            None
+        );
+        assert!(!detector.is_followed());
+
+        // trackers that have left are forgotten
+        detector.prune(at(500), &config());
+        assert!(detector.trackers.is_empty());
+    }
+
+    #[test]
+    fn weak_stretch_breaks_continuity() {
+        let mut detector = FollowDetector::new();
+
+        // still heard, but too weak to be with us for longer than the gap
+        let rssi = |secs| if (40..80).contains(&secs) { -90 } else { -60 };
+        assert_eq!(
+            timeline(&mut detector, AIRTAG, rssi, (0, 150, 2)),
+            Some(140)
+        );
+
+        // a shorter weak stretch doesn't start over
+        let mut detector = FollowDetector::new();
+        let rssi = |secs| if (20..40).contains(&secs) { -90 } else { -60 };
+        assert_eq!(timeline(&mut detector, AIRTAG, rssi, (0, 100, 2)), Some(60));
+    }
+
+    #[test]
+    fn rotating_tracker_is_still_flagged() {
+        let mut detector = FollowDetector::new();
+        assert_eq!(timeline(&mut detector, AIRTAG, |_| -60, (0, 40, 2)), None);
+        assert_eq!(
+            timeline(&mut detector, AIRTAG_ROTATED, |_| -61, (40, 80, 2)),
+            Some(60)
+        );
+        assert!(detector.followers().eq([AIRTAG_ROTATED]));
+    }
+}
@@ -0,0 +1,343 @@
+//! Linking rotated private addresses back to the device that was using the previous one.
+//!
+//! Phones change their resolvable private address every ~15 minutes, and without the identity key
+//! there is no way to know for sure that two addresses belong to the same device. Instead a new
+//! address is linked to a tracked one that just went quiet when the signal strength picks up where
+//! it left off, it shows up within a few advertising intervals and the payloads look alike.
+
+use core::time::Duration;
+
+use crate::{
+    address::{AddressType, BleAddress},
+    advertisement::{vendor::VendorInfo, AdvertisementSummary},
+    time::Instant,
+};
+
+/// Largest signal strength jump between the old and new address, in dBm
+const MAX_RSSI_JUMP: i32 = 8;
+
+/// How many advertisements the old address may miss before the new address shows up
+const MAX_MISSED_ADVERTISEMENTS: u32 = 5;
+
+/// Longest silence between the old and new address, whatever the advertising interval
+const MAX_ROTATION_GAP: Duration = Duration::from_secs(3);
+
+/// Gaps longer than this are the device being out of range, not its advertising interval
+const MAX_ADVERTISING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How much each new gap between advertisements moves the interval estimate
+const INTERVAL_SMOOTHING: f32 = 0.25;
+
+/// The latest advertisement from a tracked device, what a new address is compared against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sighting {
+    pub address: BleAddress,
+    pub address_type: AddressType,
+    pub rssi: i32,
+    pub at: Instant,
+    /// Smoothed time between advertisements, once there have been two close enough together
+    pub interval: Option<Duration>,
+}
+
+impl Sighting {
+    pub fn new(address: BleAddress, address_type: AddressType, rssi: i32, at: Instant) -> Self {
+        Self {
+            address,
+            address_type,
+            rssi,
+            at,
+            interval: None,
+        }
+    }
+
+    /// Record the next advertisement from the same device, possibly from a new address
+    pub fn next(&mut self, address: BleAddress, address_type: AddressType, rssi: i32, at: Instant) {
+        let gap = at.duration_since(self.at);
+        if address == self.address && gap <= MAX_ADVERTISING_INTERVAL {
+            self.interval = Some(match self.interval {
+                Some(interval) => {
+                    let interval = interval.as_secs_f32();
+                    Duration::from_secs_f32(
+                        interval + (gap.as_secs_f32() - interval) * INTERVAL_SMOOTHING,
+                    )
+                }
+                None => gap,
+            });
+        }
+        self.address = address;
+        self.address_type = address_type;
+        self.rssi = rssi;
+        self.at = at;
+    }
+
+    /// Whether a new `address` is likely this device after rotating its address.
+    /// `advertisement` is what this device has advertised so far, `next` what the new address did.
+    pub fn is_rotation(
+        &self,
+        advertisement: &AdvertisementSummary,
+        address: &BleAddress,
+        address_type: AddressType,
+        next: &AdvertisementSummary,
+        rssi: i32,
+        at: Instant,
+    ) -> bool {
+        if !self.address.is_resolvable_private(self.address_type)
+            || !address.is_resolvable_private(address_type)
+            || self.address == *address
+        {
+            return false;
+        }
+
+        // the old address has to have gone quiet, but only just
+        let Some(interval) = self.interval else {
+            return false;
+        };
+        let silence = at.duration_since(self.at);
+        if silence < interval / 2
+            || silence > (interval * MAX_MISSED_ADVERTISEMENTS).min(MAX_ROTATION_GAP)
+        {
+            return false;
+        }
+
+        (rssi - self.rssi).abs() <= MAX_RSSI_JUMP && fingerprints_match(advertisement, next)
+    }
+}
+
+/// Fields that survive an address rotation must agree where both payloads have them, and there
+/// has to be at least one to compare, since an empty payload says nothing about who sent it
+pub fn fingerprints_match(a: &AdvertisementSummary, b: &AdvertisementSummary) -> bool {
+    fn agree<T: PartialEq>(a: Option<T>, b: Option<T>, compared: &mut bool) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                *compared = true;
+                a == b
+            }
+            _ => true,
+        }
+    }
+
+    let mut compared = false;
+    let service_uuids = |summary: &AdvertisementSummary| {
+        Some(summary.service_uuids).filter(|uuids| !uuids.is_empty())
+    };
+    let consistent = agree(a.flags, b.flags, &mut compared)
+        && agree(a.tx_power, b.tx_power, &mut compared)
+        && agree(a.appearance, b.appearance, &mut compared)
+        && agree(a.company_id, b.company_id, &mut compared)
+        && agree(service_uuids(a), service_uuids(b), &mut compared)
+        && agree(
+            a.vendor.map(VendorKind::from),
+            b.vendor.map(VendorKind::from),
+            &mut compared,
+        );
+    consistent && compared
+}
+
+/// The parts of a vendor payload that stay the same when the address rotates, rotating devices
+/// usually change the rest of the payload too
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VendorKind {
+    Beacon(VendorInfo),
+    Eddystone,
+    AppleContinuity(crate::advertisement::vendor::ContinuityType),
+    MicrosoftCdp(u8),
+    FastPair,
+}
+
+impl From<VendorInfo> for VendorKind {
+    fn from(vendor: VendorInfo) -> Self {
+        match vendor {
+            VendorInfo::IBeacon(_) => Self::Beacon(vendor),
+            VendorInfo::Eddystone(_) => Self::Eddystone,
+            VendorInfo::AppleContinuity(message) => Self::AppleContinuity(message),
+            VendorInfo::MicrosoftCdp { device_type } => Self::MicrosoftCdp(device_type),
+            VendorInfo::FastPair(_) => Self::FastPair,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        advertisement::tests::{APPLE_NEARBY_INFO, FAST_PAIR},
+        ble_device_mgr::{DeviceTracker, FavoriteId, Pairing},
+        config::Config,
+        time::{Clock, MockClock},
+    };
+
+    /// One advertisement as a scanner saw it
+    struct TraceEntry {
+        at_ms: u64,
+        address: &'static str,
+        rssi: i32,
+        payload: &'static [u8],
+    }
+
+    const fn entry(
+        at_ms: u64,
+        address: &'static str,
+        rssi: i32,
+        payload: &'static [u8],
+    ) -> TraceEntry {
+        TraceEntry {
+            at_ms,
+            address,
+            rssi,
+            payload,
+        }
+    }
+
+    const PHONE: &str = "5A:10:20:30:40:50";
+    const PHONE_ROTATED: &str = "6B:11:21:31:41:51";
+    const OTHER_PHONE: &str = "4C:90:80:70:60:50";
+    const HEADPHONES: &str = "73:AA:BB:CC:DD:EE";
+
+    /// A phone rotates its address at 1.5s while a second phone keeps advertising nearby
+    const ROTATION_NEXT_TO_PHONE: &[TraceEntry] = &[
+        entry(0, PHONE, -61, APPLE_NEARBY_INFO),
+        entry(100, OTHER_PHONE, -74, APPLE_NEARBY_INFO),
+        entry(300, PHONE, -60, APPLE_NEARBY_INFO),
+        entry(400, OTHER_PHONE, -75, APPLE_NEARBY_INFO),
+        entry(600, PHONE, -62, APPLE_NEARBY_INFO),
+        entry(700, OTHER_PHONE, -73, APPLE_NEARBY_INFO),
+        entry(900, PHONE, -61, APPLE_NEARBY_INFO),
+        entry(1000, OTHER_PHONE, -74, APPLE_NEARBY_INFO),
+        entry(1200, PHONE, -60, APPLE_NEARBY_INFO),
+        entry(1300, OTHER_PHONE, -75, APPLE_NEARBY_INFO),
+        entry(1600, OTHER_PHONE, -74, APPLE_NEARBY_INFO),
+        entry(1700, PHONE_ROTATED, -63, APPLE_NEARBY_INFO),
+        entry(1900, OTHER_PHONE, -73, APPLE_NEARBY_INFO),
+        entry(2000, PHONE_ROTATED, -62, APPLE_NEARBY_INFO),
+        entry(2300, PHONE_ROTATED, -61, APPLE_NEARBY_INFO),
+    ];
+
+    /// A phone walks off, and headphones show up right where it was
+    const HANDOFF_TO_HEADPHONES: &[TraceEntry] = &[
+        entry(0, PHONE, -61, APPLE_NEARBY_INFO),
+        entry(300, PHONE, -60, APPLE_NEARBY_INFO),
+        entry(600, PHONE, -62, APPLE_NEARBY_INFO),
+        entry(1100, HEADPHONES, -62, FAST_PAIR),
+        entry(1300, HEADPHONES, -61, FAST_PAIR),
+    ];
+
+    /// The phone's address rotates while it is being carried away, too big a jump to link
+    const ROTATION_WHILE_LEAVING: &[TraceEntry] = &[
+        entry(0, PHONE, -55, APPLE_NEARBY_INFO),
+        entry(300, PHONE, -56, APPLE_NEARBY_INFO),
+        entry(600, PHONE, -55, APPLE_NEARBY_INFO),
+        entry(900, PHONE_ROTATED, -70, APPLE_NEARBY_INFO),
+    ];
+
+    /// The new address only shows up long after the old one went quiet
+    const ROTATION_AFTER_GAP: &[TraceEntry] = &[
+        entry(0, PHONE, -61, APPLE_NEARBY_INFO),
+        entry(300, PHONE, -60, APPLE_NEARBY_INFO),
+        entry(600, PHONE, -62, APPLE_NEARBY_INFO),
+        entry(3000, PHONE_ROTATED, -61, APPLE_NEARBY_INFO),
+    ];
+
+    /// Replay a trace, returning the tracked devices as (identity, current address)
+    fn replay(
+        trace: &[TraceEntry],
+        address_type: AddressType,
+    ) -> alloc::vec::Vec<(BleAddress, BleAddress)> {
+        let clock = MockClock::new();
+        let pairing = Pairing {
+            id: FavoriteId::new("PARTY_TIME").unwrap(),
+            hue: 0,
+        };
+        let mut tracker = DeviceTracker::new(pairing, Config::default(), clock.clone());
+        for entry in trace {
+            let at = Instant::from_micros(entry.at_ms * 1000);
+            clock.advance(at.duration_since(clock.now()));
+            tracker.update(
+                entry.address.parse().unwrap(),
+                address_type,
+                entry.payload,
+                entry.rssi,
+            );
+        }
+        tracker
+            .devices
+            .iter()
+            .map(|device| (device.address, device.current_address()))
+            .collect()
+    }
+
+    #[test]
+    fn rotation_is_linked() {
+        let phone = PHONE.parse().unwrap();
+        let other_phone = OTHER_PHONE.parse().unwrap();
+        assert_eq!(
+            replay(ROTATION_NEXT_TO_PHONE, AddressType::Random),
+            [
+                (phone, PHONE_ROTATED.parse().unwrap()),
+                (other_phone, other_phone)
+            ]
+        );
+    }
+
+    #[test]
+    fn unrelated_devices_are_not_linked() {
+        for trace in [
+            HANDOFF_TO_HEADPHONES,
+            ROTATION_WHILE_LEAVING,
+            ROTATION_AFTER_GAP,
+        ] {
+            let devices = replay(trace, AddressType::Random);
+            assert_eq!(devices.len(), 2);
+            assert!(devices.iter().all(|(address, current)| address == current));
+        }
+    }
+
+    #[test]
+    fn public_addresses_dont_rotate() {
+        // public addresses that happen to start like resolvable private ones
+        let devices = replay(ROTATION_NEXT_TO_PHONE, AddressType::Public);
+        assert_eq!(devices.len(), 3);
+        assert!(devices.iter().all(|(address, current)| address == current));
+    }
+
+    #[test]
+    fn interval_estimate() {
+        let address = PHONE.parse().unwrap();
+        let mut sighting = Sighting::new(address, AddressType::Random, -60, Instant::default());
+        assert_eq!(sighting.interval, None);
+
+        sighting.next(
+            address,
+            AddressType::Random,
+            -60,
+            Instant::from_micros(200_000),
+        );
+        assert_eq!(sighting.interval, Some(Duration::from_millis(200)));
+
+        // out of range for a while, not an advertising interval
+        sighting.next(
+            address,
+            AddressType::Random,
+            -60,
+            Instant::from_micros(10_000_000),
+        );
+        assert_eq!(sighting.interval, Some(Duration::from_millis(200)));
+
+        sighting.next(
+            address,
+            AddressType::Random,
+            -60,
+            Instant::from_micros(10_600_000),
+        );
+        let interval = sighting.interval.unwrap().as_secs_f32();
+        assert!((interval - 0.3).abs() < 1e-3);
+    }
+
+    #[test]
+    fn empty_payloads_have_no_fingerprint() {
+        let empty = AdvertisementSummary::default();
+        let phone = AdvertisementSummary::from_payload(APPLE_NEARBY_INFO);
+        assert!(!fingerprints_match(&empty, &empty));
+        assert!(!fingerprints_match(&empty, &phone));
+        assert!(fingerprints_match(&phone, &phone));
+    }
+}
@@ -0,0 +1,45 @@
+//! Hardware independent logic for the bracer:
+//! - Device tracking, address rotation, signal filtering, decay, trends and presence confidence,
+//!   and change events
+//! - Lock free snapshots of the tracked devices for the lights
+//! - User rules for which devices are shown and how
+//! - Detection of item trackers following us and of BLE spam
+//! - Distance estimation, ranking and LED animation
+//! - Guided calibration of the favorite signal range
+//! - Color allocation, by category or at random, and persistence
+//!
+//! Builds without `std` so it can be shared by the firmware and tested on the host.
+
+#![no_std]
+
+extern crate alloc;
+#[cfg(test)]
+extern crate std;
+
+pub mod address;
+pub mod advertisement;
+pub mod ble_device_mgr;
+pub mod calibration;
+pub mod color_store;
+pub mod color_strategy;
+pub mod config;
+pub mod config_service;
+pub mod device_category;
+pub mod device_events;
+pub mod distance;
+pub mod favorite_payload;
+pub mod follow_detector;
+pub mod identity;
+pub mod light_mgr;
+pub mod messages;
+pub mod pixel_sink;
+pub mod presence;
+pub mod ranking;
+pub mod rules;
+pub mod signal_filter;
+pub mod simulator;
+pub mod spam_detector;
+pub mod time;
+pub mod trend;
+pub mod triple_buffer;
+pub mod utils;
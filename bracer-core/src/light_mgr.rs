@@ -0,0 +1,1570 @@
+use alloc::{boxed::Box, collections::BTreeMap};
+
+use const_format::assertcp;
+use log::{info, warn};
+#[cfg(not(test))]
+use num::Float;
+use palette::{Blend, FromColor, Hsv, RgbHue, Srgb};
+use rand::{rngs::SmallRng, seq::IteratorRandom, SeedableRng};
+
+use crate::{
+    address::BleAddress,
+    advertisement::AdvertisementSummary,
+    ble_device_mgr::{DeviceTracker, TrackerSnapshot},
+    calibration::{CalibrationError, CalibrationState, FavoriteCalibration, NEAR_METERS},
+    color_store::ColorStore,
+    color_strategy::{CategoryColors, ColorStrategy, RandomColors},
+    config::{ColorMode, Config},
+    messages::DisplaySortMode,
+    pixel_sink::{Color, PixelSink},
+    ranking::{RankCandidate, RankStabilizer},
+    time::Clock,
+    trend::Trend,
+    utils,
+};
+
+pub const NUM_LIGHTS: usize = 60;
+pub const MAX_DEVICES_SHOWN: usize = 10;
+
+// /// How many seconds to fade in a new device, keeps a bright light from popping in
+// const FADE_IN_DURATION: u32 = 1;
+
+/// How many lights to reserve for the favorite devices at the top
+const FAVORITE_RESERVE_LIGHTS: usize = 10;
+
+/// Favorites split the reserved lights between them, any more than this are not shown
+const MAX_FAVORITES_SHOWN: usize = 5;
+
+/// Distinct shades within each category's hue family, when coloring by category
+const SHADES_PER_CATEGORY: usize = 4;
+
+/// How many lights to show per device, must be odd for looks
+const SLOT_WIDTH: usize = (NUM_LIGHTS - FAVORITE_RESERVE_LIGHTS) / MAX_DEVICES_SHOWN;
+assertcp!(SLOT_WIDTH % 2 == 1);
+
+/// In sticky mode, the dimmest a device will be drawn (as a fraction of the current brightness)
+const STICKY_MIN_BRIGHTNESS: f32 = 0.1;
+
+/// While an item tracker is following us, how often the alert plays
+const FOLLOW_ALERT_PERIOD_STEPS: u64 = 10 * STEPS_PER_SECOND;
+
+/// How long the alert plays each period
+const FOLLOW_ALERT_STEPS: u64 = 2 * STEPS_PER_SECOND;
+
+/// During the alert, how many times a second the lit and dark lights swap
+const FOLLOW_ALERT_SWAPS_PER_SECOND: u64 = 8;
+
+/// Alerts are drawn at least this bright, so they can't be missed at low brightness levels
+const FOLLOW_ALERT_MIN_BRIGHTNESS: f32 = 0.5;
+
+/// During a spam attack, how long the warning takes to sweep from one end of the strip to the other
+const SPAM_WARNING_SWEEP_STEPS: u64 = 2 * STEPS_PER_SECOND;
+
+/// Lights on either side of the sweep that glow with it
+const SPAM_WARNING_GLOW: usize = 4;
+
+/// Each light of the counter at the end of the strip stands for this many blocked addresses
+const SPAM_WARNING_PER_LIGHT: u32 = 5;
+
+/// Amber, so it isn't mistaken for the follow alert
+const SPAM_WARNING_HUE: f32 = 40.0;
+
+/// A device's trend is only shown once it is at least this confident, see [`Trend::confidence`]
+const TREND_MIN_CONFIDENCE: f32 = 0.5;
+
+/// Slower trends than this, in dB per second, aren't shown
+const TREND_MIN_RATE: f32 = 1.0;
+
+/// Laps of its slot the trend pixel makes per second, for each dB per second of trend
+const TREND_LAPS_PER_DB: f32 = 0.25;
+
+/// Fastest the trend pixel laps its slot, per second, so it stays readable
+const TREND_MAX_LAPS: f32 = 2.0;
+
+/// Brightness of the trend pixel relative to the device, lightening the lights it passes
+const TREND_BRIGHTNESS: f32 = 0.6;
+
+/// Saturation of a device we have no confidence is really around, it grows to full with its
+/// presence confidence
+const PRESENCE_MIN_SATURATION: f32 = 0.4;
+
+/// While calibrating, how long the distance to stand at takes to blink on and off
+const CALIBRATION_BLINK_STEPS: u64 = STEPS_PER_SECOND;
+
+/// How long the strip flashes green or red once calibration is done
+const CALIBRATION_RESULT_STEPS: u64 = 2 * STEPS_PER_SECOND;
+
+/// Cyan, so it isn't mistaken for either alert or the result
+const CALIBRATION_HUE: f32 = 190.0;
+
+pub const BRIGHTNESS_LEVELS: u8 = 10;
+pub const DEFAULT_BRIGHTNESS: u8 = 3;
+
+#[cfg(feature = "slow-animation")]
+const STEPS_PER_SECOND: u64 = 30; // slow for simulation
+
+#[cfg(not(feature = "slow-animation"))]
+const STEPS_PER_SECOND: u64 = 120;
+
+#[derive(Debug, Copy, Clone, Default)]
+struct AvgPixel {
+    color: Option<Hsv>,
+}
+
+impl AvgPixel {
+    fn add(&mut self, pixel: Hsv) {
+        if let Some(color) = self.color {
+            // apply the screen blend mode to the new pixel
+            let srgb1 = Srgb::from_color(color).into_linear();
+            let srgb2 = Srgb::from_color(pixel).into_linear();
+            let blended = Srgb::from_linear(srgb1.screen(srgb2));
+            self.color = Some(Hsv::from_color(blended));
+        } else {
+            self.color = Some(pixel);
+        }
+    }
+
+    fn avg(&self) -> Hsv {
+        self.color.unwrap_or_default()
+    }
+}
+
+/// Saved colors take priority, new devices get one from the strategy
+struct ColorAllocator {
+    strategy: Box<dyn ColorStrategy>,
+    color_store: ColorStore,
+}
+
+impl ColorAllocator {
+    fn strategy(mode: &ColorMode) -> Box<dyn ColorStrategy> {
+        match mode {
+            ColorMode::Random => Box::new(RandomColors::new(MAX_DEVICES_SHOWN * 2)),
+            ColorMode::Category(hues) => Box::new(CategoryColors::new(*hues, SHADES_PER_CATEGORY)),
+        }
+    }
+
+    /// Devices seen before get their saved color back if it still fits, otherwise the strategy
+    /// picks one
+    fn allocate_color(
+        &mut self,
+        address: &BleAddress,
+        advertisement: &AdvertisementSummary,
+        rng: &mut SmallRng,
+    ) -> RgbHue {
+        if let Some(color) = self.color_store.get(address) {
+            if self.strategy.fits(color, advertisement) {
+                self.strategy.reuse(color);
+                return color;
+            }
+        }
+
+        let color = self.strategy.allocate(advertisement, rng);
+        self.color_store.insert(address, color);
+        color
+    }
+
+    /// A shown device's `color`, or a new one if it no longer fits, e.g. once the device's
+    /// category became known
+    fn refit_color(
+        &mut self,
+        address: &BleAddress,
+        advertisement: &AdvertisementSummary,
+        color: RgbHue,
+        rng: &mut SmallRng,
+    ) -> RgbHue {
+        if self.strategy.fits(color, advertisement) {
+            return color;
+        }
+        self.strategy.release(color);
+        let color = self.strategy.allocate(advertisement, rng);
+        self.color_store.insert(address, color);
+        color
+    }
+
+    fn release_color(&mut self, color: RgbHue) {
+        self.strategy.release(color);
+    }
+}
+
+struct DeviceLightState {
+    /// Signal strength, or its equivalent for the device's distance, see
+    /// [`DeviceSnapshot::proximity`](crate::ble_device_mgr::DeviceSnapshot::proximity)
+    rssi: i32,
+    color: RgbHue,
+    /// Color set by a rule rather than allocated, so it isn't released either
+    pinned: bool,
+    /// Slot randomly assigned when the device first appeared, used in [`DisplaySortMode::Sticky`]
+    sticky_slot: Option<usize>,
+    current_rank_slot: usize,
+    target_rank_slot: usize,
+    steps_remaining: u64,
+    /// Length of the current transition, fixed when it starts so config changes don't jump
+    transition_steps: u64,
+    trend: Trend,
+    /// See [`DeviceSnapshot::presence`](crate::ble_device_mgr::DeviceSnapshot::presence)
+    presence: f32,
+    /// How far the trend pixel is through its current lap of the slot, `0.0..1.0`
+    trend_phase: f32,
+}
+
+impl DeviceLightState {
+    /// Move towards a new slot, starting a transition if not already in one
+    fn set_target_slot(&mut self, slot: usize, transition_steps: u64) {
+        self.target_rank_slot = slot;
+
+        if self.steps_remaining == 0 && self.current_rank_slot != self.target_rank_slot {
+            info!("resetting steps_remaining");
+            self.start_transition(transition_steps);
+        }
+    }
+
+    fn start_transition(&mut self, transition_steps: u64) {
+        self.steps_remaining = transition_steps;
+        self.transition_steps = transition_steps;
+    }
+
+    /// When position doesn't show signal strength, brightness does
+    fn signal_brightness(&self, brightness: f32, config: &Config) -> f32 {
+        brightness
+            * utils::num_linear_conversion(
+                self.rssi as f32,
+                config.signal_ignore_below as f32,
+                config.signal_ignore_above as f32,
+                STICKY_MIN_BRIGHTNESS,
+                1.0,
+            )
+    }
+
+    fn get_target_pixel(&self) -> f32 {
+        let slot_progress = (self.steps_remaining as f32 / self.transition_steps.max(1) as f32)
+            * (self.current_rank_slot as f32 - self.target_rank_slot as f32)
+            + self.target_rank_slot as f32;
+        utils::num_linear_conversion(
+            slot_progress,
+            0.0,
+            MAX_DEVICES_SHOWN as f32 - 1.0,
+            (FAVORITE_RESERVE_LIGHTS as f32 + SLOT_WIDTH as f32 / 2.0).floor(),
+            NUM_LIGHTS as f32 - (SLOT_WIDTH as f32 / 2.0).ceil(),
+        )
+    }
+
+    /// Devices that may only be passing by are drawn washed out
+    fn saturation(&self) -> f32 {
+        PRESENCE_MIN_SATURATION + (1.0 - PRESENCE_MIN_SATURATION) * self.presence.clamp(0.0, 1.0)
+    }
+
+    /// Where the trend pixel is, `None` unless the device is clearly approaching or receding. It
+    /// runs through the slot towards the top of the strip while the device approaches and away
+    /// from it while it recedes.
+    fn trend_pixel(&self, target_pixel: f32) -> Option<f32> {
+        if self.trend.confidence < TREND_MIN_CONFIDENCE
+            || self.trend.db_per_sec.abs() < TREND_MIN_RATE
+        {
+            return None;
+        }
+        let half_width = (SLOT_WIDTH / 2) as f32;
+        let offset = half_width - self.trend_phase * 2.0 * half_width;
+        Some(if self.trend.db_per_sec > 0.0 {
+            target_pixel + offset
+        } else {
+            target_pixel - offset
+        })
+    }
+
+    /// Update pixel positions based on current position, target position, and steps remaining.
+    /// Write pixel data to light strip
+    /// TODO: might make sense to memoize this if steps_remaining is 0
+    fn tick(
+        &mut self,
+        brightness: f32,
+        fall_off_rate: f32,
+        light_strip: &mut [AvgPixel; NUM_LIGHTS],
+    ) {
+        let target_pixel = self.get_target_pixel();
+        let saturation = self.saturation();
+        // determine the brightness of each pixel in slot, mapped back to physical lights
+
+        // center
+        let left_pixel = target_pixel.floor() as usize;
+        let right_pixel = target_pixel.ceil() as usize;
+
+        let left_brightness = (1.0 - (target_pixel - target_pixel.floor())) * brightness;
+        let right_brightness = (target_pixel.ceil() - target_pixel) * brightness;
+
+        light_strip[left_pixel].add(Hsv::new(self.color, saturation, left_brightness));
+        light_strip[right_pixel].add(Hsv::new(self.color, saturation, right_brightness));
+
+        // sides
+        let mut cur_falloff = fall_off_rate;
+        let mut left_virt_pixel = target_pixel - 1.0;
+        let mut right_virt_pixel = target_pixel + 1.0;
+        for _ in 0..SLOT_WIDTH / 2 {
+            // left side
+            let left_pixel = left_virt_pixel.floor() as usize;
+            let right_pixel = left_virt_pixel.ceil() as usize;
+            let left_brightness =
+                (1.0 - (left_virt_pixel - left_virt_pixel.floor())) * brightness * cur_falloff;
+            let right_brightness =
+                (left_virt_pixel.ceil() - left_virt_pixel) * brightness * cur_falloff;
+
+            light_strip[left_pixel].add(Hsv::new(self.color, saturation, left_brightness));
+
+            light_strip[right_pixel].add(Hsv::new(self.color, saturation, right_brightness));
+
+            // right side
+            let left_pixel = right_virt_pixel.floor() as usize;
+            let right_pixel = right_virt_pixel.ceil() as usize;
+            let left_brightness =
+                (1.0 - (right_virt_pixel - right_virt_pixel.floor())) * brightness * cur_falloff;
+            let right_brightness =
+                (right_virt_pixel.ceil() - right_virt_pixel) * brightness * cur_falloff;
+
+            light_strip[left_pixel].add(Hsv::new(self.color, saturation, left_brightness));
+
+            if right_pixel < NUM_LIGHTS {
+                light_strip[right_pixel].add(Hsv::new(self.color, saturation, right_brightness));
+            }
+
+            // inc vals
+            left_virt_pixel -= 1.0;
+            right_virt_pixel += 1.0;
+            cur_falloff *= fall_off_rate;
+        }
+
+        // trend, lightening the slot as it passes
+        if let Some(trend_pixel) = self.trend_pixel(target_pixel) {
+            let brightness = brightness * TREND_BRIGHTNESS;
+            let (left_pixel, right_pixel) = (trend_pixel.floor(), trend_pixel.ceil());
+            for (pixel, coverage) in [
+                (left_pixel, 1.0 - (trend_pixel - left_pixel)),
+                (right_pixel, right_pixel - trend_pixel),
+            ] {
+                if let Some(light) = light_strip.get_mut(pixel as usize) {
+                    light.add(Hsv::new(self.color, 0.0, brightness * coverage));
+                }
+            }
+            let laps = (self.trend.db_per_sec.abs() * TREND_LAPS_PER_DB).min(TREND_MAX_LAPS);
+            self.trend_phase = (self.trend_phase + laps / STEPS_PER_SECOND as f32).fract();
+        }
+
+        // update state
+        self.steps_remaining = self.steps_remaining.saturating_sub(1);
+        if self.steps_remaining == 0 {
+            self.current_rank_slot = self.target_rank_slot;
+        }
+    }
+}
+
+struct FavoriteLightState {
+    rssi: i32,
+    color: RgbHue,
+    /// Share of the reserved lights, grows from 0 to 1 on entry and shrinks back on exit
+    presence: f32,
+    /// No longer seen by the tracker, removed once `presence` reaches 0
+    leaving: bool,
+}
+
+impl FavoriteLightState {
+    /// Advance the entry or exit animation, `step` is the change in presence per tick
+    fn animate(&mut self, step: f32) {
+        self.presence = if self.leaving {
+            (self.presence - step).max(0.0)
+        } else {
+            (self.presence + step).min(1.0)
+        };
+    }
+
+    /// Fill the segment `[start, start + width)` of the reserved lights outwards from its center,
+    /// the stronger the signal the more of it is lit
+    fn tick(
+        &self,
+        start: f32,
+        width: f32,
+        brightness: f32,
+        config: &Config,
+        light_strip: &mut [AvgPixel; NUM_LIGHTS],
+    ) {
+        let signal_strength = utils::num_linear_conversion(
+            self.rssi as f32,
+            config.favorite_signal_min as f32,
+            config.favorite_signal_max as f32,
+            0.0,
+            1.0,
+        );
+        let lit_width = width * signal_strength;
+        let center = start + width / 2.0;
+        let (low, high) = (center - lit_width / 2.0, center + lit_width / 2.0);
+
+        // partially covered lights at the edges are dimmed so segments resize smoothly
+        let end = (high.ceil() as usize).min(FAVORITE_RESERVE_LIGHTS);
+        for (idx, pixel) in light_strip
+            .iter_mut()
+            .enumerate()
+            .take(end)
+            .skip(low.floor() as usize)
+        {
+            let coverage = high.min(idx as f32 + 1.0) - low.max(idx as f32);
+            if coverage > 0.0 {
+                pixel.add(Hsv::new(self.color, 1.0, brightness * coverage));
+            }
+        }
+    }
+}
+
+/// Whether light `idx` is lit during the follow alert, `None` between alerts.
+/// Every other light is red, swapping with its neighbors so the strip looks like it is shaking.
+fn follow_alert_lit(step: u64, idx: usize) -> Option<bool> {
+    let step = step % FOLLOW_ALERT_PERIOD_STEPS;
+    if step >= FOLLOW_ALERT_STEPS {
+        return None;
+    }
+    let swaps = step * FOLLOW_ALERT_SWAPS_PER_SECOND / STEPS_PER_SECOND;
+    Some(idx % 2 == (swaps % 2) as usize)
+}
+
+/// Light `idx` of the spam warning at full brightness: an amber glow sweeping back and forth,
+/// and a white counter of blocked addresses filling in from the end of the strip
+fn spam_warning_pixel(step: u64, blocked: u32, idx: usize) -> Hsv {
+    let counter_lights =
+        (blocked.saturating_add(SPAM_WARNING_PER_LIGHT - 1) / SPAM_WARNING_PER_LIGHT) as usize;
+    let counter_lights = counter_lights.min(NUM_LIGHTS / 2);
+    if idx >= NUM_LIGHTS - counter_lights {
+        return Hsv::new(0.0, 0.0, 1.0);
+    }
+
+    let phase = step % (2 * SPAM_WARNING_SWEEP_STEPS);
+    let progress =
+        phase.min(2 * SPAM_WARNING_SWEEP_STEPS - phase) as f32 / SPAM_WARNING_SWEEP_STEPS as f32;
+    let distance = (idx as f32 - progress * (NUM_LIGHTS - 1) as f32).abs();
+    let glow = (1.0 - distance / (SPAM_WARNING_GLOW + 1) as f32).max(0.0);
+    Hsv::new(SPAM_WARNING_HUE, 1.0, glow)
+}
+
+/// Light `idx` of the non-reserved lights while calibrating, at full brightness: the number of
+/// meters to stand apart blinks while positioning, and stays lit with the progress filling in
+/// behind it while sampling
+fn calibration_pixel(state: CalibrationState, step: u64, idx: usize) -> Hsv {
+    let (meters, progress) = match state {
+        CalibrationState::Positioning { meters } => (meters, None),
+        CalibrationState::Sampling { meters, progress } => (meters, Some(progress.fraction())),
+    };
+    let lit = match progress {
+        None => step % (2 * CALIBRATION_BLINK_STEPS) < CALIBRATION_BLINK_STEPS,
+        Some(_) => true,
+    };
+    if idx < meters as usize {
+        return Hsv::new(CALIBRATION_HUE, 1.0, if lit { 1.0 } else { 0.0 });
+    }
+    let filled = progress.unwrap_or_default() * (NUM_LIGHTS - FAVORITE_RESERVE_LIGHTS) as f32;
+    Hsv::new(
+        CALIBRATION_HUE,
+        0.3,
+        if (idx as f32) < filled { 0.3 } else { 0.0 },
+    )
+}
+
+/// Pick and remove a random slot
+fn take_random_slot(
+    slots: &mut tinyvec::TinyVec<[usize; MAX_DEVICES_SHOWN]>,
+    rng: &mut SmallRng,
+) -> Option<usize> {
+    let idx = (0..slots.len()).choose(rng)?;
+    Some(slots.remove(idx))
+}
+
+pub struct LightMgr<S: PixelSink, C: Clock> {
+    pixel_sink: S,
+    clock: C,
+    mode: DisplaySortMode,
+    brightness: f32,
+    brightness_level: u8,
+    color_allocator: ColorAllocator,
+    rng: SmallRng,
+    /// Copied from the device tracker on each update
+    config: Config,
+
+    displayed_devices: BTreeMap<BleAddress, DeviceLightState>,
+    /// Keeps devices at similar signal strengths from swapping slots back and forth
+    ranking: RankStabilizer,
+    favorite_devices: BTreeMap<BleAddress, FavoriteLightState>,
+    /// Ticks since an item tracker was found following us
+    follow_alert_step: Option<u64>,
+    /// Ticks since a spam attack was detected
+    spam_warning_step: Option<u64>,
+    /// Addresses blocked so far in the current attack
+    spam_blocked: u32,
+    calibration: Option<FavoriteCalibration>,
+    /// As of the last update, drawn over the non-reserved lights
+    calibration_state: Option<CalibrationState>,
+    /// Ticks since calibration was entered, or since it finished while the result is shown
+    calibration_step: u64,
+    /// Whether the last calibration succeeded, until its result has been shown
+    calibration_shown: Option<bool>,
+    /// The favorite last heard while calibrating, the distance model is calibrated against it
+    calibration_favorite: AdvertisementSummary,
+    /// Waiting for [`Self::take_calibration_result`]
+    calibration_result: Option<Result<Config, CalibrationError>>,
+}
+
+impl<S: PixelSink, C: Clock> LightMgr<S, C> {
+    /// `seed` drives color and sticky slot choices, it should differ between boots
+    pub fn new(
+        initial_mode: DisplaySortMode,
+        color_store: ColorStore,
+        pixel_sink: S,
+        seed: u64,
+        clock: C,
+    ) -> Self {
+        let config = Config::default();
+        Self {
+            pixel_sink,
+            clock,
+            mode: initial_mode,
+            brightness: Self::get_brightness(DEFAULT_BRIGHTNESS),
+            brightness_level: DEFAULT_BRIGHTNESS,
+            color_allocator: ColorAllocator {
+                strategy: ColorAllocator::strategy(&config.color_mode),
+                color_store,
+            },
+            rng: SmallRng::seed_from_u64(seed),
+            config,
+            displayed_devices: BTreeMap::new(),
+            ranking: RankStabilizer::new(),
+            favorite_devices: BTreeMap::new(),
+            follow_alert_step: None,
+            spam_warning_step: None,
+            spam_blocked: 0,
+            calibration: None,
+            calibration_state: None,
+            calibration_step: 0,
+            calibration_shown: None,
+            calibration_favorite: AdvertisementSummary::default(),
+            calibration_result: None,
+        }
+    }
+
+    pub fn get_tick_interval(&self) -> core::time::Duration {
+        core::time::Duration::from_millis(1000 / STEPS_PER_SECOND)
+    }
+
+    /// How many ticks it takes a device to move between slots
+    fn transition_steps(&self) -> u64 {
+        (self.config.transition.as_millis() as u64 * STEPS_PER_SECOND / 1000).max(1)
+    }
+
+    /// What the tracker advertises to our favorites: brightness level and display mode
+    pub fn display_state(&self) -> (u8, DisplaySortMode) {
+        (self.brightness_level, self.mode)
+    }
+
+    /// Pick up changes from the device tracker and decide where each device should go, see
+    /// [`Self::update_from_snapshot`] for updating without locking the tracker
+    pub fn update_devices<T: Clock>(&mut self, device_manager: &mut DeviceTracker<T>) {
+        let (brightness_level, mode) = self.display_state();
+        device_manager.set_display_state(brightness_level, mode);
+        self.update_from_snapshot(&device_manager.snapshot());
+    }
+
+    /// Decide where each device should go, from a snapshot the tracker published. Kept separate
+    /// from [`Self::tick`] so ticks don't have to wait for a new one.
+    pub fn update_from_snapshot(&mut self, snapshot: &TrackerSnapshot) {
+        let now = self.clock.now();
+        // determine which devices to show
+        let mut device_rankings = tinyvec::tiny_vec!([RankCandidate; MAX_DEVICES_SHOWN * 2]);
+        // what devices are ranked by changed, so their old order means nothing
+        if (snapshot.config.rank_by_distance, snapshot.config.distance)
+            != (self.config.rank_by_distance, self.config.distance)
+        {
+            self.ranking.reset();
+        }
+        // shown devices keep their colors until they no longer fit the new strategy
+        if snapshot.config.color_mode != self.config.color_mode {
+            let mut strategy = ColorAllocator::strategy(&snapshot.config.color_mode);
+            for device in self
+                .displayed_devices
+                .values()
+                .filter(|device| !device.pinned)
+            {
+                strategy.reuse(device.color);
+            }
+            self.color_allocator.strategy = strategy;
+        }
+        self.config = snapshot.config;
+        let transition_steps = self.transition_steps();
+        self.follow_alert_step = match self.follow_alert_step {
+            _ if !snapshot.followed => None,
+            Some(step) => Some(step),
+            None => Some(0),
+        };
+        self.spam_warning_step = match self.spam_warning_step {
+            _ if snapshot.spam_blocked.is_none() => None,
+            Some(step) => Some(step),
+            None => Some(0),
+        };
+        self.spam_blocked = snapshot.spam_blocked.unwrap_or_default();
+
+        {
+            // favorites the tracker still knows about are marked as staying below
+            for fav_device in self.favorite_devices.values_mut() {
+                fav_device.leaving = true;
+            }
+
+            for device in snapshot.devices.iter() {
+                let rssi = device.interpolated_signal_strength(now, &snapshot.config);
+                if let Some(color) = device.favorite_color {
+                    if let Some(fav_device) = self.favorite_devices.get_mut(&device.address) {
+                        fav_device.rssi = rssi;
+                        fav_device.color = color;
+                        fav_device.leaving = false;
+                    } else if self.favorite_devices.len() < MAX_FAVORITES_SHOWN {
+                        info!("new favorite: addr: {}", device.address);
+                        self.favorite_devices.insert(
+                            device.address,
+                            FavoriteLightState {
+                                rssi,
+                                color,
+                                presence: 0.0,
+                                leaving: false,
+                            },
+                        );
+                    }
+                } else if device.always_shown
+                    || device.presence >= snapshot.config.presence_min
+                    || self.displayed_devices.contains_key(&device.address)
+                {
+                    // devices only have to earn their place once, so they don't flicker in and out
+                    device_rankings.push(RankCandidate {
+                        address: device.address,
+                        always_shown: device.always_shown,
+                        proximity: device.proximity(now, &snapshot.config),
+                        first_seen: device.first_seen,
+                    });
+                }
+            }
+        }
+
+        if let Some(calibration) = &mut self.calibration {
+            // the strongest favorite, in case the old one is still around
+            let favorite = snapshot
+                .devices
+                .iter()
+                .filter(|device| device.favorite_color.is_some())
+                .map(|device| {
+                    (
+                        device.interpolated_signal_strength(now, &snapshot.config),
+                        device.advertisement,
+                    )
+                })
+                .max_by_key(|(signal, _)| *signal);
+            if let Some((_, advertisement)) = favorite {
+                self.calibration_favorite = advertisement;
+            }
+            match calibration.record(favorite.map(|(signal, _)| signal), now) {
+                Some(result) => {
+                    match &result {
+                        Ok(range) => info!("Favorite calibrated to {:?}", range),
+                        Err(err) => warn!("Favorite calibration failed: {}", err),
+                    }
+                    let result = result.map(|range| {
+                        let mut config = Config {
+                            favorite_signal_min: range.min,
+                            favorite_signal_max: range.max,
+                            ..snapshot.config
+                        };
+                        // the near step held the favorite at the model's reference distance
+                        config.distance.calibrate(
+                            range.max as f32,
+                            NEAR_METERS as f32,
+                            &self.calibration_favorite,
+                        );
+                        config
+                    });
+                    self.calibration = None;
+                    self.calibration_state = None;
+                    self.calibration_step = 0;
+                    self.calibration_shown = Some(result.is_ok());
+                    self.calibration_result = Some(result);
+                }
+                None => self.calibration_state = Some(calibration.state(now)),
+            }
+        }
+
+        // devices a rule always shows rank ahead of everything else
+        let device_rankings = self.ranking.rank(&device_rankings, &self.config, now);
+
+        // for each device that is no longer tracked by the device manager, mark its target position as off the strip
+        for (dev_addr, device) in self.displayed_devices.iter_mut() {
+            if !device_rankings
+                .iter()
+                .any(|candidate| candidate.address == *dev_addr)
+                && device.target_rank_slot != MAX_DEVICES_SHOWN + 1
+            {
+                device.target_rank_slot = MAX_DEVICES_SHOWN + 1;
+                device.start_transition(transition_steps);
+            }
+        }
+
+        // sticky slots are held until a device is fully off the strip
+        let mut free_sticky_slots = tinyvec::tiny_vec!([usize; MAX_DEVICES_SHOWN]);
+        for slot in 0..MAX_DEVICES_SHOWN {
+            if !self
+                .displayed_devices
+                .values()
+                .any(|device| device.sticky_slot == Some(slot))
+            {
+                free_sticky_slots.push(slot);
+            }
+        }
+
+        // if any new devices, create a new light state for them, otherwise update
+        for (i, candidate) in device_rankings.iter().enumerate() {
+            let (address, rssi) = (candidate.address, candidate.proximity);
+            let tracked = snapshot
+                .devices
+                .iter()
+                .find(|device| device.address == address);
+            let pinned_color = tracked.and_then(|device| device.pinned_color);
+
+            let trend = tracked.map(|device| device.trend).unwrap_or_default();
+            let presence = tracked.map_or(0.0, |device| device.presence);
+            let advertisement = tracked
+                .map(|device| device.advertisement)
+                .unwrap_or_default();
+
+            if let Some(device) = self.displayed_devices.get_mut(&address) {
+                device.rssi = rssi;
+                device.trend = trend;
+                device.presence = presence;
+                // a pin that is removed lasts until the device leaves the strip
+                if let Some(color) = pinned_color {
+                    if !device.pinned {
+                        self.color_allocator.release_color(device.color);
+                        device.pinned = true;
+                    }
+                    device.color = color;
+                } else if !device.pinned {
+                    device.color = self.color_allocator.refit_color(
+                        &address,
+                        &advertisement,
+                        device.color,
+                        &mut self.rng,
+                    );
+                }
+
+                match self.mode {
+                    DisplaySortMode::Ordered => device.set_target_slot(i, transition_steps),
+                    DisplaySortMode::Sticky => {
+                        // devices that arrived in ordered mode may not have a sticky slot yet
+                        if device.sticky_slot.is_none() {
+                            device.sticky_slot =
+                                take_random_slot(&mut free_sticky_slots, &mut self.rng);
+                        }
+                        device.set_target_slot(
+                            device.sticky_slot.unwrap_or(MAX_DEVICES_SHOWN + 1),
+                            transition_steps,
+                        );
+                    }
+                }
+            } else {
+                // only insert new devices if there is room
+                let (sticky_slot, target_rank_slot) = match self.mode {
+                    DisplaySortMode::Ordered if i < MAX_DEVICES_SHOWN => {
+                        (take_random_slot(&mut free_sticky_slots, &mut self.rng), i)
+                    }
+                    DisplaySortMode::Ordered => continue,
+                    DisplaySortMode::Sticky => {
+                        match take_random_slot(&mut free_sticky_slots, &mut self.rng) {
+                            Some(slot) => (Some(slot), slot),
+                            None => continue,
+                        }
+                    }
+                };
+
+                info!("new device: addr: {}, signal: {}", address, rssi);
+                let new_device = DeviceLightState {
+                    rssi,
+                    color: pinned_color.unwrap_or_else(|| {
+                        self.color_allocator
+                            .allocate_color(&address, &advertisement, &mut self.rng)
+                    }),
+                    pinned: pinned_color.is_some(),
+                    sticky_slot,
+                    current_rank_slot: MAX_DEVICES_SHOWN + 1,
+                    target_rank_slot,
+                    steps_remaining: transition_steps,
+                    transition_steps,
+                    trend,
+                    presence,
+                    trend_phase: 0.0,
+                };
+
+                self.displayed_devices.insert(address, new_device);
+            }
+        }
+
+        self.color_allocator.color_store.flush_if_due(now);
+    }
+
+    /// Animate the lights
+    pub fn tick(&mut self) {
+        // create new light strip update
+        let mut next_light_update = [AvgPixel::default(); NUM_LIGHTS];
+
+        // update device positions
+        for (_, device) in self.displayed_devices.iter_mut() {
+            let brightness = match self.mode {
+                DisplaySortMode::Ordered => self.brightness,
+                DisplaySortMode::Sticky => device.signal_brightness(self.brightness, &self.config),
+            };
+            device.tick(
+                brightness,
+                self.config.fall_off_rate,
+                &mut next_light_update,
+            );
+        }
+
+        // update favorite device signal strength indicators, the reserved lights are split
+        // between them in proportion to how far each has animated in
+        let presence_step = 1.0 / self.transition_steps() as f32;
+        let total_presence = self
+            .favorite_devices
+            .values()
+            .map(|fav| fav.presence)
+            .sum::<f32>()
+            .max(1.0);
+        let mut segment_start = 0.0;
+        for fav in self.favorite_devices.values_mut() {
+            let segment_width = FAVORITE_RESERVE_LIGHTS as f32 * fav.presence / total_presence;
+            fav.tick(
+                segment_start,
+                segment_width,
+                self.brightness,
+                &self.config,
+                &mut next_light_update,
+            );
+            segment_start += segment_width;
+            fav.animate(presence_step);
+        }
+        self.favorite_devices
+            .retain(|_, fav| !fav.leaving || fav.presence > 0.0);
+
+        // calibration takes over all but the favorites, so the favorite can be seen being heard
+        if let Some(state) = self.calibration_state {
+            let brightness = self.brightness.max(FOLLOW_ALERT_MIN_BRIGHTNESS);
+            for (i, pixel) in next_light_update[FAVORITE_RESERVE_LIGHTS..]
+                .iter_mut()
+                .enumerate()
+            {
+                let mut color = calibration_pixel(state, self.calibration_step, i);
+                color.value *= brightness;
+                pixel.color = Some(color);
+            }
+            self.calibration_step += 1;
+        } else if let Some(succeeded) = self.calibration_shown {
+            let brightness = self.brightness.max(FOLLOW_ALERT_MIN_BRIGHTNESS);
+            let hue = if succeeded { 120.0 } else { 0.0 };
+            for pixel in next_light_update[FAVORITE_RESERVE_LIGHTS..].iter_mut() {
+                pixel.color = Some(Hsv::new(hue, 1.0, brightness));
+            }
+            self.calibration_step += 1;
+            if self.calibration_step >= CALIBRATION_RESULT_STEPS {
+                self.calibration_shown = None;
+            }
+        }
+
+        // the spam warning takes over the whole strip for as long as the attack lasts
+        if let Some(step) = self.spam_warning_step {
+            let brightness = self.brightness.max(FOLLOW_ALERT_MIN_BRIGHTNESS);
+            for (i, pixel) in next_light_update.iter_mut().enumerate() {
+                let mut color = spam_warning_pixel(step, self.spam_blocked, i);
+                color.value *= brightness;
+                pixel.color = Some(color);
+            }
+            self.spam_warning_step = Some(step + 1);
+        }
+
+        // the follow alert takes over the whole strip while it plays, even during a spam attack
+        if let Some(step) = self.follow_alert_step {
+            let brightness = self.brightness.max(FOLLOW_ALERT_MIN_BRIGHTNESS);
+            for (i, pixel) in next_light_update.iter_mut().enumerate() {
+                if let Some(lit) = follow_alert_lit(step, i) {
+                    pixel.color = Some(Hsv::new(0.0, 1.0, if lit { brightness } else { 0.0 }));
+                }
+            }
+            self.follow_alert_step = Some(step + 1);
+        }
+
+        // write light strip update
+        // TODO: gamma correct
+        let mut frame = [Color::default(); NUM_LIGHTS];
+        for (pixel, avg_pixel) in frame.iter_mut().zip(next_light_update.iter()) {
+            let rgb = Srgb::from_color(avg_pixel.avg());
+            let r = utils::num_linear_conversion(rgb.red, 0.0, 1.0, 0.0, 255.0) as u8;
+            let g = utils::num_linear_conversion(rgb.green, 0.0, 1.0, 0.0, 255.0) as u8;
+            let b = utils::num_linear_conversion(rgb.blue, 0.0, 1.0, 0.0, 255.0) as u8;
+            *pixel = Color::new(r, g, b);
+        }
+        self.pixel_sink.update(&frame).unwrap();
+
+        // remove devices that are off the strip
+        self.displayed_devices.retain(|_, device| {
+            if device.current_rank_slot > MAX_DEVICES_SHOWN
+                && device.target_rank_slot > MAX_DEVICES_SHOWN
+            {
+                if !device.pinned {
+                    self.color_allocator.release_color(device.color);
+                }
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn get_brightness(level: u8) -> f32 {
+        0.714_f32.powf((BRIGHTNESS_LEVELS - level) as f32)
+    }
+
+    pub fn increase_brightness(&mut self) {
+        self.brightness_level = (self.brightness_level + 1).min(BRIGHTNESS_LEVELS);
+        info!("Brightness increased to level: {}", self.brightness_level);
+        self.brightness = Self::get_brightness(self.brightness_level);
+    }
+
+    pub fn decrease_brightness(&mut self) {
+        self.brightness_level = (self.brightness_level - 1).max(1);
+        info!("Brightness decreased to level: {}", self.brightness_level);
+        self.brightness = Self::get_brightness(self.brightness_level);
+    }
+
+    /// `level` is clamped to `1..=BRIGHTNESS_LEVELS`
+    pub fn set_brightness_level(&mut self, level: u8) {
+        self.brightness_level = level.clamp(1, BRIGHTNESS_LEVELS);
+        info!("Brightness set to level: {}", self.brightness_level);
+        self.brightness = Self::get_brightness(self.brightness_level);
+    }
+
+    /// Start calibrating the favorite signal range, or start over if already calibrating
+    pub fn start_calibration(&mut self) {
+        info!("Favorite calibration started");
+        self.calibration = Some(FavoriteCalibration::new());
+        self.calibration_step = 0;
+        self.calibration_shown = None;
+        self.calibration_result = None;
+    }
+
+    pub fn is_calibrating(&self) -> bool {
+        self.calibration.is_some()
+    }
+
+    /// The user is in position for the current calibration step
+    pub fn confirm_calibration_step(&mut self) {
+        if let Some(calibration) = &mut self.calibration {
+            calibration.confirm(self.clock.now());
+        }
+    }
+
+    pub fn cancel_calibration(&mut self) {
+        info!("Favorite calibration cancelled");
+        self.calibration = None;
+        self.calibration_state = None;
+    }
+
+    /// The outcome of the last calibration, once. On success, the config with the favorite signal
+    /// range and distance model calibrated, for the caller to apply and save.
+    pub fn take_calibration_result(&mut self) -> Option<Result<Config, CalibrationError>> {
+        self.calibration_result.take()
+    }
+
+    /// Devices animate to their new slots on the next tick
+    pub fn switch_mode(&mut self, new_mode: DisplaySortMode) {
+        info!("Display mode switched to: {:?}", new_mode);
+        self.mode = new_mode;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        address::AddressType,
+        ble_device_mgr::{FavoriteId, Pairing},
+        color_store::MemoryBackend,
+        favorite_payload::FavoritePayload,
+        pixel_sink::FrameRecorder,
+        time::MockClock,
+    };
+
+    /// Transition length with the default config
+    const NUM_TRANSITIONAL_STEPS: u64 = 3 * STEPS_PER_SECOND;
+
+    #[test]
+    fn device_light_state_tick() {
+        let mut device = DeviceLightState {
+            rssi: 0,
+            color: super::RgbHue::from_degrees(0.0),
+            pinned: false,
+            sticky_slot: None,
+            current_rank_slot: 10,
+            target_rank_slot: 0,
+            steps_remaining: NUM_TRANSITIONAL_STEPS / 3,
+            transition_steps: NUM_TRANSITIONAL_STEPS,
+            trend: Trend::default(),
+            presence: 1.0,
+            trend_phase: 0.0,
+        };
+
+        // Slots map onto lights 12 to 57, 5 lights apart. A third of the way back from slot 10 to
+        // slot 0 is slot 3.33, so light 12 + 3.33 * 5. Checked both ways, a one sided check would
+        // pass for any pixel further down the strip.
+        info!("{:?}", device.get_target_pixel());
+        assert!((device.get_target_pixel() - 28.67).abs() < 0.1);
+
+        device.current_rank_slot = 0;
+        device.target_rank_slot = 10;
+
+        // slot 6.67, so light 12 + 6.67 * 5
+        info!("{:?}", device.get_target_pixel());
+        assert!((device.get_target_pixel() - 45.33).abs() < 0.1);
+    }
+
+    #[test]
+    fn device_light_state_retarget() {
+        let config = Config::default();
+        let mut device = DeviceLightState {
+            rssi: config.signal_ignore_below,
+            color: super::RgbHue::from_degrees(0.0),
+            pinned: false,
+            sticky_slot: Some(7),
+            current_rank_slot: 2,
+            target_rank_slot: 2,
+            steps_remaining: 0,
+            transition_steps: 0,
+            trend: Trend::default(),
+            presence: 1.0,
+            trend_phase: 0.0,
+        };
+
+        // switching to sticky moves the device to its assigned slot
+        device.set_target_slot(device.sticky_slot.unwrap(), NUM_TRANSITIONAL_STEPS);
+        assert_eq!(device.target_rank_slot, 7);
+        assert_eq!(device.steps_remaining, NUM_TRANSITIONAL_STEPS);
+
+        // weakest signal is dimmest, strongest is full brightness
+        assert!((device.signal_brightness(1.0, &config) - STICKY_MIN_BRIGHTNESS).abs() < 0.001);
+        device.rssi = config.signal_ignore_above;
+        assert!((device.signal_brightness(1.0, &config) - 1.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn trend_pixel_points_the_way() {
+        let mut device = DeviceLightState {
+            rssi: -60,
+            color: super::RgbHue::from_degrees(0.0),
+            pinned: false,
+            sticky_slot: None,
+            current_rank_slot: 4,
+            target_rank_slot: 4,
+            steps_remaining: 0,
+            transition_steps: 0,
+            trend: Trend {
+                db_per_sec: 4.0,
+                confidence: 0.9,
+            },
+            presence: 1.0,
+            trend_phase: 0.0,
+        };
+        let center = device.get_target_pixel();
+        let mut strip = [AvgPixel::default(); NUM_LIGHTS];
+
+        // approaching, runs towards the top of the strip, one lap a second
+        let start = device.trend_pixel(center).unwrap();
+        assert!(start > center);
+        device.tick(1.0, 0.5, &mut strip);
+        let next = device.trend_pixel(center).unwrap();
+        assert!(next < start);
+        for _ in 1..STEPS_PER_SECOND {
+            device.tick(1.0, 0.5, &mut strip);
+        }
+        assert!((device.trend_phase - device.trend_phase.round()).abs() < 0.01);
+
+        // receding, the other way
+        device.trend.db_per_sec = -4.0;
+        device.trend_phase = 0.0;
+        let start = device.trend_pixel(center).unwrap();
+        assert!(start < center);
+        device.tick(1.0, 0.5, &mut strip);
+        assert!(device.trend_pixel(center).unwrap() > start);
+
+        // not shown when unsure or barely moving
+        device.trend.confidence = 0.2;
+        assert_eq!(device.trend_pixel(center), None);
+        device.trend = Trend {
+            db_per_sec: 0.5,
+            confidence: 1.0,
+        };
+        assert_eq!(device.trend_pixel(center), None);
+    }
+
+    /// Shares its clock with the device tracker from [`setup`]
+    type TestLightMgr = LightMgr<FrameRecorder, MockClock>;
+
+    /// Advertise each device every 300ms for 3s, long enough to be trusted as present
+    fn setup(mode: DisplaySortMode) -> (DeviceTracker<MockClock>, TestLightMgr) {
+        let clock = MockClock::new();
+        let device_manager = DeviceTracker::new(
+            Pairing {
+                id: FavoriteId::new("PARTY_TIME").unwrap(),
+                hue: 0,
+            },
+            Config::default(),
+            clock.clone(),
+        );
+        let light_mgr = LightMgr::new(
+            mode,
+            ColorStore::new(Box::<MemoryBackend>::default(), 8),
+            FrameRecorder::default(),
+            0,
+            clock,
+        );
+        (device_manager, light_mgr)
+    }
+
+    fn stay_around(
+        device_manager: &mut DeviceTracker<MockClock>,
+        devices: &[(BleAddress, &[u8], i32)],
+    ) {
+        for _ in 0..10 {
+            device_manager
+                .clock()
+                .advance(core::time::Duration::from_millis(300));
+            for &(address, advertisement, rssi) in devices {
+                device_manager.update(address, AddressType::Random, advertisement, rssi);
+            }
+        }
+    }
+
+    fn run_ticks(
+        light_mgr: &mut TestLightMgr,
+        device_manager: &mut DeviceTracker<MockClock>,
+        ticks: u64,
+    ) {
+        for _ in 0..ticks {
+            device_manager
+                .clock()
+                .advance(light_mgr.get_tick_interval());
+            light_mgr.update_devices(device_manager);
+            light_mgr.tick();
+        }
+    }
+
+    #[test]
+    fn rotated_address_keeps_color_and_slot() {
+        use crate::advertisement::tests::APPLE_NEARBY_INFO;
+
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Sticky);
+
+        // a phone advertising every ~300ms
+        let phone = BleAddress([0x5A, 0x10, 0x20, 0x30, 0x40, 0x50]);
+        for _ in 0..5 {
+            device_manager.update(phone, AddressType::Random, APPLE_NEARBY_INFO, -60);
+            run_ticks(&mut light_mgr, &mut device_manager, 38);
+        }
+        let (color, sticky_slot) = {
+            let device = &light_mgr.displayed_devices[&phone];
+            (device.color, device.sticky_slot)
+        };
+
+        // skips an advertisement, then comes back with a new address
+        run_ticks(&mut light_mgr, &mut device_manager, 38);
+        let rotated = BleAddress([0x6B, 0x11, 0x21, 0x31, 0x41, 0x51]);
+        device_manager.update(rotated, AddressType::Random, APPLE_NEARBY_INFO, -62);
+        run_ticks(&mut light_mgr, &mut device_manager, 38);
+
+        assert_eq!(device_manager.devices.len(), 1);
+        assert_eq!(device_manager.devices[0].current_address(), rotated);
+        assert_eq!(light_mgr.displayed_devices.len(), 1);
+        let device = &light_mgr.displayed_devices[&phone];
+        assert_eq!(device.color, color);
+        assert_eq!(device.sticky_slot, sticky_slot);
+    }
+
+    #[test]
+    fn passers_by_wait_their_turn() {
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        let friend = BleAddress([0x30, 0, 0, 0, 0, 0]);
+        let passer_by = BleAddress([0x31, 0, 0, 0, 0, 0]);
+
+        // heard once, however strongly, isn't shown yet
+        device_manager.update(passer_by, AddressType::Random, &[], -50);
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        assert!(light_mgr.displayed_devices.is_empty());
+
+        stay_around(&mut device_manager, &[(friend, &[], -70)]);
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        assert!(light_mgr.displayed_devices.contains_key(&friend));
+        assert!(!light_mgr.displayed_devices.contains_key(&passer_by));
+
+        // the longer a device stays, the more saturated it is drawn
+        let saturation = light_mgr.displayed_devices[&friend].saturation();
+        stay_around(&mut device_manager, &[(friend, &[], -70)]);
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        assert!(light_mgr.displayed_devices[&friend].saturation() > saturation);
+    }
+
+    #[test]
+    fn rules_pin_colors_and_rank_first() {
+        use crate::rules::{Rule, RuleAction, RuleMatch, Rules};
+
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        let addr = |i: u8| BleAddress([0x10, i, 0x02, 0x03, 0x04, 0x05]);
+        let mut rules = Rules::new();
+        for (i, action) in [(0, RuleAction::AlwaysShow), (1, RuleAction::PinColor(200))] {
+            let matcher = RuleMatch::Address(addr(i));
+            rules.push(Rule { matcher, action }).unwrap();
+        }
+        device_manager.set_rules(rules);
+
+        stay_around(
+            &mut device_manager,
+            &[
+                (addr(0), &[], -78),
+                (addr(1), &[], -60),
+                (addr(2), &[], -50),
+            ],
+        );
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+
+        let slot = |i| light_mgr.displayed_devices[&addr(i)].target_rank_slot;
+        assert_eq!((slot(0), slot(2), slot(1)), (0, 1, 2));
+        let pinned = &light_mgr.displayed_devices[&addr(1)];
+        assert!(pinned.pinned);
+        assert_eq!(pinned.color, RgbHue::from_degrees(200.0));
+    }
+
+    #[test]
+    fn colors_follow_category() {
+        use crate::{
+            advertisement::tests::FAST_PAIR,
+            color_strategy::{CategoryHues, HueFamily},
+        };
+
+        let hues = CategoryHues::default();
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        device_manager.set_config(Config {
+            color_mode: ColorMode::Category(hues),
+            ..Default::default()
+        });
+        let addr = BleAddress([0x30, 0, 0, 0, 0, 0]);
+        let in_family = |color: RgbHue, family: HueFamily| {
+            (color - RgbHue::from_degrees(family.center))
+                .to_degrees()
+                .abs()
+                <= family.spread / 2.0 + 0.01
+        };
+
+        // nothing to tell the category by until the scan response
+        stay_around(&mut device_manager, &[(addr, &[], -60)]);
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        let color = light_mgr.displayed_devices[&addr].color;
+        assert!(in_family(color, hues.unknown));
+
+        device_manager.update(addr, AddressType::Random, FAST_PAIR, -60);
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        let color = light_mgr.displayed_devices[&addr].color;
+        assert!(in_family(color, hues.audio));
+        assert_eq!(
+            light_mgr.color_allocator.color_store.get(&addr),
+            Some(color)
+        );
+
+        // switching strategy recolors shown devices
+        let mut config = *device_manager.config();
+        config.color_mode = ColorMode::Random;
+        device_manager.set_config(config);
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        let color = light_mgr.displayed_devices[&addr].color;
+        assert!(RandomColors::new(MAX_DEVICES_SHOWN * 2).fits(color, &Default::default()));
+    }
+
+    #[test]
+    fn ranks_by_distance() {
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        // a loud speaker across the room and a quiet wearable next to us, by advertised TX power
+        let speaker = BleAddress([0x20, 0, 0, 0, 0, 0]);
+        let wearable = BleAddress([0x21, 0, 0, 0, 0, 0]);
+        stay_around(
+            &mut device_manager,
+            &[
+                (speaker, &[2, 0x0a, 8], -60),
+                (wearable, &[2, 0x0a, -20_i8 as u8], -65),
+            ],
+        );
+
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        let slot = |light_mgr: &TestLightMgr, address| {
+            light_mgr.displayed_devices[&address].target_rank_slot
+        };
+        assert_eq!(slot(&light_mgr, speaker), 0);
+
+        device_manager.set_config(Config {
+            rank_by_distance: true,
+            ..Config::default()
+        });
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        assert_eq!(slot(&light_mgr, wearable), 0);
+        assert_eq!(slot(&light_mgr, speaker), 1);
+    }
+
+    #[test]
+    fn light_mgr_runs_off_target() {
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        let devices: [(BleAddress, &[u8], i32); 3] = core::array::from_fn(|i| {
+            let address = BleAddress([i as u8, 0x22, 0x33, 0x44, 0x55, 0x66]);
+            (address, &[][..], -50 - i as i32 * 5)
+        });
+        stay_around(&mut device_manager, &devices);
+
+        run_ticks(
+            &mut light_mgr,
+            &mut device_manager,
+            NUM_TRANSITIONAL_STEPS + 1,
+        );
+        assert_eq!(light_mgr.displayed_devices.len(), 3);
+        assert!(light_mgr.displayed_devices.values().all(|device| {
+            device.steps_remaining == 0 && device.current_rank_slot == device.target_rank_slot
+        }));
+
+        // all devices move to their sticky slots
+        light_mgr.switch_mode(DisplaySortMode::Sticky);
+        run_ticks(
+            &mut light_mgr,
+            &mut device_manager,
+            NUM_TRANSITIONAL_STEPS * 2 + 1,
+        );
+        assert!(light_mgr
+            .displayed_devices
+            .values()
+            .all(|device| Some(device.current_rank_slot) == device.sticky_slot));
+
+        // once the tracker forgets them, devices animate off the strip
+        device_manager.devices.clear();
+        run_ticks(
+            &mut light_mgr,
+            &mut device_manager,
+            NUM_TRANSITIONAL_STEPS * 2 + 1,
+        );
+        assert!(light_mgr.displayed_devices.is_empty());
+
+        let recorder = &light_mgr.pixel_sink;
+        assert_eq!(recorder.frames.len() as u64, NUM_TRANSITIONAL_STEPS * 5 + 3);
+        assert!(recorder.frames[NUM_TRANSITIONAL_STEPS as usize]
+            .iter()
+            .any(|pixel| !pixel.is_off()));
+        assert!(recorder.last_frame().unwrap().iter().all(Color::is_off));
+    }
+
+    #[test]
+    fn follow_alert_takes_over_strip() {
+        use crate::advertisement::tests::AIRTAG_SEPARATED;
+
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        device_manager.set_config(Config {
+            follow_duration: core::time::Duration::from_secs(1),
+            ..Default::default()
+        });
+
+        let airtag = BleAddress([0x4E, 0x01, 0x02, 0x03, 0x04, 0x05]);
+        for _ in 0..4 {
+            device_manager.update(airtag, AddressType::Random, AIRTAG_SEPARATED, -50);
+            assert!(light_mgr.follow_alert_step.is_none());
+            run_ticks(&mut light_mgr, &mut device_manager, 60);
+        }
+        assert!(light_mgr.follow_alert_step.is_some());
+
+        // every other light is red while the alert plays, then it waits for the next period
+        light_mgr.follow_alert_step = Some(0);
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        let frame = light_mgr.pixel_sink.last_frame().unwrap();
+        for (i, pixel) in frame.iter().enumerate() {
+            let red = pixel.r > 0 && pixel.g == 0 && pixel.b == 0;
+            assert_eq!(red, i % 2 == 0);
+            assert_eq!(pixel.is_off(), i % 2 == 1);
+        }
+        assert_eq!(follow_alert_lit(FOLLOW_ALERT_STEPS, 0), None);
+        assert_eq!(follow_alert_lit(FOLLOW_ALERT_PERIOD_STEPS, 0), Some(true));
+        assert_eq!(
+            follow_alert_lit(STEPS_PER_SECOND / FOLLOW_ALERT_SWAPS_PER_SECOND, 0),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn spam_warning_takes_over_strip() {
+        use crate::advertisement::tests::{APPLE_NEARBY_INFO, FAST_PAIR};
+
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        let addr = |i: u8| BleAddress([0x10, i, 0x02, 0x03, 0x04, 0x05]);
+
+        stay_around(&mut device_manager, &[(addr(0), APPLE_NEARBY_INFO, -60)]);
+        run_ticks(&mut light_mgr, &mut device_manager, STEPS_PER_SECOND * 10);
+        assert!(light_mgr.spam_warning_step.is_none());
+
+        // a new Fast Pair popup every 100ms
+        for i in 1..=20 {
+            device_manager.update(addr(i), AddressType::Random, FAST_PAIR, -60);
+            run_ticks(&mut light_mgr, &mut device_manager, STEPS_PER_SECOND / 10);
+        }
+        assert!(light_mgr.spam_warning_step.is_some());
+        assert_eq!(light_mgr.displayed_devices.len(), 1);
+
+        // nothing but amber and the white counter of 20 blocked addresses
+        let frame = light_mgr.pixel_sink.last_frame().unwrap();
+        for (i, pixel) in frame.iter().enumerate() {
+            if i >= NUM_LIGHTS - 4 {
+                assert!(pixel.r == pixel.g && pixel.g == pixel.b && pixel.r > 0);
+            } else {
+                assert!(pixel.is_off() || (pixel.r > pixel.g && pixel.b == 0));
+            }
+        }
+        assert!(frame.iter().any(|pixel| pixel.r > pixel.g && pixel.g > 0));
+
+        // the glow sweeps to the far end and back
+        let brightest = |step| {
+            (0..NUM_LIGHTS)
+                .max_by(|&a, &b| {
+                    let value = |idx| spam_warning_pixel(step, 0, idx).value;
+                    value(a).total_cmp(&value(b))
+                })
+                .unwrap()
+        };
+        assert_eq!(brightest(0), 0);
+        assert_eq!(brightest(SPAM_WARNING_SWEEP_STEPS), NUM_LIGHTS - 1);
+        assert_eq!(brightest(2 * SPAM_WARNING_SWEEP_STEPS), 0);
+        assert_eq!(
+            spam_warning_pixel(0, 10_000, NUM_LIGHTS / 2 - 1),
+            Hsv::new(SPAM_WARNING_HUE, 1.0, 0.0)
+        );
+
+        // back to normal once it has been quiet for a while
+        run_ticks(&mut light_mgr, &mut device_manager, STEPS_PER_SECOND * 12);
+        device_manager.expire();
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        assert!(light_mgr.spam_warning_step.is_none());
+    }
+
+    #[test]
+    fn favorites_share_reserved_lights() {
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        let red_addr = BleAddress([1, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let blue_addr = BleAddress([2, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let red = FavoritePayload {
+            hue: 0,
+            ..device_manager.advertisement()
+        };
+        let blue = FavoritePayload {
+            hue: 240,
+            ..device_manager.advertisement()
+        };
+        let full_signal = device_manager.config().favorite_signal_max;
+        device_manager.update(
+            red_addr,
+            AddressType::Random,
+            &red.to_advertisement(),
+            full_signal,
+        );
+        device_manager.update(
+            blue_addr,
+            AddressType::Random,
+            &blue.to_advertisement(),
+            full_signal,
+        );
+
+        let is_red = |pixel: &Color| pixel.r > 0 && pixel.b == 0;
+        let is_blue = |pixel: &Color| pixel.b > 0 && pixel.r == 0;
+
+        // each favorite animates in to half of the reserved lights
+        run_ticks(
+            &mut light_mgr,
+            &mut device_manager,
+            NUM_TRANSITIONAL_STEPS + 2,
+        );
+        let frame = light_mgr.pixel_sink.last_frame().unwrap();
+        assert!(frame[..FAVORITE_RESERVE_LIGHTS / 2].iter().all(is_red));
+        assert!(frame[FAVORITE_RESERVE_LIGHTS / 2..FAVORITE_RESERVE_LIGHTS]
+            .iter()
+            .all(is_blue));
+
+        // when one leaves, the other grows into its space, red keeps advertising so it doesn't decay
+        device_manager.devices.retain(|d| d.address != blue_addr);
+        device_manager.update(
+            red_addr,
+            AddressType::Random,
+            &red.to_advertisement(),
+            full_signal,
+        );
+        run_ticks(
+            &mut light_mgr,
+            &mut device_manager,
+            NUM_TRANSITIONAL_STEPS / 2,
+        );
+        let frame = light_mgr.pixel_sink.last_frame().unwrap();
+        let red_lights = frame.iter().filter(|pixel| is_red(pixel)).count();
+        assert!(red_lights > FAVORITE_RESERVE_LIGHTS / 2);
+        assert!(frame[..FAVORITE_RESERVE_LIGHTS].iter().any(is_blue));
+
+        run_ticks(
+            &mut light_mgr,
+            &mut device_manager,
+            NUM_TRANSITIONAL_STEPS / 2 + 2,
+        );
+        assert_eq!(light_mgr.favorite_devices.len(), 1);
+        let frame = light_mgr.pixel_sink.last_frame().unwrap();
+        assert!(frame[..FAVORITE_RESERVE_LIGHTS].iter().all(is_red));
+    }
+
+    #[test]
+    fn calibrates_favorite() {
+        let (mut device_manager, mut light_mgr) = setup(DisplaySortMode::Ordered);
+        let fav_addr = BleAddress([1, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        let fav = device_manager.advertisement().to_advertisement();
+        let is_cyan = |pixel: &Color| pixel.b > 0 && pixel.g > 0 && pixel.r == 0;
+
+        light_mgr.start_calibration();
+        let step = |light_mgr: &mut TestLightMgr,
+                    device_manager: &mut DeviceTracker<MockClock>,
+                    signal: i32| {
+            light_mgr.confirm_calibration_step();
+            // advertising every 250ms, for a little longer than the step
+            for _ in 0..45 {
+                device_manager.update(fav_addr, AddressType::Random, &fav, signal);
+                run_ticks(light_mgr, device_manager, STEPS_PER_SECOND / 4);
+            }
+        };
+
+        // the one light for 1 m blinks until confirmed
+        run_ticks(&mut light_mgr, &mut device_manager, 1);
+        let frame = light_mgr.pixel_sink.last_frame().unwrap();
+        assert!(is_cyan(&frame[FAVORITE_RESERVE_LIGHTS]));
+        assert_eq!(frame[FAVORITE_RESERVE_LIGHTS + 1], Color::default());
+
+        step(&mut light_mgr, &mut device_manager, -50);
+        assert!(light_mgr.is_calibrating());
+        assert_eq!(light_mgr.take_calibration_result(), None);
+        step(&mut light_mgr, &mut device_manager, -66);
+        assert!(!light_mgr.is_calibrating());
+        let config = light_mgr.take_calibration_result().unwrap().unwrap();
+        assert!((-52..=-50).contains(&config.favorite_signal_max));
+        assert!((-66..=-64).contains(&config.favorite_signal_min));
+        assert_eq!(light_mgr.take_calibration_result(), None);
+
+        // the favorite was 1 m away for the first step
+        let distance = config.distance.estimate(
+            config.favorite_signal_max as f32,
+            0.0,
+            &AdvertisementSummary::from_payload(&fav),
+        );
+        assert!((distance.meters - 1.0).abs() < 0.01);
+        assert_ne!(config.distance, Config::default().distance);
+    }
+}
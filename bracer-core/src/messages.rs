@@ -0,0 +1,33 @@
+use crate::{ble_device_mgr::FavoriteId, config::Config, rules::Rule};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightControls {
+    BrightnessIncrease,
+    BrightnessDecrease,
+    ModeChange(DisplaySortMode),
+    /// Level in `1..=BRIGHTNESS_LEVELS`
+    BrightnessChange(u8),
+    SignalThresholdsChange {
+        ignore_below: i8,
+        ignore_above: i8,
+    },
+    /// Replace the whole tracker and display config, already validated
+    ConfigChange(Config),
+    FavoriteIdChange(FavoriteId),
+    /// Hue in degrees the favorite device should draw us with
+    FavoriteHueChange(u16),
+    /// Add a rule after the existing ones
+    RuleAdd(Rule),
+    /// Remove the rule at this index
+    RuleRemove(u8),
+    RulesClear,
+    /// Walk through calibrating the favorite signal range, see
+    /// [`FavoriteCalibration`](crate::calibration::FavoriteCalibration)
+    CalibrationStart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DisplaySortMode {
+    Sticky,
+    Ordered,
+}
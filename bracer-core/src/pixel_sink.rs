@@ -0,0 +1,60 @@
+use alloc::vec::Vec;
+
+use palette::{FromColor, Hsv, Srgb};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub fn new(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b }
+    }
+
+    pub fn is_off(&self) -> bool {
+        *self == Color::default()
+    }
+}
+
+impl From<Hsv> for Color {
+    fn from(hsv: Hsv) -> Self {
+        let rgb = Srgb::from_color(hsv);
+
+        Color {
+            r: (rgb.red * 255.0) as u8,
+            g: (rgb.green * 255.0) as u8,
+            b: (rgb.blue * 255.0) as u8,
+        }
+    }
+}
+
+/// Anything that can display a frame of pixels, one color per light
+pub trait PixelSink {
+    type Error: core::fmt::Debug;
+
+    fn update(&mut self, colors: &[Color]) -> Result<(), Self::Error>;
+}
+
+/// Keeps every frame in memory instead of lighting anything, so animations can run off target
+#[derive(Debug, Default)]
+pub struct FrameRecorder {
+    pub frames: Vec<Vec<Color>>,
+}
+
+impl FrameRecorder {
+    pub fn last_frame(&self) -> Option<&[Color]> {
+        self.frames.last().map(|frame| frame.as_slice())
+    }
+}
+
+impl PixelSink for FrameRecorder {
+    type Error = core::convert::Infallible;
+
+    fn update(&mut self, colors: &[Color]) -> Result<(), Self::Error> {
+        self.frames.push(colors.to_vec());
+        Ok(())
+    }
+}
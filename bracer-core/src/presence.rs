@@ -0,0 +1,154 @@
+//! How sure we are that a device is really around us rather than passing by, from how often it has
+//! been heard, how regularly it advertises and how steady its signal strength is
+
+use core::time::Duration;
+
+#[cfg(not(test))]
+use num::Float;
+
+use crate::time::Instant;
+
+/// Advertisements closer together than this are the scan response to the one before, not a new
+/// advertisement
+const SCAN_RESPONSE_GAP: Duration = Duration::from_millis(20);
+
+/// Weight of each new gap and signal strength in the running averages
+const SMOOTHING: f32 = 0.2;
+
+/// Every this many advertisements heard cover about two thirds of the remaining doubt
+const COUNT_SCALE: f32 = 5.0;
+
+/// Devices advertising at least this often are as regular as they come
+const REGULAR_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Most a slow advertising interval alone can take off, so a beacon that advertises every few
+/// seconds from the same spot still shows up. Its own interval decides when it has gone quiet.
+const MIN_REGULAR: f32 = 0.5;
+
+/// Signal strength variance, in dB², that halves confidence. Assumed for new devices until their
+/// own is known.
+const STEADY_VARIANCE: f32 = 36.0;
+
+/// Advertisement count, interval and signal strength spread of a device
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PresenceStats {
+    count: u32,
+    /// Running average of the time between advertisements, in seconds, `None` until the second
+    interval: Option<f32>,
+    mean: f32,
+    variance: f32,
+    last_heard: Instant,
+}
+
+impl PresenceStats {
+    pub fn new(signal: i32, now: Instant) -> Self {
+        Self {
+            count: 1,
+            interval: None,
+            mean: signal as f32,
+            variance: STEADY_VARIANCE,
+            last_heard: now,
+        }
+    }
+
+    pub fn record(&mut self, signal: i32, now: Instant) {
+        let gap = now.duration_since(self.last_heard);
+        if gap < SCAN_RESPONSE_GAP {
+            return;
+        }
+        self.count = self.count.saturating_add(1);
+        let gap = gap.as_secs_f32();
+        self.interval = Some(match self.interval {
+            Some(interval) => interval + SMOOTHING * (gap - interval),
+            None => gap,
+        });
+        let diff = signal as f32 - self.mean;
+        self.mean += SMOOTHING * diff;
+        self.variance = (1.0 - SMOOTHING) * (self.variance + SMOOTHING * diff * diff);
+        self.last_heard = now;
+    }
+
+    /// Advertisements heard, not counting scan responses
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Estimated time between advertisements, `None` until heard twice
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval.map(Duration::from_secs_f32)
+    }
+
+    /// Spread of the signal strength, in dB²
+    pub fn variance(&self) -> f32 {
+        self.variance
+    }
+
+    /// In `0.0..=1.0`, grows as the device keeps being heard regularly with a steady signal
+    /// strength, and falls as it goes quiet for longer than it usually does
+    pub fn confidence(&self, now: Instant) -> f32 {
+        let heard = 1.0 - (-(self.count as f32) / COUNT_SCALE).exp();
+        let interval = self.interval.unwrap_or_default();
+        let regular = (REGULAR_INTERVAL.as_secs_f32() / interval).clamp(MIN_REGULAR, 1.0);
+        // quiet for longer than it usually is, whether that is fast or slow
+        let quiet = now.duration_since(self.last_heard).as_secs_f32();
+        let overdue = (interval.max(REGULAR_INTERVAL.as_secs_f32()) / quiet).min(1.0);
+        let steady = STEADY_VARIANCE / (STEADY_VARIANCE + self.variance);
+        heard * regular * overdue * steady
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Heard `count` times, `every` apart, from `signal(i)`
+    fn heard(count: u32, every: Duration, signal: impl Fn(u32) -> i32) -> (PresenceStats, Instant) {
+        let mut now = Instant::default();
+        let mut stats = PresenceStats::new(signal(0), now);
+        for i in 1..count {
+            now += every;
+            stats.record(signal(i), now);
+        }
+        (stats, now)
+    }
+
+    #[test]
+    fn staying_beats_passing_by() {
+        let (fly_by, now) = heard(1, Duration::ZERO, |_| -79);
+        assert!(fly_by.confidence(now) < 0.15);
+
+        let (standing, now) = heard(50, Duration::from_millis(300), |i| -70 + (i % 3) as i32);
+        assert_eq!(standing.count(), 50);
+        assert!((standing.interval().unwrap().as_secs_f32() - 0.3).abs() < 0.01);
+        assert!(standing.variance() < 2.0);
+        assert!(standing.confidence(now) > 0.9);
+
+        // fades once it goes quiet for longer than it should
+        assert!(standing.confidence(now + Duration::from_secs(10)) < 0.3);
+    }
+
+    #[test]
+    fn slow_but_steady_stays() {
+        // a beacon advertising every 8s from the same spot
+        let (beacon, now) = heard(50, Duration::from_secs(8), |_| -70);
+        assert!(beacon.confidence(now) >= 0.45);
+        assert!(beacon.confidence(now + Duration::from_secs(7)) >= 0.45);
+
+        // but not once it misses a few
+        assert!(beacon.confidence(now + Duration::from_secs(30)) < 0.15);
+    }
+
+    #[test]
+    fn irregular_or_unsteady_is_doubted() {
+        let (unsteady, now) = heard(50, Duration::from_millis(300), |i| {
+            -70 + 15 * (i % 2) as i32
+        });
+        assert!(unsteady.confidence(now) < 0.5);
+
+        // scan responses aren't counted
+        let (stats, now) = heard(3, Duration::from_millis(300), |_| -70);
+        let mut with_response = stats;
+        with_response.record(-70, now + Duration::from_millis(5));
+        assert_eq!(with_response, stats);
+    }
+}
@@ -0,0 +1,355 @@
+//! Keeps the order of devices on the strip steady. Ranking by the latest signal strength every frame
+//! makes devices at similar strengths swap back and forth, restarting their transitions each time,
+//! so a device only overtakes another once it has been clearly ahead for a while.
+
+use core::cmp::Ordering;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use crate::{address::BleAddress, config::Config, light_mgr::MAX_DEVICES_SHOWN, time::Instant};
+
+/// What a device is ranked by
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RankCandidate {
+    pub address: BleAddress,
+    /// Ranks ahead of every device that isn't, see
+    /// [`RuleAction::AlwaysShow`](crate::rules::RuleAction::AlwaysShow)
+    pub always_shown: bool,
+    /// Higher is closer, see
+    /// [`DeviceSnapshot::proximity`](crate::ble_device_mgr::DeviceSnapshot::proximity)
+    pub proximity: i32,
+    /// Breaks ties, devices that have been around longer rank first
+    pub first_seen: Instant,
+}
+
+impl RankCandidate {
+    /// Order by the latest values alone, best first
+    fn raw_cmp(&self, other: &Self) -> Ordering {
+        (other.always_shown, other.proximity, self.first_seen).cmp(&(
+            self.always_shown,
+            self.proximity,
+            other.first_seen,
+        ))
+    }
+
+    /// Whether this device should overtake `leader`, right away when a rule says so
+    fn overtakes(&self, leader: &Self, margin: i32) -> Option<bool> {
+        if self.always_shown != leader.always_shown {
+            return self.always_shown.then_some(true);
+        }
+        (self.proximity > leader.proximity + margin).then_some(false)
+    }
+}
+
+/// Only the devices this close to the top can challenge the ones ahead of them, the rest are just
+/// ordered by their latest values since they are nowhere near the strip
+const STABLE_RANKS: usize = MAX_DEVICES_SHOWN * 2;
+
+/// A device behind another that is ahead by more than the margin
+#[derive(Debug, Clone, Copy)]
+struct Challenge {
+    since: Instant,
+    /// Overtakes without waiting out the dwell time
+    immediate: bool,
+    /// Still ahead as of this update, cleared challenges are dropped at the end of it
+    current: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct RankStabilizer {
+    /// Best first, as of the last update
+    order: Vec<BleAddress>,
+    /// Ongoing challenges by (challenger, leader), among the first [`STABLE_RANKS`] of `order`
+    challenges: BTreeMap<(BleAddress, BleAddress), Challenge>,
+    /// Index of each candidate by address, sorted for lookups
+    index: Vec<(BleAddress, usize)>,
+    /// Which candidates, by index, were already in `order`
+    placed: Vec<bool>,
+    ranked: Vec<RankCandidate>,
+    swaps: u32,
+}
+
+impl RankStabilizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Times a device has overtaken another, new devices finding their place don't count
+    pub fn swaps(&self) -> u32 {
+        self.swaps
+    }
+
+    /// Forget the current order, e.g. when what devices are ranked by changes
+    pub fn reset(&mut self) {
+        self.order.clear();
+        self.challenges.clear();
+    }
+
+    /// `candidates` ordered best first. Devices keep their place from the last update unless one
+    /// behind has been more than [`Config::rank_margin`] ahead for [`Config::rank_dwell`]. New
+    /// devices go straight to where they rank now.
+    pub fn rank(
+        &mut self,
+        candidates: &[RankCandidate],
+        config: &Config,
+        now: Instant,
+    ) -> &[RankCandidate] {
+        self.index.clear();
+        self.index.extend(
+            candidates
+                .iter()
+                .enumerate()
+                .map(|(i, candidate)| (candidate.address, i)),
+        );
+        self.index.sort_unstable();
+        let index = &self.index;
+        let position = |address: &BleAddress| {
+            index
+                .binary_search_by_key(address, |&(address, _)| address)
+                .ok()
+                .map(|i| index[i].1)
+        };
+        let find = |address: &BleAddress| position(address).map(|i| &candidates[i]);
+        let placed = &mut self.placed;
+        placed.clear();
+        placed.resize(candidates.len(), false);
+        self.order.retain(|address| match position(address) {
+            Some(i) => {
+                placed[i] = true;
+                true
+            }
+            None => false,
+        });
+
+        // arrivals are placed best first, in `ranked` until it is filled in below
+        self.ranked.clear();
+        self.ranked.extend(
+            candidates
+                .iter()
+                .zip(&self.placed)
+                .filter(|(_, &placed)| !placed)
+                .map(|(candidate, _)| *candidate),
+        );
+        self.ranked.sort_by(|a, b| a.raw_cmp(b));
+        for candidate in &self.ranked {
+            let idx = self
+                .order
+                .iter()
+                .position(|address| candidate.raw_cmp(find(address).unwrap()) == Ordering::Less)
+                .unwrap_or(self.order.len());
+            self.order.insert(idx, candidate.address);
+        }
+
+        // far from the strip there is nothing to keep steady
+        if self.order.len() > STABLE_RANKS {
+            self.order[STABLE_RANKS..].sort_by(|a, b| find(a).unwrap().raw_cmp(find(b).unwrap()));
+        }
+
+        // ongoing challenges keep their start, the first device past the stable ranks can
+        // challenge its way in
+        let margin = config.rank_margin as i32;
+        let stable = self.order.len().min(STABLE_RANKS + 1);
+        for (i, leader) in self.order[..stable].iter().enumerate() {
+            let leader_candidate = find(leader).unwrap();
+            for challenger in &self.order[i + 1..stable] {
+                let Some(immediate) = find(challenger)
+                    .unwrap()
+                    .overtakes(leader_candidate, margin)
+                else {
+                    continue;
+                };
+                let challenge =
+                    self.challenges
+                        .entry((*challenger, *leader))
+                        .or_insert(Challenge {
+                            since: now,
+                            immediate,
+                            current: true,
+                        });
+                challenge.immediate = immediate;
+                challenge.current = true;
+            }
+        }
+        self.challenges
+            .retain(|_, challenge| core::mem::take(&mut challenge.current));
+
+        // a device can pass several others in one update, each pair only swaps once
+        let mut swapped = true;
+        while swapped {
+            swapped = false;
+            for idx in 1..stable {
+                let pair = (self.order[idx], self.order[idx - 1]);
+                let due = self.challenges.get(&pair).map_or(false, |challenge| {
+                    challenge.immediate || now.duration_since(challenge.since) >= config.rank_dwell
+                });
+                if due {
+                    self.challenges.remove(&pair);
+                    self.order.swap(idx - 1, idx);
+                    self.swaps += 1;
+                    swapped = true;
+                }
+            }
+        }
+
+        self.ranked.clear();
+        self.ranked
+            .extend(self.order.iter().map(|address| *find(address).unwrap()));
+        &self.ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn candidate(i: u8, proximity: i32) -> RankCandidate {
+        RankCandidate {
+            address: BleAddress([i, 0, 0, 0, 0, 0]),
+            always_shown: false,
+            proximity,
+            first_seen: Instant::from_micros(i as u64),
+        }
+    }
+
+    fn order(ranked: &[RankCandidate]) -> Vec<u8> {
+        ranked
+            .iter()
+            .map(|candidate| candidate.address.0[0])
+            .collect()
+    }
+
+    /// A minute of updates 10 times a second, three devices a dB or two apart with +-4dB of noise
+    fn jittery_swaps(config: &Config) -> u32 {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let mut ranking = RankStabilizer::new();
+        let mut now = Instant::default();
+        for _ in 0..600 {
+            now += Duration::from_millis(100);
+            let candidates = [-60, -61, -63]
+                .into_iter()
+                .enumerate()
+                .map(|(i, level)| candidate(i as u8, level + rng.gen_range(-4..=4)))
+                .collect::<Vec<_>>();
+            ranking.rank(&candidates, config, now);
+        }
+        ranking.swaps()
+    }
+
+    #[test]
+    fn jitter_barely_swaps() {
+        let unstable = Config {
+            rank_margin: 0,
+            rank_dwell: Duration::ZERO,
+            ..Default::default()
+        };
+        assert!(jittery_swaps(&unstable) > 100);
+        assert!(jittery_swaps(&Config::default()) <= 10);
+    }
+
+    #[test]
+    fn clear_leads_overtake_after_dwell() {
+        let config = Config::default();
+        let mut ranking = RankStabilizer::new();
+        let mut now = Instant::default();
+        let ranked = ranking.rank(&[candidate(0, -60), candidate(1, -70)], &config, now);
+        assert_eq!(order(ranked), [0, 1]);
+
+        // pulls ahead, but has to stay ahead for the dwell time
+        let overtaking = [candidate(0, -60), candidate(1, -50)];
+        now += config.rank_dwell / 2;
+        assert_eq!(order(ranking.rank(&overtaking, &config, now)), [0, 1]);
+        now += config.rank_dwell / 2;
+        assert_eq!(order(ranking.rank(&overtaking, &config, now)), [0, 1]);
+        now += config.rank_dwell / 2;
+        assert_eq!(order(ranking.rank(&overtaking, &config, now)), [1, 0]);
+        assert_eq!(ranking.swaps(), 1);
+
+        // falling back within the margin part way through starts the wait over
+        let behind = [candidate(0, -60), candidate(1, -50)];
+        let overtaking = [candidate(0, -40), candidate(1, -50)];
+        let within_margin = [candidate(0, -48), candidate(1, -50)];
+        now += config.rank_dwell;
+        ranking.rank(&behind, &config, now);
+        ranking.rank(&overtaking, &config, now);
+        now += config.rank_dwell / 2;
+        assert_eq!(order(ranking.rank(&within_margin, &config, now)), [1, 0]);
+        now += config.rank_dwell / 4;
+        assert_eq!(order(ranking.rank(&overtaking, &config, now)), [1, 0]);
+        // a full dwell since the first lead, but not since the second
+        now += config.rank_dwell * 3 / 4;
+        assert_eq!(order(ranking.rank(&overtaking, &config, now)), [1, 0]);
+        now += config.rank_dwell / 4;
+        assert_eq!(order(ranking.rank(&overtaking, &config, now)), [0, 1]);
+        assert_eq!(ranking.swaps(), 2);
+    }
+
+    #[test]
+    fn ties_and_rules() {
+        let config = Config::default();
+        let mut ranking = RankStabilizer::new();
+        let now = Instant::default();
+
+        // equal devices rank by first seen, new devices go straight to their place
+        let ranked = ranking.rank(
+            &[candidate(2, -60), candidate(1, -60), candidate(0, -80)],
+            &config,
+            now,
+        );
+        assert_eq!(order(ranked), [1, 2, 0]);
+        let ranked = ranking.rank(
+            &[
+                candidate(2, -60),
+                candidate(1, -60),
+                candidate(0, -80),
+                candidate(3, -50),
+            ],
+            &config,
+            now,
+        );
+        assert_eq!(order(ranked), [3, 1, 2, 0]);
+        assert_eq!(ranking.swaps(), 0);
+
+        // a rule to always show a device applies right away
+        let shown = RankCandidate {
+            always_shown: true,
+            ..candidate(0, -80)
+        };
+        let ranked = ranking.rank(
+            &[
+                candidate(2, -60),
+                candidate(1, -60),
+                shown,
+                candidate(3, -50),
+            ],
+            &config,
+            now,
+        );
+        assert_eq!(order(ranked), [0, 3, 1, 2]);
+    }
+
+    #[test]
+    fn only_ranks_near_the_strip_are_held() {
+        let config = Config::default();
+        let mut ranking = RankStabilizer::new();
+        let now = Instant::default();
+        let mut candidates: Vec<_> = (0..STABLE_RANKS as u8 + 5)
+            .map(|i| candidate(i, -40 - i as i32))
+            .collect();
+        ranking.rank(&candidates, &config, now);
+
+        // the last two swap places right away, well past the stable ranks
+        let last = candidates.len() - 1;
+        candidates[last].proximity = -62;
+        candidates[last - 1].proximity = -70;
+        // the first one past them has to wait to challenge its way in, like any other
+        candidates[STABLE_RANKS].proximity = -45;
+        let ranked = order(ranking.rank(&candidates, &config, now));
+        assert_eq!(ranked[last - 1..], [last as u8, last as u8 - 1]);
+        assert_eq!(ranked[STABLE_RANKS], STABLE_RANKS as u8);
+        assert_eq!(ranking.swaps(), 0);
+    }
+}
@@ -0,0 +1,613 @@
+//! User rules deciding what happens to scanned devices, checked in order with the first match
+//! winning. Rules are serialized the same way for persistent storage and for the configuration
+//! service.
+//!
+//! | Matcher            | Encoding                                          |
+//! |--------------------|---------------------------------------------------|
+//! | 0 Address          | 6 bytes in display order                          |
+//! | 1 Address prefix   | `u8` length in `1..=5`, then that many bytes      |
+//! | 2 Name pattern     | `u8` length, then UTF-8 with `*` and `?` wildcards |
+//! | 3 Company ID       | `u16` little endian                               |
+//! | 4 Service UUID     | `u8` size (2, 4 or 16), then the UUID little endian |
+//! | 5 Signal range     | `i8` min, `i8` max, dBm                           |
+//!
+//! | Action             | Encoding                                          |
+//! |--------------------|---------------------------------------------------|
+//! | 0 Ignore           |                                                   |
+//! | 1 Always show      |                                                   |
+//! | 2 Pin color        | `u16` hue in degrees, little endian               |
+//! | 3 Favorite         | `u16` hue in degrees, little endian               |
+//! | 4 Signal filter    | 9 bytes, as the filter is stored in the config    |
+
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use crate::{
+    address::BleAddress,
+    advertisement::{AdvertisementSummary, Uuid},
+    config::SignalFilterKind,
+};
+
+/// Bump whenever the serialized layout changes, older lists are rejected and no rules used instead
+const RULES_VERSION: u8 = 1;
+
+/// Most rules that can be stored
+pub const MAX_RULES: usize = 16;
+
+/// Longest encoded rule, a 128 bit service UUID with a signal filter
+pub const MAX_RULE_LEN: usize = 1 + 1 + 16 + 1 + 9;
+
+/// Size of the largest rule list in persistent storage
+pub const MAX_SERIALIZED_LEN: usize = 2 + MAX_RULES * MAX_RULE_LEN;
+
+/// Glob matched against advertised names, ASCII case insensitive. `*` matches any run of
+/// characters and `?` any single byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NamePattern {
+    bytes: [u8; NamePattern::MAX_LEN],
+    len: u8,
+}
+
+impl NamePattern {
+    pub const MAX_LEN: usize = 16;
+
+    /// `None` if empty or too long
+    pub fn new(pattern: &str) -> Option<Self> {
+        if pattern.is_empty() || pattern.len() > Self::MAX_LEN {
+            return None;
+        }
+        let mut bytes = [0; Self::MAX_LEN];
+        bytes[..pattern.len()].copy_from_slice(pattern.as_bytes());
+        Some(Self {
+            bytes,
+            len: pattern.len() as u8,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only ever built from a str
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// Names aren't necessarily valid UTF-8, so they are matched as bytes
+    pub fn matches(&self, name: &[u8]) -> bool {
+        let pattern = &self.bytes[..self.len as usize];
+        // backtrack to just after the last `*` on a mismatch, it can't need to go further back
+        let (mut p, mut n) = (0, 0);
+        let mut last_star = None;
+        while n < name.len() {
+            match pattern.get(p) {
+                Some(b'*') => {
+                    last_star = Some((p, n));
+                    p += 1;
+                }
+                Some(&c) if c == b'?' || c.eq_ignore_ascii_case(&name[n]) => {
+                    p += 1;
+                    n += 1;
+                }
+                _ => match last_star {
+                    Some((star, star_n)) => {
+                        p = star + 1;
+                        n = star_n + 1;
+                        last_star = Some((star, star_n + 1));
+                    }
+                    None => return false,
+                },
+            }
+        }
+        pattern[p..].iter().all(|&c| c == b'*')
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuleMatch {
+    Address(BleAddress),
+    /// The first `len` bytes of the address, e.g. a manufacturer's OUI
+    AddressPrefix {
+        bytes: [u8; 5],
+        len: u8,
+    },
+    Name(NamePattern),
+    CompanyId(u16),
+    ServiceUuid(Uuid),
+    /// Inclusive, dBm
+    SignalRange {
+        min: i8,
+        max: i8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RuleAction {
+    /// Never track the device
+    Ignore,
+    /// Track the device whatever its signal strength, and show it ahead of everything else
+    AlwaysShow,
+    /// Hue in degrees to draw the device with, instead of an allocated color
+    PinColor(u16),
+    /// Treat the device as a favorite, drawn with the hue in degrees
+    Favorite(u16),
+    /// Smooth the device's signal strength with this filter instead of the configured one, e.g.
+    /// a median for a device whose signal spikes
+    SignalFilter(SignalFilterKind),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rule {
+    pub matcher: RuleMatch,
+    pub action: RuleAction,
+}
+
+/// What a rule is checked against, from a single advertisement
+#[derive(Debug, Clone, Copy)]
+pub struct ScannedDevice<'a> {
+    pub address: BleAddress,
+    /// Advertised name, if this advertisement carried one
+    pub name: Option<&'a [u8]>,
+    pub advertisement: &'a AdvertisementSummary,
+    pub rssi: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleError {
+    /// Serialized rule is truncated or has an unknown tag
+    Malformed,
+    /// Serialized rule list has the wrong version, or trailing bytes
+    MalformedList,
+    TooManyRules,
+    AddressPrefix,
+    NamePattern,
+    ServiceUuid,
+    SignalRange,
+    Hue,
+    SignalFilter,
+    NoSuchRule,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            RuleError::Malformed => "malformed rule",
+            RuleError::MalformedList => "malformed rule list",
+            RuleError::TooManyRules => "too many rules",
+            RuleError::AddressPrefix => "address prefix must be 1 to 5 bytes",
+            RuleError::NamePattern => "name pattern must be 1 to 16 bytes of UTF-8",
+            RuleError::ServiceUuid => "service UUID must be 2, 4 or 16 bytes",
+            RuleError::SignalRange => "signal range must be in -127..=0 with min <= max",
+            RuleError::Hue => "hue must be in 0..360",
+            RuleError::SignalFilter => "unknown signal filter or parameter out of range",
+            RuleError::NoSuchRule => "no such rule",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl RuleMatch {
+    pub fn matches(&self, device: &ScannedDevice) -> bool {
+        match *self {
+            RuleMatch::Address(address) => device.address == address,
+            RuleMatch::AddressPrefix { bytes, len } => {
+                device.address.0.starts_with(&bytes[..len as usize])
+            }
+            RuleMatch::Name(pattern) => matches!(device.name, Some(name) if pattern.matches(name)),
+            RuleMatch::CompanyId(company_id) => device.advertisement.company_id == Some(company_id),
+            RuleMatch::ServiceUuid(uuid) => device.advertisement.service_uuids.contains(&uuid),
+            RuleMatch::SignalRange { min, max } => (min as i32..=max as i32).contains(&device.rssi),
+        }
+    }
+}
+
+impl Rule {
+    pub fn validate(&self) -> Result<(), RuleError> {
+        match self.matcher {
+            RuleMatch::AddressPrefix { len, .. } if !(1..=5).contains(&len) => {
+                return Err(RuleError::AddressPrefix)
+            }
+            RuleMatch::SignalRange { min, max } if min == i8::MIN || max > 0 || min > max => {
+                return Err(RuleError::SignalRange)
+            }
+            _ => {}
+        }
+        match self.action {
+            RuleAction::PinColor(hue) | RuleAction::Favorite(hue) if hue >= 360 => {
+                Err(RuleError::Hue)
+            }
+            RuleAction::SignalFilter(kind) => kind.validate().map_err(|_| RuleError::SignalFilter),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self.matcher {
+            RuleMatch::Address(address) => {
+                out.push(0);
+                out.extend_from_slice(&address.0);
+            }
+            RuleMatch::AddressPrefix { bytes, len } => {
+                out.extend_from_slice(&[1, len]);
+                out.extend_from_slice(&bytes[..len as usize]);
+            }
+            RuleMatch::Name(pattern) => {
+                out.extend_from_slice(&[2, pattern.len]);
+                out.extend_from_slice(pattern.as_str().as_bytes());
+            }
+            RuleMatch::CompanyId(company_id) => {
+                out.push(3);
+                out.extend_from_slice(&company_id.to_le_bytes());
+            }
+            RuleMatch::ServiceUuid(uuid) => {
+                out.extend_from_slice(&[4, uuid.size() as u8]);
+                uuid.encode(out);
+            }
+            RuleMatch::SignalRange { min, max } => {
+                out.extend_from_slice(&[5, min as u8, max as u8]);
+            }
+        }
+        match self.action {
+            RuleAction::Ignore => out.push(0),
+            RuleAction::AlwaysShow => out.push(1),
+            RuleAction::PinColor(hue) => {
+                out.push(2);
+                out.extend_from_slice(&hue.to_le_bytes());
+            }
+            RuleAction::Favorite(hue) => {
+                out.push(3);
+                out.extend_from_slice(&hue.to_le_bytes());
+            }
+            RuleAction::SignalFilter(kind) => {
+                out.push(4);
+                out.extend_from_slice(&kind.to_bytes());
+            }
+        }
+    }
+
+    /// Parse and validate a single rule, which must take up all of `data`
+    pub fn decode(data: &[u8]) -> Result<Self, RuleError> {
+        let mut data = data;
+        let rule = Self::decode_from(&mut data)?;
+        if !data.is_empty() {
+            return Err(RuleError::Malformed);
+        }
+        Ok(rule)
+    }
+
+    /// Parse and validate the rule at the start of `data`, advancing past it
+    fn decode_from(data: &mut &[u8]) -> Result<Self, RuleError> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], RuleError> {
+            if data.len() < len {
+                return Err(RuleError::Malformed);
+            }
+            let (taken, rest) = data.split_at(len);
+            *data = rest;
+            Ok(taken)
+        }
+        let u16_from = |bytes: &[u8]| u16::from_le_bytes([bytes[0], bytes[1]]);
+
+        let matcher = match take(data, 1)?[0] {
+            0 => RuleMatch::Address(BleAddress(take(data, 6)?.try_into().unwrap())),
+            1 => {
+                let len = take(data, 1)?[0];
+                if !(1..=5).contains(&len) {
+                    return Err(RuleError::AddressPrefix);
+                }
+                let mut bytes = [0; 5];
+                bytes[..len as usize].copy_from_slice(take(data, len as usize)?);
+                RuleMatch::AddressPrefix { bytes, len }
+            }
+            2 => {
+                let len = take(data, 1)?[0] as usize;
+                core::str::from_utf8(take(data, len)?)
+                    .ok()
+                    .and_then(NamePattern::new)
+                    .map(RuleMatch::Name)
+                    .ok_or(RuleError::NamePattern)?
+            }
+            3 => RuleMatch::CompanyId(u16_from(take(data, 2)?)),
+            4 => {
+                let size = take(data, 1)?[0] as usize;
+                if ![2, 4, 16].contains(&size) {
+                    return Err(RuleError::ServiceUuid);
+                }
+                let uuid = Uuid::from_le_slice(take(data, size)?).ok_or(RuleError::ServiceUuid)?;
+                RuleMatch::ServiceUuid(uuid)
+            }
+            5 => {
+                let range = take(data, 2)?;
+                RuleMatch::SignalRange {
+                    min: range[0] as i8,
+                    max: range[1] as i8,
+                }
+            }
+            _ => return Err(RuleError::Malformed),
+        };
+        let action = match take(data, 1)?[0] {
+            0 => RuleAction::Ignore,
+            1 => RuleAction::AlwaysShow,
+            2 => RuleAction::PinColor(u16_from(take(data, 2)?)),
+            3 => RuleAction::Favorite(u16_from(take(data, 2)?)),
+            4 => RuleAction::SignalFilter(
+                SignalFilterKind::from_bytes(take(data, 9)?)
+                    .map_err(|_| RuleError::SignalFilter)?,
+            ),
+            _ => return Err(RuleError::Malformed),
+        };
+
+        let rule = Self { matcher, action };
+        rule.validate()?;
+        Ok(rule)
+    }
+}
+
+/// Ordered list of rules, adjustable at runtime
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Action of the first rule matching `device`
+    pub fn evaluate(&self, device: &ScannedDevice) -> Option<RuleAction> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matcher.matches(device))
+            .map(|rule| rule.action)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter()
+    }
+
+    /// Add a rule after the existing ones, so it only applies where none of them do
+    pub fn push(&mut self, rule: Rule) -> Result<(), RuleError> {
+        rule.validate()?;
+        if self.rules.len() >= MAX_RULES {
+            return Err(RuleError::TooManyRules);
+        }
+        self.rules.push(rule);
+        Ok(())
+    }
+
+    pub fn remove(&mut self, idx: usize) -> Result<Rule, RuleError> {
+        if idx >= self.rules.len() {
+            return Err(RuleError::NoSuchRule);
+        }
+        Ok(self.rules.remove(idx))
+    }
+
+    pub fn clear(&mut self) {
+        self.rules.clear();
+    }
+
+    /// Serialize for persistent storage
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = alloc::vec![RULES_VERSION, self.rules.len() as u8];
+        for rule in self.rules.iter() {
+            rule.encode(&mut out);
+        }
+        out
+    }
+
+    /// Parse and validate a stored rule list
+    pub fn from_bytes(data: &[u8]) -> Result<Self, RuleError> {
+        let [RULES_VERSION, count, ref data @ ..] = *data else {
+            return Err(RuleError::MalformedList);
+        };
+        let mut data = data;
+        let mut rules = Self::new();
+        for _ in 0..count {
+            rules.push(Rule::decode_from(&mut data)?)?;
+        }
+        if !data.is_empty() {
+            return Err(RuleError::MalformedList);
+        }
+        Ok(rules)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advertisement::tests::{APPLE_NEARBY_INFO, FAST_PAIR, MICROSOFT_CDP};
+
+    const PHONE: BleAddress = BleAddress([0x5A, 0x10, 0x20, 0x30, 0x40, 0x50]);
+
+    fn scanned<'a>(
+        advertisement: &'a AdvertisementSummary,
+        name: Option<&'a str>,
+        rssi: i32,
+    ) -> ScannedDevice<'a> {
+        ScannedDevice {
+            address: PHONE,
+            name: name.map(str::as_bytes),
+            advertisement,
+            rssi,
+        }
+    }
+
+    fn name_rule(pattern: &str, action: RuleAction) -> Rule {
+        Rule {
+            matcher: RuleMatch::Name(NamePattern::new(pattern).unwrap()),
+            action,
+        }
+    }
+
+    #[test]
+    fn name_patterns() {
+        let matches =
+            |pattern, name: &str| NamePattern::new(pattern).unwrap().matches(name.as_bytes());
+        assert!(matches("Pixel*", "Pixel 8"));
+        assert!(matches("*buds*", "Galaxy Buds2 Pro"));
+        assert!(matches("JBL ???", "jbl go3"));
+        assert!(matches("*", ""));
+        assert!(matches("a*b*c", "aXbYbZc"));
+        assert!(!matches("Pixel*", "My Pixel"));
+        assert!(!matches("JBL ???", "JBL Flip"));
+        assert!(!matches("a*b*c", "aXbYbZ"));
+        assert!(NamePattern::new("").is_none());
+        assert!(NamePattern::new("a pattern far too long").is_none());
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let phone = AdvertisementSummary::from_payload(APPLE_NEARBY_INFO);
+        let headphones = AdvertisementSummary::from_payload(FAST_PAIR);
+        let laptop = AdvertisementSummary::from_payload(MICROSOFT_CDP);
+
+        let mut rules = Rules::new();
+        rules
+            .push(Rule {
+                matcher: RuleMatch::SignalRange { min: -40, max: 0 },
+                action: RuleAction::AlwaysShow,
+            })
+            .unwrap();
+        rules
+            .push(name_rule("*iPhone*", RuleAction::Favorite(120)))
+            .unwrap();
+        rules
+            .push(Rule {
+                matcher: RuleMatch::CompanyId(0x004c),
+                action: RuleAction::Ignore,
+            })
+            .unwrap();
+        rules
+            .push(Rule {
+                matcher: RuleMatch::ServiceUuid(Uuid::Uuid16(0xfe2c)),
+                action: RuleAction::PinColor(300),
+            })
+            .unwrap();
+        rules
+            .push(Rule {
+                matcher: RuleMatch::AddressPrefix {
+                    bytes: [0x5A, 0x10, 0, 0, 0],
+                    len: 2,
+                },
+                action: RuleAction::Ignore,
+            })
+            .unwrap();
+
+        let evaluate = |device| rules.evaluate(&device);
+        assert_eq!(
+            evaluate(scanned(&phone, None, -30)),
+            Some(RuleAction::AlwaysShow)
+        );
+        assert_eq!(
+            evaluate(scanned(&phone, Some("Sam's iPhone"), -60)),
+            Some(RuleAction::Favorite(120))
+        );
+        assert_eq!(
+            evaluate(scanned(&phone, None, -60)),
+            Some(RuleAction::Ignore)
+        );
+        assert_eq!(
+            evaluate(scanned(&headphones, None, -60)),
+            Some(RuleAction::PinColor(300))
+        );
+        assert_eq!(
+            evaluate(scanned(&laptop, None, -60)),
+            Some(RuleAction::Ignore)
+        );
+
+        rules.remove(4).unwrap();
+        assert_eq!(rules.evaluate(&scanned(&laptop, None, -60)), None);
+        assert_eq!(rules.remove(4), Err(RuleError::NoSuchRule));
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rules = Rules::new();
+        for rule in [
+            Rule {
+                matcher: RuleMatch::Address(PHONE),
+                action: RuleAction::AlwaysShow,
+            },
+            Rule {
+                matcher: RuleMatch::AddressPrefix {
+                    bytes: [0xAC, 0xDE, 0x48, 0, 0],
+                    len: 3,
+                },
+                action: RuleAction::Ignore,
+            },
+            name_rule("Pixel*", RuleAction::PinColor(45)),
+            Rule {
+                matcher: RuleMatch::CompanyId(0x0006),
+                action: RuleAction::Ignore,
+            },
+            Rule {
+                matcher: RuleMatch::ServiceUuid(Uuid::Uuid128(0x1234_5678_9abc_def0)),
+                action: RuleAction::Favorite(359),
+            },
+            Rule {
+                matcher: RuleMatch::SignalRange { min: -127, max: 0 },
+                action: RuleAction::AlwaysShow,
+            },
+            Rule {
+                matcher: RuleMatch::ServiceUuid(Uuid::Uuid128(0x1234_5678_9abc_def0)),
+                action: RuleAction::SignalFilter(SignalFilterKind::Kalman {
+                    process_noise: 0.5,
+                    measurement_noise: 12.0,
+                }),
+            },
+        ] {
+            let mut encoded = Vec::new();
+            rule.encode(&mut encoded);
+            assert!(encoded.len() <= MAX_RULE_LEN);
+            assert_eq!(Rule::decode(&encoded), Ok(rule));
+            rules.push(rule).unwrap();
+        }
+        assert_eq!(Rules::from_bytes(&rules.to_bytes()), Ok(rules));
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        assert_eq!(Rule::decode(&[]), Err(RuleError::Malformed));
+        assert_eq!(Rule::decode(&[3, 0x06, 0x00]), Err(RuleError::Malformed));
+        assert_eq!(Rule::decode(&[3, 0x06, 0x00, 9]), Err(RuleError::Malformed));
+        assert_eq!(
+            Rule::decode(&[3, 0x06, 0x00, 0, 0]),
+            Err(RuleError::Malformed)
+        );
+        assert_eq!(
+            Rule::decode(&[1, 6, 1, 2, 3, 4, 5, 6, 0]),
+            Err(RuleError::AddressPrefix)
+        );
+        assert_eq!(
+            Rule::decode(&[2, 2, 0xff, 0xfe, 0]),
+            Err(RuleError::NamePattern)
+        );
+        assert_eq!(
+            Rule::decode(&[4, 3, 1, 2, 3, 0]),
+            Err(RuleError::ServiceUuid)
+        );
+        assert_eq!(
+            Rule::decode(&[5, -40i8 as u8, -60i8 as u8, 0]),
+            Err(RuleError::SignalRange)
+        );
+        assert_eq!(
+            Rule::decode(&[3, 0x06, 0x00, 2, 0x68, 0x01]),
+            Err(RuleError::Hue)
+        );
+        assert_eq!(
+            Rule::decode(&[3, 0x06, 0x00, 4, 9, 0, 0, 0, 0, 0, 0, 0, 0]),
+            Err(RuleError::SignalFilter)
+        );
+
+        let mut rules = Rules::new();
+        let rule = name_rule("*", RuleAction::Ignore);
+        for _ in 0..MAX_RULES {
+            rules.push(rule).unwrap();
+        }
+        assert_eq!(rules.push(rule), Err(RuleError::TooManyRules));
+        assert!(rules.to_bytes().len() <= MAX_SERIALIZED_LEN);
+
+        let mut bytes = rules.to_bytes();
+        bytes.push(0);
+        assert_eq!(Rules::from_bytes(&bytes), Err(RuleError::MalformedList));
+        assert_eq!(Rules::from_bytes(&[0, 0]), Err(RuleError::MalformedList));
+        assert_eq!(Rules::from_bytes(&[RULES_VERSION, 0]), Ok(Rules::new()));
+    }
+}
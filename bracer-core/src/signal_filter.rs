@@ -0,0 +1,438 @@
+//! Filters that turn noisy RSSI samples into a signal strength estimate. Which one each device
+//! uses is picked by [`Config::signal_filter`], unless a
+//! [`RuleAction::SignalFilter`](crate::rules::RuleAction::SignalFilter) picks one for it.
+
+#[cfg(not(test))]
+use num::Float;
+
+use crate::{
+    config::{SignalFilterKind, MAX_SIGNAL_MOVING_AVG_WINDOW},
+    utils::MovingAvg,
+};
+
+pub trait SignalFilter {
+    fn push(&mut self, sample: i32);
+
+    /// Current estimate of the signal strength, in dBm
+    fn estimate(&self) -> f32;
+
+    /// How uncertain [`Self::estimate`] is, in dB², infinite until there are enough samples to
+    /// tell
+    fn variance(&self) -> f32;
+}
+
+/// Mean and unbiased variance of `samples`
+fn mean_and_variance(samples: &[i32]) -> (f32, f32) {
+    let n = samples.len() as f32;
+    let mean = samples.iter().sum::<i32>() as f32 / n;
+    let squares = samples
+        .iter()
+        .map(|&sample| (sample as f32 - mean) * (sample as f32 - mean))
+        .sum::<f32>();
+    (mean, squares / (n - 1.0))
+}
+
+/// Variance of the mean of `samples`, the spread of the samples shrinks as more are averaged
+fn variance_of_mean(samples: &[i32]) -> f32 {
+    match samples.len() {
+        0 | 1 => f32::INFINITY,
+        n => mean_and_variance(samples).1 / n as f32,
+    }
+}
+
+/// Unlike [`MovingAvg::get_avg`], the estimate isn't truncated
+impl<const N: usize> SignalFilter for MovingAvg<N> {
+    fn push(&mut self, sample: i32) {
+        MovingAvg::push(self, sample);
+    }
+
+    fn estimate(&self) -> f32 {
+        match self.samples() {
+            [] => 0.0,
+            samples => mean_and_variance(samples).0,
+        }
+    }
+
+    fn variance(&self) -> f32 {
+        variance_of_mean(self.samples())
+    }
+}
+
+/// Exponential moving average, recent samples count the most but old ones never quite drop out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ema {
+    /// Weight of each new sample
+    alpha: f32,
+    estimate: Option<f32>,
+    /// Exponentially weighted variance of the samples
+    sample_variance: f32,
+    samples: u32,
+}
+
+impl Ema {
+    pub fn new(alpha: f32) -> Self {
+        Self {
+            alpha,
+            estimate: None,
+            sample_variance: 0.0,
+            samples: 0,
+        }
+    }
+}
+
+impl SignalFilter for Ema {
+    fn push(&mut self, sample: i32) {
+        let sample = sample as f32;
+        self.samples = self.samples.saturating_add(1);
+        let Some(estimate) = self.estimate else {
+            self.estimate = Some(sample);
+            return;
+        };
+        let diff = sample - estimate;
+        self.estimate = Some(estimate + self.alpha * diff);
+        self.sample_variance =
+            (1.0 - self.alpha) * (self.sample_variance + self.alpha * diff * diff);
+    }
+
+    fn estimate(&self) -> f32 {
+        self.estimate.unwrap_or_default()
+    }
+
+    fn variance(&self) -> f32 {
+        if self.samples < 2 {
+            return f32::INFINITY;
+        }
+        self.sample_variance * self.alpha / (2.0 - self.alpha)
+    }
+}
+
+/// Median of the last samples, ignores spikes entirely as long as they are less than half the
+/// window
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Median<const N: usize> {
+    window: MovingAvg<N>,
+}
+
+impl<const N: usize> Median<N> {
+    pub fn with_window(window: usize) -> Self {
+        Self {
+            window: MovingAvg::with_window(window),
+        }
+    }
+
+    pub fn set_window(&mut self, window: usize) {
+        self.window.set_window(window);
+    }
+}
+
+/// `samples` in ascending order, in a copy
+fn sorted<const N: usize>(samples: &[i32]) -> ([i32; N], usize) {
+    let mut sorted = [0; N];
+    sorted[..samples.len()].copy_from_slice(samples);
+    sorted[..samples.len()].sort_unstable();
+    (sorted, samples.len())
+}
+
+impl<const N: usize> SignalFilter for Median<N> {
+    fn push(&mut self, sample: i32) {
+        self.window.push(sample);
+    }
+
+    fn estimate(&self) -> f32 {
+        let (sorted, len) = sorted::<N>(self.window.samples());
+        match len {
+            0 => 0.0,
+            len if len % 2 == 1 => sorted[len / 2] as f32,
+            len => (sorted[len / 2 - 1] + sorted[len / 2]) as f32 / 2.0,
+        }
+    }
+
+    /// The median of normally distributed samples is about π/2 times as uncertain as their mean
+    fn variance(&self) -> f32 {
+        variance_of_mean(self.window.samples()) * core::f32::consts::FRAC_PI_2
+    }
+}
+
+/// Mean of the last samples after dropping the highest and lowest few, between a moving average
+/// and a median
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrimmedMean<const N: usize> {
+    window: MovingAvg<N>,
+    /// Samples dropped from each end, fewer while the window is filling up
+    trim: usize,
+}
+
+impl<const N: usize> TrimmedMean<N> {
+    pub fn with_window(window: usize, trim: usize) -> Self {
+        Self {
+            window: MovingAvg::with_window(window),
+            trim,
+        }
+    }
+
+    pub fn set_window(&mut self, window: usize, trim: usize) {
+        self.window.set_window(window);
+        self.trim = trim;
+    }
+
+    fn kept(&self) -> ([i32; N], core::ops::Range<usize>) {
+        let (sorted, len) = sorted::<N>(self.window.samples());
+        // always keep at least one sample, or two if there are enough to choose from
+        let trim = self.trim.min(len.saturating_sub(1) / 2);
+        (sorted, trim..len - trim)
+    }
+}
+
+impl<const N: usize> SignalFilter for TrimmedMean<N> {
+    fn push(&mut self, sample: i32) {
+        self.window.push(sample);
+    }
+
+    fn estimate(&self) -> f32 {
+        let (sorted, kept) = self.kept();
+        match &sorted[kept] {
+            [] => 0.0,
+            samples => mean_and_variance(samples).0,
+        }
+    }
+
+    fn variance(&self) -> f32 {
+        let (sorted, kept) = self.kept();
+        variance_of_mean(&sorted[kept])
+    }
+}
+
+/// One dimensional Kalman filter, modelling the signal strength as a random walk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Kalman {
+    /// How much the true signal strength is expected to wander between samples, in dB²
+    process_noise: f32,
+    /// How noisy each sample is, in dB²
+    measurement_noise: f32,
+    estimate: Option<f32>,
+    variance: f32,
+}
+
+impl Kalman {
+    pub fn new(process_noise: f32, measurement_noise: f32) -> Self {
+        Self {
+            process_noise,
+            measurement_noise,
+            estimate: None,
+            variance: f32::INFINITY,
+        }
+    }
+}
+
+impl SignalFilter for Kalman {
+    fn push(&mut self, sample: i32) {
+        let sample = sample as f32;
+        let Some(estimate) = self.estimate else {
+            self.estimate = Some(sample);
+            self.variance = self.measurement_noise;
+            return;
+        };
+        let predicted_variance = self.variance + self.process_noise;
+        let gain = predicted_variance / (predicted_variance + self.measurement_noise);
+        self.estimate = Some(estimate + gain * (sample - estimate));
+        self.variance = (1.0 - gain) * predicted_variance;
+    }
+
+    fn estimate(&self) -> f32 {
+        self.estimate.unwrap_or_default()
+    }
+
+    fn variance(&self) -> f32 {
+        self.variance
+    }
+}
+
+/// Whichever filter the config picks, kept inline so devices don't need an allocation each
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfiguredFilter {
+    MovingAvg(MovingAvg<MAX_SIGNAL_MOVING_AVG_WINDOW>),
+    Ema(Ema),
+    Median(Median<MAX_SIGNAL_MOVING_AVG_WINDOW>),
+    TrimmedMean(TrimmedMean<MAX_SIGNAL_MOVING_AVG_WINDOW>),
+    Kalman(Kalman),
+}
+
+impl ConfiguredFilter {
+    /// Windowed filters look at the last `window` samples
+    pub fn new(kind: SignalFilterKind, window: usize) -> Self {
+        match kind {
+            SignalFilterKind::MovingAvg => Self::MovingAvg(MovingAvg::with_window(window)),
+            SignalFilterKind::Ema { alpha } => Self::Ema(Ema::new(alpha)),
+            SignalFilterKind::Median => Self::Median(Median::with_window(window)),
+            SignalFilterKind::TrimmedMean { trim } => {
+                Self::TrimmedMean(TrimmedMean::with_window(window, trim as usize))
+            }
+            SignalFilterKind::Kalman {
+                process_noise,
+                measurement_noise,
+            } => Self::Kalman(Kalman::new(process_noise, measurement_noise)),
+        }
+    }
+
+    /// Switch to another filter or window. Windows are resized keeping their samples, other
+    /// changes start a new filter from the current estimate.
+    pub fn reconfigure(&mut self, kind: SignalFilterKind, window: usize) {
+        match (self, kind) {
+            (Self::MovingAvg(filter), SignalFilterKind::MovingAvg) => filter.set_window(window),
+            (Self::Median(filter), SignalFilterKind::Median) => filter.set_window(window),
+            (Self::TrimmedMean(filter), SignalFilterKind::TrimmedMean { trim }) => {
+                filter.set_window(window, trim as usize)
+            }
+            (filter, _) => {
+                let estimate = filter.estimate();
+                *filter = Self::new(kind, window);
+                filter.push(estimate.round() as i32);
+            }
+        }
+    }
+
+    fn as_filter(&self) -> &dyn SignalFilter {
+        match self {
+            Self::MovingAvg(filter) => filter,
+            Self::Ema(filter) => filter,
+            Self::Median(filter) => filter,
+            Self::TrimmedMean(filter) => filter,
+            Self::Kalman(filter) => filter,
+        }
+    }
+}
+
+impl SignalFilter for ConfiguredFilter {
+    fn push(&mut self, sample: i32) {
+        match self {
+            Self::MovingAvg(filter) => SignalFilter::push(filter, sample),
+            Self::Ema(filter) => filter.push(sample),
+            Self::Median(filter) => filter.push(sample),
+            Self::TrimmedMean(filter) => filter.push(sample),
+            Self::Kalman(filter) => filter.push(sample),
+        }
+    }
+
+    fn estimate(&self) -> f32 {
+        self.as_filter().estimate()
+    }
+
+    fn variance(&self) -> f32 {
+        self.as_filter().variance()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use alloc::{boxed::Box, vec, vec::Vec};
+
+    const WINDOW: usize = 5;
+
+    fn filters() -> Vec<(&'static str, Box<dyn SignalFilter>)> {
+        vec![
+            ("moving avg", Box::new(MovingAvg::<WINDOW>::new())),
+            ("ema", Box::new(Ema::new(0.3))),
+            ("median", Box::new(Median::<WINDOW>::with_window(WINDOW))),
+            (
+                "trimmed mean",
+                Box::new(TrimmedMean::<WINDOW>::with_window(WINDOW, 1)),
+            ),
+            ("kalman", Box::new(Kalman::new(1.0, 16.0))),
+        ]
+    }
+
+    /// Estimate after each sample
+    fn run(filter: &mut dyn SignalFilter, samples: impl IntoIterator<Item = i32>) -> Vec<f32> {
+        samples
+            .into_iter()
+            .map(|sample| {
+                filter.push(sample);
+                filter.estimate()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn steady_signal() {
+        for (name, mut filter) in filters() {
+            assert_eq!(filter.variance(), f32::INFINITY, "{name}");
+            let estimates = run(filter.as_mut(), [-70; 20]);
+            assert!(estimates.iter().all(|&e| e == -70.0), "{name}");
+            // the Kalman filter's variance settles to what its noise parameters allow
+            assert!(filter.variance() < 4.0, "{name}");
+        }
+    }
+
+    #[test]
+    fn step_change() {
+        // how many samples after a 20dB jump until the estimate is within 2dB of it
+        let settle = |filter: &mut dyn SignalFilter| {
+            run(filter, [-80; 10]);
+            run(filter, [-60; 30])
+                .iter()
+                .position(|&e| e > -62.0)
+                .map(|samples| samples + 1)
+        };
+        let settled: Vec<_> = filters()
+            .into_iter()
+            .map(|(name, mut filter)| (name, settle(filter.as_mut()).unwrap()))
+            .collect();
+        assert_eq!(
+            settled,
+            [
+                ("moving avg", 5),
+                ("ema", 7),
+                ("median", 3),
+                ("trimmed mean", 4),
+                ("kalman", 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn spikes() {
+        // how far a single 30dB spike in a steady signal moves the estimate
+        let jump = |filter: &mut dyn SignalFilter| {
+            run(filter, [-70; 10]);
+            run(filter, [-40]).last().unwrap() + 70.0
+        };
+        let jumps: Vec<_> = filters()
+            .into_iter()
+            .map(|(name, mut filter)| (name, jump(filter.as_mut())))
+            .collect();
+        for (name, jump) in &jumps {
+            match *name {
+                "median" | "trimmed mean" => assert_eq!(*jump, 0.0, "{name}"),
+                _ => assert!(*jump > 1.0 && *jump <= 9.0, "{name}: {jump}"),
+            }
+        }
+
+        // noise makes the filters that measure it less sure of themselves, the Kalman filter's
+        // variance only depends on its noise parameters
+        for (name, mut filter) in filters().into_iter().filter(|(name, _)| *name != "kalman") {
+            run(filter.as_mut(), [-70; 10]);
+            let steady = filter.variance();
+            run(filter.as_mut(), [-60, -80, -65, -75]);
+            assert!(filter.variance() > steady, "{name}");
+        }
+    }
+
+    #[test]
+    fn reconfigure_keeps_estimate() {
+        let config = Config::default();
+        let mut filter =
+            ConfiguredFilter::new(config.signal_filter, config.signal_moving_avg_window);
+        run(&mut filter, [-60, -70]);
+        assert_eq!(filter.estimate(), -65.0);
+
+        let kalman = SignalFilterKind::Kalman {
+            process_noise: 1.0,
+            measurement_noise: 16.0,
+        };
+        filter.reconfigure(kalman, config.signal_moving_avg_window);
+        assert!(matches!(filter, ConfiguredFilter::Kalman(_)));
+        assert_eq!(filter.estimate(), -65.0);
+    }
+}
@@ -0,0 +1,138 @@
+use core::time::Duration;
+
+use crate::{
+    address::{AddressType, BleAddress},
+    ble_device_mgr::DeviceTracker,
+    favorite_payload::FavoritePayload,
+    time::{Clock, Instant},
+};
+
+/// Fake scan results, for running without a radio (e.g. in the Wokwi simulator)
+pub struct SimulatedScanner {
+    favorite_hue: u16,
+
+    /// When to advertise the fake devices again, once the tracker has forgotten them
+    respawn_at: Option<Instant>,
+
+    /// While the fake devices keep advertising, and when they next do
+    advertising: Option<(Instant, Instant)>,
+}
+
+impl SimulatedScanner {
+    /// How long the strip stays empty before the fake devices come back
+    const RESPAWN_DELAY: Duration = Duration::from_secs(5);
+
+    /// How long the fake devices stay around, advertising often enough to be trusted as present,
+    /// before going quiet so they decay
+    const ADVERTISE_DURATION: Duration = Duration::from_secs(10);
+    const ADVERTISE_INTERVAL: Duration = Duration::from_millis(300);
+
+    /// The fake favorite device is in the tracker's group and shows us with `favorite_hue`
+    pub fn new(favorite_hue: u16) -> Self {
+        Self {
+            favorite_hue,
+            respawn_at: Some(Instant::default()),
+            advertising: None,
+        }
+    }
+
+    /// Call periodically, stands in for scan results
+    pub fn poll<C: Clock>(&mut self, device_mgr: &mut DeviceTracker<C>) {
+        let now = device_mgr.clock().now();
+        if device_mgr.devices.is_empty() {
+            self.advertising = None;
+            let respawn_at = *self.respawn_at.get_or_insert(now + Self::RESPAWN_DELAY);
+            if now < respawn_at {
+                return;
+            }
+            self.respawn_at = None;
+            self.advertising = Some((now + Self::ADVERTISE_DURATION, now));
+        }
+
+        match self.advertising {
+            Some((until, _)) if now >= until => self.advertising = None,
+            Some((until, next)) if now >= next => {
+                self.advertise(device_mgr);
+                self.advertising = Some((until, now + Self::ADVERTISE_INTERVAL));
+            }
+            _ => {}
+        }
+    }
+
+    fn advertise<C: Clock>(&self, device_mgr: &mut DeviceTracker<C>) {
+        // add fake devices
+        for i in 0..10 {
+            device_mgr.update(
+                BleAddress([i, 0x22, 0x33, 0x44, 0x55, 0x66]),
+                AddressType::Random,
+                &[],
+                -50 - i as i32 * 2,
+            );
+        }
+
+        // add fake favorite device
+        let favorite = FavoritePayload {
+            hue: self.favorite_hue,
+            ..device_mgr.advertisement()
+        };
+        device_mgr.update(
+            BleAddress([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
+            AddressType::Random,
+            &favorite.to_advertisement(),
+            -50,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ble_device_mgr::{FavoriteId, Pairing},
+        config::Config,
+        time::MockClock,
+    };
+
+    #[test]
+    fn simulated_devices_respawn() {
+        let clock = MockClock::new();
+        let mut device_mgr = DeviceTracker::new(
+            Pairing {
+                id: FavoriteId::new("PARTY_TIME").unwrap(),
+                hue: 0,
+            },
+            Config::default(),
+            clock.clone(),
+        );
+        let mut scanner = SimulatedScanner::new(0);
+
+        scanner.poll(&mut device_mgr);
+        assert_eq!(device_mgr.devices.len(), 11);
+        assert_eq!(
+            device_mgr
+                .devices
+                .iter()
+                .filter(|d| d.is_favorite())
+                .count(),
+            1
+        );
+
+        // they keep advertising for a while, so they are trusted as present
+        clock.advance(SimulatedScanner::ADVERTISE_INTERVAL);
+        scanner.poll(&mut device_mgr);
+        assert_eq!(device_mgr.devices[0].presence.count(), 2);
+        clock.advance(SimulatedScanner::ADVERTISE_DURATION);
+        scanner.poll(&mut device_mgr);
+        assert_eq!(device_mgr.devices[0].presence.count(), 2);
+
+        device_mgr.devices.clear();
+        scanner.poll(&mut device_mgr);
+        clock.advance(SimulatedScanner::RESPAWN_DELAY - Duration::from_millis(100));
+        scanner.poll(&mut device_mgr);
+        assert!(device_mgr.devices.is_empty());
+
+        clock.advance(Duration::from_millis(100));
+        scanner.poll(&mut device_mgr);
+        assert_eq!(device_mgr.devices.len(), 11);
+    }
+}
@@ -0,0 +1,324 @@
+//! Spotting BLE spam, e.g. from a Flipper Zero flooding phones with pairing popups. Spam shows up
+//! as a burst of short lived addresses, usually with payloads that trigger popups on phones.
+
+use core::time::Duration;
+
+use alloc::collections::VecDeque;
+use log::{info, warn};
+
+use crate::{
+    address::BleAddress,
+    advertisement::{
+        vendor::{ContinuityType, VendorInfo},
+        AdvertisementSummary,
+    },
+    time::Instant,
+};
+
+const COMPANY_MICROSOFT: u16 = 0x0006;
+const COMPANY_SAMSUNG: u16 = 0x0075;
+
+/// How far back new addresses are counted
+const SPAM_WINDOW: Duration = Duration::from_secs(5);
+
+/// New addresses with popup payloads within the window that make an attack
+const SPAM_SIGNATURE_THRESHOLD: usize = 8;
+
+/// New addresses of any kind within the window that make an attack, nothing legitimate churns
+/// through addresses this fast
+const SPAM_RATE_THRESHOLD: usize = 30;
+
+/// An attack is over once nothing has been blocked for this long
+const SPAM_QUIET_PERIOD: Duration = Duration::from_secs(10);
+
+/// Addresses silent for longer than this are forgotten, and are new again if they come back
+const SPAM_MEMORY: Duration = Duration::from_secs(30);
+
+/// Most addresses remembered, bounds memory during a flood
+const MAX_RECENT_ADDRESSES: usize = 256;
+
+/// Payloads that make phones show a popup, what spam tools send. Real devices send these too,
+/// so they only count towards an attack.
+pub fn is_spam_signature(advertisement: &AdvertisementSummary) -> bool {
+    match advertisement.vendor {
+        Some(VendorInfo::AppleContinuity(
+            ContinuityType::ProximityPairing | ContinuityType::NearbyAction,
+        )) => true,
+        Some(VendorInfo::FastPair(_)) => true,
+        // ordinary Windows PCs, other Microsoft payloads are likely Swift Pair which isn't decoded
+        Some(VendorInfo::MicrosoftCdp { .. }) => false,
+        _ => matches!(
+            advertisement.company_id,
+            Some(COMPANY_MICROSOFT | COMPANY_SAMSUNG)
+        ),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RecentAddress {
+    address: BleAddress,
+    first_seen: Instant,
+    last_seen: Instant,
+    signature: bool,
+    /// Part of the current attack
+    blocked: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Attack {
+    last_blocked: Instant,
+    /// Distinct addresses blocked so far
+    blocked: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct SpamDetector {
+    /// Addresses the tracker isn't showing, oldest first
+    recent: VecDeque<RecentAddress>,
+    attack: Option<Attack>,
+    /// Everything is new when scanning starts, so nothing counts until a window has passed
+    started: Option<Instant>,
+}
+
+impl SpamDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check an advertisement from an address the tracker isn't showing, returns `true` if it
+    /// should be blocked
+    pub fn check(
+        &mut self,
+        address: BleAddress,
+        advertisement: &AdvertisementSummary,
+        now: Instant,
+    ) -> bool {
+        let started = *self.started.get_or_insert(now);
+        self.expire(now);
+
+        let idx = match self
+            .recent
+            .iter()
+            .position(|recent| recent.address == address)
+        {
+            Some(idx) => {
+                self.recent[idx].last_seen = now;
+                idx
+            }
+            None => {
+                if self.recent.len() == MAX_RECENT_ADDRESSES {
+                    self.recent.pop_front();
+                }
+                self.recent.push_back(RecentAddress {
+                    address,
+                    first_seen: now,
+                    last_seen: now,
+                    signature: is_spam_signature(advertisement),
+                    blocked: false,
+                });
+
+                if self.attack.is_none()
+                    && now.duration_since(started) > SPAM_WINDOW
+                    && self.threshold_reached(now)
+                {
+                    warn!("BLE spam detected");
+                    self.attack = Some(Attack {
+                        last_blocked: now,
+                        blocked: 0,
+                    });
+                    // addresses let in before the attack was noticed are part of it too
+                    for idx in 0..self.recent.len() {
+                        self.block_if_suspect(idx, now);
+                    }
+                } else {
+                    self.block_if_suspect(self.recent.len() - 1, now);
+                }
+                self.recent.len() - 1
+            }
+        };
+
+        match self.attack.as_mut() {
+            Some(attack) if self.recent[idx].blocked => {
+                attack.last_blocked = now;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget old addresses and end an attack that has gone quiet
+    pub fn tick(&mut self, now: Instant) {
+        self.expire(now);
+        if let Some(attack) = self
+            .attack
+            .filter(|attack| now.duration_since(attack.last_blocked) > SPAM_QUIET_PERIOD)
+        {
+            info!("BLE spam over, {} addresses blocked", attack.blocked);
+            self.attack = None;
+            for recent in self.recent.iter_mut() {
+                recent.blocked = false;
+            }
+        }
+    }
+
+    /// Whether an address is part of the current attack, e.g. to remove devices that were let in
+    /// before the attack was noticed
+    pub fn is_suspect(&self, address: &BleAddress) -> bool {
+        self.attack.is_some()
+            && self
+                .recent
+                .iter()
+                .any(|recent| recent.address == *address && recent.blocked)
+    }
+
+    pub fn under_attack(&self) -> bool {
+        self.attack.is_some()
+    }
+
+    /// Addresses blocked during the current attack
+    pub fn blocked_count(&self) -> u32 {
+        self.attack.map_or(0, |attack| attack.blocked)
+    }
+
+    /// During an attack, new addresses with popup payloads are blocked, and any new address is
+    /// while they are arriving faster than anything legitimate would
+    fn block_if_suspect(&mut self, idx: usize, now: Instant) {
+        let rate_exceeded = self.new_addresses(now).count() >= SPAM_RATE_THRESHOLD;
+        let Some(attack) = self.attack.as_mut() else {
+            return;
+        };
+        let recent = &mut self.recent[idx];
+        if !recent.blocked
+            && now.duration_since(recent.first_seen) <= SPAM_WINDOW
+            && (recent.signature || rate_exceeded)
+        {
+            recent.blocked = true;
+            attack.blocked += 1;
+        }
+    }
+
+    fn threshold_reached(&self, now: Instant) -> bool {
+        let signatures = self.new_addresses(now).filter(|recent| recent.signature);
+        signatures.count() >= SPAM_SIGNATURE_THRESHOLD
+            || self.new_addresses(now).count() >= SPAM_RATE_THRESHOLD
+    }
+
+    /// Addresses first seen within the window
+    fn new_addresses(&self, now: Instant) -> impl Iterator<Item = &RecentAddress> {
+        self.recent
+            .iter()
+            .filter(move |recent| now.duration_since(recent.first_seen) <= SPAM_WINDOW)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.recent
+            .retain(|recent| now.duration_since(recent.last_seen) <= SPAM_MEMORY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advertisement::tests::{APPLE_NEARBY_INFO, FAST_PAIR, MICROSOFT_CDP};
+
+    /// AirPods pairing popup as sent by spam tools
+    const APPLE_POPUP: &[u8] = &[
+        0x1e, 0xff, 0x4c, 0x00, 0x07, 0x19, 0x07, 0x02, 0x20, 0x75, 0xaa, 0x30, 0x01, 0x00, 0x00,
+        0x45, 0x12, 0x12, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00,
+    ];
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_micros(millis * 1000)
+    }
+
+    fn addr(i: u16) -> BleAddress {
+        let [hi, lo] = i.to_be_bytes();
+        BleAddress([0x40 | hi, lo, 0x33, 0x44, 0x55, 0x66])
+    }
+
+    /// A detector past its warm up, having seen a few ordinary devices
+    fn detector() -> SpamDetector {
+        let mut detector = SpamDetector::new();
+        let phone = AdvertisementSummary::from_payload(APPLE_NEARBY_INFO);
+        for i in 0..5 {
+            assert!(!detector.check(addr(1000 + i), &phone, at(0)));
+        }
+        detector
+    }
+
+    /// One new address every `interval_ms` from `start_ms`, returns how many were blocked
+    fn flood(
+        detector: &mut SpamDetector,
+        payload: &[u8],
+        (start_ms, count, interval_ms): (u64, u16, u64),
+    ) -> usize {
+        let advertisement = AdvertisementSummary::from_payload(payload);
+        (0..count)
+            .filter(|&i| {
+                let now = at(start_ms + i as u64 * interval_ms);
+                detector.tick(now);
+                detector.check(addr(i), &advertisement, now)
+            })
+            .count()
+    }
+
+    #[test]
+    fn popup_payloads() {
+        let signature = |payload| is_spam_signature(&AdvertisementSummary::from_payload(payload));
+        assert!(signature(APPLE_POPUP));
+        assert!(signature(FAST_PAIR));
+        assert!(!signature(APPLE_NEARBY_INFO));
+        assert!(!signature(MICROSOFT_CDP));
+    }
+
+    #[test]
+    fn popup_flood_is_blocked() {
+        let mut detector = detector();
+
+        // 5 popups a second, the 8th one sets it off
+        assert_eq!(flood(&mut detector, APPLE_POPUP, (10_000, 50, 200)), 43);
+        assert!(detector.under_attack());
+        // the ones let in before count, the phones from before don't
+        assert_eq!(detector.blocked_count(), 50);
+        assert!(detector.is_suspect(&addr(0)));
+        assert!(!detector.is_suspect(&addr(1000)));
+
+        // blocked addresses stay blocked, without being counted again
+        let popup = AdvertisementSummary::from_payload(APPLE_POPUP);
+        assert!(detector.check(addr(0), &popup, at(20_000)));
+        assert_eq!(detector.blocked_count(), 50);
+
+        // a real device showing up during the attack isn't caught up in it
+        let phone = AdvertisementSummary::from_payload(APPLE_NEARBY_INFO);
+        assert!(!detector.check(addr(2000), &phone, at(20_000)));
+
+        // over once it goes quiet
+        detector.tick(at(29_000));
+        assert!(detector.under_attack());
+        detector.tick(at(31_000));
+        assert!(!detector.under_attack());
+        assert_eq!(detector.blocked_count(), 0);
+    }
+
+    #[test]
+    fn address_churn_is_blocked() {
+        let mut detector = detector();
+
+        // no popup payloads, just far too many new addresses
+        assert_eq!(flood(&mut detector, &[], (10_000, 60, 50)), 31);
+        assert_eq!(detector.blocked_count(), 60);
+    }
+
+    #[test]
+    fn busy_places_are_not_attacks() {
+        let mut detector = detector();
+
+        // a few popups now and then, e.g. people opening their AirPods cases
+        assert_eq!(flood(&mut detector, APPLE_POPUP, (10_000, 20, 2_000)), 0);
+        // everything is new when scanning starts
+        let mut detector = SpamDetector::new();
+        assert_eq!(flood(&mut detector, &[], (0, 40, 100)), 0);
+        assert!(!detector.under_attack());
+    }
+}
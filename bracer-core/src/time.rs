@@ -0,0 +1,85 @@
+use core::{
+    cell::Cell,
+    ops::{Add, AddAssign},
+    time::Duration,
+};
+
+use alloc::rc::Rc;
+
+/// A point on a monotonic clock, measured from an arbitrary epoch (usually boot).
+/// There is no clock in `no_std`, so callers supply these.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    pub const fn from_micros(micros: u64) -> Self {
+        Self(Duration::from_micros(micros))
+    }
+
+    pub fn as_micros(&self) -> u64 {
+        self.0.as_micros() as u64
+    }
+
+    /// Time elapsed since `earlier`, zero if `earlier` is actually later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Self(self.0 + rhs)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.0 += rhs;
+    }
+}
+
+/// Source of monotonic time
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// Only moves when told to, so time dependent behavior can be tested without sleeping.
+/// Clones share the same time.
+#[derive(Debug, Default, Clone)]
+pub struct MockClock(Rc<Cell<Instant>>);
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.0.set(self.0.get() + duration);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.0.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock_is_shared() {
+        let clock = MockClock::new();
+        let tracker_clock = clock.clone();
+
+        clock.advance(Duration::from_millis(1500));
+        assert_eq!(tracker_clock.now(), Instant::from_micros(1_500_000));
+        assert_eq!(
+            tracker_clock.now().duration_since(Instant::default()),
+            Duration::from_millis(1500)
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Whether a device is approaching or receding, from the slope of a straight line fitted to its
+//! recent signal strength samples
+
+use core::time::Duration;
+
+use crate::time::Instant;
+
+/// Samples kept per device, enough to fill the longest trend window at the usual advertising
+/// intervals
+const MAX_SAMPLES: usize = 32;
+
+/// Any fewer samples in the window and a trend can't be told from noise
+const MIN_SAMPLES: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Trend {
+    /// Positive while the signal gets stronger, i.e. the device is approaching
+    pub db_per_sec: f32,
+    /// In `0.0..=1.0`, how well a straight line fits the samples and how much of the window they
+    /// span
+    pub confidence: f32,
+}
+
+/// Timestamped signal strength samples, oldest overwritten first. Kept small since every tracked
+/// device has one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrendEstimator {
+    /// Wrapping milliseconds, see [`millis`]
+    times: [u32; MAX_SAMPLES],
+    signals: [i8; MAX_SAMPLES],
+    next: usize,
+    len: usize,
+}
+
+/// Wraps every 49 days, which is fine for comparing times seconds apart
+fn millis(at: Instant) -> u32 {
+    (at.as_micros() / 1000) as u32
+}
+
+impl Default for TrendEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TrendEstimator {
+    pub fn new() -> Self {
+        Self {
+            times: [0; MAX_SAMPLES],
+            signals: [0; MAX_SAMPLES],
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, signal: i32, now: Instant) {
+        self.times[self.next] = millis(now);
+        self.signals[self.next] = signal.clamp(i8::MIN as i32, 0) as i8;
+        self.next = (self.next + 1) % MAX_SAMPLES;
+        self.len = (self.len + 1).min(MAX_SAMPLES);
+    }
+
+    /// Least squares fit over the samples from the last `window`, no trend with too few of them
+    pub fn trend(&self, window: Duration, now: Instant) -> Trend {
+        let (now, window) = (millis(now), window.as_millis() as u32);
+        // (seconds relative to now, signal strength)
+        let recent = || {
+            self.times[..self.len]
+                .iter()
+                .zip(&self.signals)
+                .map(move |(&at, &signal)| (now.wrapping_sub(at), signal))
+                .filter(move |&(age, _)| age <= window)
+                .map(|(age, signal)| (-(age as f32) / 1000.0, signal as f32))
+        };
+        let n = recent().count();
+        if n < MIN_SAMPLES {
+            return Trend::default();
+        }
+
+        let n = n as f32;
+        let (sum_x, sum_y) = recent().fold((0.0, 0.0), |(x, y), (at, signal)| (x + at, y + signal));
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let (mut sxx, mut sxy, mut syy) = (0.0, 0.0, 0.0);
+        let (mut oldest, mut newest) = (0.0_f32, f32::NEG_INFINITY);
+        for (x, y) in recent() {
+            let (dx, dy) = (x - mean_x, y - mean_y);
+            sxx += dx * dx;
+            sxy += dx * dy;
+            syy += dy * dy;
+            oldest = oldest.min(x);
+            newest = newest.max(x);
+        }
+        if sxx <= 0.0 {
+            return Trend::default();
+        }
+
+        // a perfectly steady signal is a confident zero
+        let fit = if syy > 0.0 {
+            sxy * sxy / (sxx * syy)
+        } else {
+            1.0
+        };
+        let span = ((newest - oldest) * 1000.0 / window as f32).min(1.0);
+        Trend {
+            db_per_sec: sxy / sxx,
+            confidence: fit * span,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: Duration = Duration::from_secs(3);
+
+    /// Samples every 250ms for 4s, from `signal(secs)`
+    fn sampled(signal: impl Fn(f32) -> f32) -> (TrendEstimator, Instant) {
+        let mut trend = TrendEstimator::new();
+        let mut now = Instant::default();
+        for i in 0..16 {
+            now += Duration::from_millis(250);
+            trend.push(signal(i as f32 * 0.25).round() as i32, now);
+        }
+        (trend, now)
+    }
+
+    #[test]
+    fn approaching_and_receding() {
+        let (approaching, now) = sampled(|secs| -80.0 + 4.0 * secs);
+        let trend = approaching.trend(WINDOW, now);
+        assert!((trend.db_per_sec - 4.0).abs() < 0.2);
+        assert!(trend.confidence > 0.9);
+
+        let (receding, now) = sampled(|secs| -50.0 - 4.0 * secs);
+        assert!((receding.trend(WINDOW, now).db_per_sec + 4.0).abs() < 0.2);
+
+        let (steady, now) = sampled(|_| -60.0);
+        assert_eq!(
+            steady.trend(WINDOW, now),
+            Trend {
+                db_per_sec: 0.0,
+                confidence: 1.0
+            }
+        );
+    }
+
+    #[test]
+    fn noise_is_not_confident() {
+        // alternating +-5dB around a steady level
+        let (noisy, now) = sampled(|secs| {
+            -60.0
+                + if (secs * 4.0) as i32 % 2 == 0 {
+                    5.0
+                } else {
+                    -5.0
+                }
+        });
+        assert!(noisy.trend(WINDOW, now).confidence < 0.1);
+
+        // samples older than the window are ignored, and a quiet device has no trend
+        let (approaching, now) = sampled(|secs| -80.0 + 4.0 * secs);
+        assert_eq!(
+            approaching.trend(WINDOW, now + Duration::from_secs(4)),
+            Trend::default()
+        );
+        // just a few samples in the window
+        let trend = approaching.trend(Duration::from_millis(800), now);
+        assert!(trend.db_per_sec > 3.0);
+    }
+}
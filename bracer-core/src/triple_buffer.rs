@@ -0,0 +1,140 @@
+//! Lock free handoff of the latest value from one thread to another. The publisher and reader each
+//! own a buffer and swap it with a third shared one, so neither ever waits on the other and the
+//! reader always sees the most recently published value.
+
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use alloc::sync::Arc;
+
+/// Set on the shared buffer index when it holds a value the reader hasn't picked up yet
+const FRESH: u8 = 0b100;
+const INDEX: u8 = 0b011;
+
+struct Shared<T> {
+    buffers: [UnsafeCell<T>; 3],
+    /// Index of the buffer between publisher and reader, see [`FRESH`]
+    middle: AtomicU8,
+}
+
+// SAFETY: each buffer is only ever accessed by whichever side holds its index, and the indices
+// are exchanged atomically, so a buffer is never accessed from two threads at once
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+pub struct Publisher<T> {
+    shared: Arc<Shared<T>>,
+    back: u8,
+}
+
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    front: u8,
+}
+
+/// The reader sees `initial` until something is published
+pub fn triple_buffer<T: Clone>(initial: T) -> (Publisher<T>, Reader<T>) {
+    let shared = Arc::new(Shared {
+        buffers: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        middle: AtomicU8::new(1),
+    });
+    (
+        Publisher {
+            shared: shared.clone(),
+            back: 0,
+        },
+        Reader { shared, front: 2 },
+    )
+}
+
+impl<T> Publisher<T> {
+    /// Fill in the next value and hand it to the reader. The buffer still holds an older value,
+    /// so it can be updated in place to reuse its allocations.
+    pub fn publish_with(&mut self, write: impl FnOnce(&mut T)) {
+        // SAFETY: the back buffer is only accessed by the publisher, see `Shared`
+        write(unsafe { &mut *self.shared.buffers[self.back as usize].get() });
+        let previous = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = previous & INDEX;
+    }
+
+    pub fn publish(&mut self, value: T) {
+        self.publish_with(|buffer| *buffer = value);
+    }
+}
+
+impl<T> Reader<T> {
+    /// The most recently published value, never waits on the publisher
+    pub fn latest(&mut self) -> &T {
+        if self.shared.middle.load(Ordering::Relaxed) & FRESH != 0 {
+            let previous = self.shared.middle.swap(self.front, Ordering::AcqRel);
+            self.front = previous & INDEX;
+        }
+        // SAFETY: the front buffer is only accessed by the reader, see `Shared`
+        unsafe { &*self.shared.buffers[self.front as usize].get() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, thread};
+
+    #[test]
+    fn reader_sees_latest() {
+        let (mut publisher, mut reader) = triple_buffer(0);
+        assert_eq!(*reader.latest(), 0);
+
+        publisher.publish(1);
+        publisher.publish(2);
+        assert_eq!(*reader.latest(), 2);
+        assert_eq!(*reader.latest(), 2);
+
+        // buffers are reused, so writes can build on an older value
+        publisher.publish_with(|value| *value += 10);
+        assert!([10, 11].contains(reader.latest()));
+    }
+
+    #[test]
+    fn reader_never_waits_on_publisher() {
+        let (mut publisher, mut reader) = triple_buffer(alloc::vec![0_u32; 64]);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (finish_tx, finish_rx) = mpsc::channel::<()>();
+
+        // a slow publish that only finishes once the reader has been able to read
+        let publisher_thread = thread::spawn(move || {
+            publisher.publish(alloc::vec![1; 64]);
+            publisher.publish_with(|value| {
+                started_tx.send(()).unwrap();
+                finish_rx.recv().unwrap();
+                value.fill(2);
+            });
+            publisher
+        });
+
+        started_rx.recv().unwrap();
+        assert!(reader.latest().iter().all(|&value| value == 1));
+        finish_tx.send(()).unwrap();
+        let mut publisher = publisher_thread.join().unwrap();
+        assert!(reader.latest().iter().all(|&value| value == 2));
+
+        // values are never torn, whatever the interleaving
+        let publisher_thread = thread::spawn(move || {
+            for i in 3..2000 {
+                publisher.publish_with(|value| value.fill(i));
+            }
+        });
+        let mut last = 2;
+        while last < 1999 {
+            let value = reader.latest();
+            assert!(value.iter().all(|&v| v == value[0]));
+            assert!(value[0] >= last);
+            last = value[0];
+        }
+        publisher_thread.join().unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+use core::time::Duration;
+
+use crate::time::Instant;
+
+/// Moving average over the last `window` samples, `N` is the largest window it can hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MovingAvg<const N: usize> {
+    data: [i32; N],
+    window: usize,
+    num_samples: usize,
+}
+
+impl<const N: usize> Default for MovingAvg<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> MovingAvg<N> {
+    pub fn new() -> Self {
+        Self::with_window(N)
+    }
+
+    /// Average over fewer samples than the capacity, `window` is clamped to `1..=N`
+    pub fn with_window(window: usize) -> Self {
+        Self {
+            data: [0; N],
+            window: window.clamp(1, N),
+            num_samples: 0,
+        }
+    }
+
+    /// Change the window size, keeping the most recent samples
+    pub fn set_window(&mut self, window: usize) {
+        self.window = window.clamp(1, N);
+        self.num_samples = self.num_samples.min(self.window);
+    }
+
+    /// Push a new value into the moving average
+    pub fn push(&mut self, val: i32) {
+        self.data[..self.window].rotate_right(1);
+        self.data[0] = val;
+        self.num_samples = self.window.min(self.num_samples + 1);
+    }
+
+    pub fn peek_last(&self) -> i32 {
+        self.data[0]
+    }
+
+    /// Samples in the window, newest first
+    pub fn samples(&self) -> &[i32] {
+        &self.data[..self.num_samples]
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    pub fn get_avg(&self) -> i32 {
+        self.data.iter().take(self.num_samples).sum::<i32>() / self.num_samples as i32
+    }
+}
+
+/// Constant velocity (alpha-beta) filter, smooths a noisy signal and predicts it between samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlphaBetaFilter {
+    alpha: f32,
+    beta: f32,
+    /// Never extrapolate further than this past the last sample
+    max_extrapolation: Duration,
+    estimate: f32,
+    /// Rate of change, per second
+    velocity: f32,
+    last_update: Instant,
+}
+
+impl AlphaBetaFilter {
+    pub fn new(
+        alpha: f32,
+        beta: f32,
+        max_extrapolation: Duration,
+        initial: f32,
+        now: Instant,
+    ) -> Self {
+        Self {
+            alpha,
+            beta,
+            max_extrapolation,
+            estimate: initial,
+            velocity: 0.0,
+            last_update: now,
+        }
+    }
+
+    fn elapsed_secs(&self, now: Instant) -> f32 {
+        now.duration_since(self.last_update)
+            .min(self.max_extrapolation)
+            .as_secs_f32()
+    }
+
+    /// Correct the estimate with a new sample
+    pub fn update(&mut self, val: f32, now: Instant) {
+        let dt = self.elapsed_secs(now);
+        let predicted = self.predict(now);
+        let residual = val - predicted;
+
+        self.estimate = predicted + self.alpha * residual;
+        if dt > 0.0 {
+            self.velocity += self.beta * residual / dt;
+        }
+        self.last_update = now;
+    }
+
+    /// Estimated value at `now`
+    pub fn predict(&self, now: Instant) -> f32 {
+        self.estimate + self.velocity * self.elapsed_secs(now)
+    }
+}
+
+/// How far the time between ticks of a periodic loop strays from the intended interval
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JitterStats {
+    interval: Duration,
+    last_tick: Option<Instant>,
+    ticks: u32,
+    total: Duration,
+    /// Largest deviation either way
+    pub max: Duration,
+    /// Ticks that came at least a whole interval late, i.e. a frame was dropped
+    pub missed: u32,
+}
+
+impl JitterStats {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_tick: None,
+            ticks: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+            missed: 0,
+        }
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        if let Some(last_tick) = self.last_tick.replace(now) {
+            let elapsed = now.duration_since(last_tick);
+            let jitter = elapsed.max(self.interval) - elapsed.min(self.interval);
+            self.ticks += 1;
+            self.total += jitter;
+            self.max = self.max.max(jitter);
+            if elapsed >= 2 * self.interval {
+                self.missed += 1;
+            }
+        }
+    }
+
+    pub fn mean(&self) -> Duration {
+        self.total.checked_div(self.ticks).unwrap_or_default()
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    /// Start a new measurement period, keeping the last tick so no interval is lost
+    pub fn reset(&mut self) {
+        *self = Self {
+            last_tick: self.last_tick,
+            ..Self::new(self.interval)
+        };
+    }
+}
+
+pub fn num_linear_conversion(
+    val: f32,
+    in_min: f32,
+    in_max: f32,
+    out_min: f32,
+    out_max: f32,
+) -> f32 {
+    let val = num::clamp(val, in_min, in_max);
+    ((val - in_min) / (in_max - in_min)) * (out_max - out_min) + out_min
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_moving_avg() {
+        let mut avg = MovingAvg::<3>::new();
+        avg.push(1);
+        avg.push(2);
+        avg.push(3);
+        assert_eq!(avg.get_avg(), 2);
+        avg.push(4);
+        assert_eq!(avg.get_avg(), 3);
+        avg.push(5);
+        assert_eq!(avg.get_avg(), 4);
+        avg.push(6);
+        assert_eq!(avg.get_avg(), 5);
+    }
+
+    #[test]
+    fn moving_avg_window_resize() {
+        let mut avg = MovingAvg::<5>::with_window(2);
+        avg.push(1);
+        avg.push(2);
+        avg.push(3);
+        assert_eq!(avg.get_avg(), 2);
+        avg.set_window(4);
+        avg.push(5);
+        assert_eq!(avg.get_avg(), 3);
+        avg.set_window(1);
+        assert_eq!(avg.get_avg(), 5);
+    }
+
+    #[test]
+    fn peek_last() {
+        let mut avg = MovingAvg::<3>::new();
+        avg.push(1);
+        assert_eq!(avg.peek_last(), 1);
+        avg.push(2);
+        assert_eq!(avg.peek_last(), 2);
+        avg.push(3);
+        assert_eq!(avg.peek_last(), 3);
+        avg.push(4);
+        assert_eq!(avg.peek_last(), 4);
+        avg.push(5);
+        assert_eq!(avg.peek_last(), 5);
+        avg.push(6);
+        assert_eq!(avg.peek_last(), 6);
+    }
+
+    #[test]
+    fn alpha_beta_filter() {
+        let start = Instant::default();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut filter = AlphaBetaFilter::new(0.5, 0.1, Duration::from_secs(1), -70.0, start);
+
+        // track a signal rising by 10 per second, sampled every 100ms
+        for i in 1..=50 {
+            filter.update(-70.0 + i as f32, at(i * 100));
+        }
+        assert!((filter.predict(at(5000)) - -20.0).abs() < 0.5);
+
+        // between samples the value keeps moving
+        assert!((filter.predict(at(5050)) - -19.5).abs() < 0.5);
+
+        // but it is never extrapolated past the limit
+        assert_eq!(filter.predict(at(6000)), filter.predict(at(60000)));
+    }
+
+    #[test]
+    fn jitter_stats() {
+        let start = Instant::default();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut stats = JitterStats::new(Duration::from_millis(10));
+
+        for ms in [0, 10, 22, 30, 55, 60] {
+            stats.record(at(ms));
+        }
+        assert_eq!(stats.ticks(), 5);
+        assert_eq!(stats.max, Duration::from_millis(15));
+        assert_eq!(stats.mean(), Duration::from_micros(4800));
+        assert_eq!(stats.missed, 1);
+
+        stats.reset();
+        assert_eq!(stats.mean(), Duration::ZERO);
+        stats.record(at(70));
+        assert_eq!(stats.ticks(), 1);
+        assert_eq!(stats.max, Duration::ZERO);
+    }
+}
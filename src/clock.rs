@@ -0,0 +1,11 @@
+use bracer_core::time::{Clock, Instant};
+
+/// Time since boot, from the ESP high resolution timer
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::from_micros(unsafe { esp_idf_sys::esp_timer_get_time() } as u64)
+    }
+}
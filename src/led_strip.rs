@@ -6,38 +6,14 @@ use esp_idf_sys::{
     rmt_carrier_level_t_RMT_CARRIER_LEVEL_LOW, rmt_channel_t, rmt_config_t,
     rmt_config_t__bindgen_ty_1, rmt_item32_t, rmt_mode_t_RMT_MODE_TX, rmt_tx_config_t, ESP_OK,
 };
-use palette::{FromColor, Hsv, Srgb};
+
+use bracer_core::pixel_sink::{Color, PixelSink};
 
 const WS2812_TO0H_NS: u32 = 400;
 const WS2812_TO0L_NS: u32 = 850;
 const WS2812_TO1H_NS: u32 = 800;
 const WS2812_TO1L_NS: u32 = 450;
 
-#[derive(Copy, Clone)]
-pub struct Color {
-    pub r: u8,
-    pub g: u8,
-    pub b: u8,
-}
-
-impl Color {
-    pub fn new(r: u8, g: u8, b: u8) -> Color {
-        Color { r, g, b }
-    }
-}
-
-impl From<Hsv> for Color {
-    fn from(hsv: Hsv) -> Self {
-        let rgb = Srgb::from_color(hsv);
-
-        Color {
-            r: (rgb.red * 255.0) as u8,
-            g: (rgb.green * 255.0) as u8,
-            b: (rgb.blue * 255.0) as u8,
-        }
-    }
-}
-
 #[derive(Debug)]
 pub struct EspError {
     inner: esp_err_t,
@@ -61,7 +37,6 @@ pub fn esp_res(err_code: esp_err_t) -> Result<(), EspError> {
 
 // #[derive(Debug)]
 pub struct LedStrip<const NUM_LEDS: usize> {
-    pub colors: [Color; NUM_LEDS],
     pub channel: rmt_channel_t,
     bit0: rmt_item32_t,
     bit1: rmt_item32_t,
@@ -117,7 +92,6 @@ impl<const NUM_LEDS: usize> LedStrip<NUM_LEDS> {
         let bit1 = Self::create_rmt_item32(one_high_ticks, 1, one_low_ticks, 0);
 
         Ok(LedStrip {
-            colors: [Color::new(0, 0, 0); NUM_LEDS],
             channel,
             bit0,
             bit1,
@@ -164,10 +138,12 @@ impl<const NUM_LEDS: usize> Drop for LedStrip<NUM_LEDS> {
     }
 }
 
-impl<const NUM_LEDS: usize> LedStrip<NUM_LEDS> {
-    pub fn update(&mut self) -> Result<(), EspError> {
+impl<const NUM_LEDS: usize> PixelSink for LedStrip<NUM_LEDS> {
+    type Error = EspError;
+
+    fn update(&mut self, colors: &[Color]) -> Result<(), EspError> {
         let mut num = 0;
-        for color in self.colors {
+        for color in colors.iter().take(NUM_LEDS) {
             for byte in [color.g, color.r, color.b] {
                 for i in 0..8 {
                     let bit = byte & (1 << (7 - i));
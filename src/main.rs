@@ -1,9 +1,11 @@
 //! Runtime tasks:
-//! - BLE scan
+//! - BLE scan, advertising and configuration service
 //! - LED control
-//! - BLE decay
+//! - Settings writer
+//! - Tracker updates from LED control
 //! - Input monitor
-//! - TODO: interpolate device signal strength
+//! - Device event logger
+//! - BLE signal interpolation (driven by LED control, from the snapshots the tracker publishes)
 //!
 //! TODO: Remove floating point math
 
@@ -15,12 +17,30 @@ use esp_idf_sys as _;
 use log::*;
 use std::sync::Mutex;
 
-mod ble_device_mgr;
+mod clock;
 mod led_strip;
-mod light_mgr;
-mod messages;
+mod nvs_storage;
 mod tasks;
-mod utils;
+
+/// Pairing ID for favorite device, until changed over the configuration service
+pub const FAVORITE_DEVICE_ID: &str = env!("FAVORITE_DEVICE_ID");
+
+/// Hue the favorite device shows us with, until changed over the configuration service
+const FAVORITE_COLOR: &str = env!("FAVORITE_COLOR");
+
+/// Passkey, 6 digits, a phone must enter to pair before it can change the configuration
+const CONFIG_PASSKEY: &str = env!("CONFIG_PASSKEY");
+
+/// Room for a burst of configuration writes while the animator is busy
+const LIGHT_CONTROLS_CHANNEL_SIZE: usize = 4;
+
+/// Room for the configuration writes the animator forwards, and a display state change
+const TRACKER_UPDATES_CHANNEL_SIZE: usize = 8;
+
+/// Room for a scan's worth of device events while the logger catches up
+const DEVICE_EVENTS_CHANNEL_SIZE: usize = 64;
+
+pub type DeviceTracker = bracer_core::ble_device_mgr::DeviceTracker<clock::SystemClock>;
 
 fn main() {
     // It is necessary to call this function once. Otherwise some patches to the runtime
@@ -41,21 +61,122 @@ fn main() {
         .unwrap();
     }
 
-    let device_mgr = Arc::new(Mutex::new(ble_device_mgr::DeviceTracker::new()));
+    let nvs_partition = esp_idf_svc::nvs::EspDefaultNvsPartition::take().unwrap();
+
+    let config_store = match nvs_storage::NvsConfigStore::new(nvs_partition.clone()) {
+        Ok(store) => Some(store),
+        Err(err) => {
+            warn!("Unable to open NVS, config changes won't be saved: {}", err);
+            None
+        }
+    };
+    let config = config_store
+        .as_ref()
+        .map(|store| store.load())
+        .unwrap_or_default();
+
+    let rules_store = match nvs_storage::NvsRulesStore::new(nvs_partition.clone()) {
+        Ok(store) => Some(store),
+        Err(err) => {
+            warn!("Unable to open NVS, rule changes won't be saved: {}", err);
+            None
+        }
+    };
+
+    let pairing_store = match nvs_storage::NvsPairingStore::new(nvs_partition.clone()) {
+        Ok(store) => Some(store),
+        Err(err) => {
+            warn!(
+                "Unable to open NVS, pairing changes won't be saved: {}",
+                err
+            );
+            None
+        }
+    };
+    let color_store = match nvs_storage::NvsColorBackend::new(nvs_partition.clone()) {
+        Ok(store) => Some(store),
+        Err(err) => {
+            warn!("Unable to open NVS, device colors won't be saved: {}", err);
+            None
+        }
+    };
+
+    let pairing = pairing_store
+        .as_ref()
+        .and_then(|store| store.load())
+        .unwrap_or_else(|| bracer_core::ble_device_mgr::Pairing {
+            id: bracer_core::ble_device_mgr::FavoriteId::new(FAVORITE_DEVICE_ID)
+                .expect("FAVORITE_DEVICE_ID must be 1-16 bytes"),
+            hue: FAVORITE_COLOR
+                .parse()
+                .expect("FAVORITE_COLOR must be a hue in degrees"),
+        });
+    let config_passkey = CONFIG_PASSKEY
+        .parse()
+        .ok()
+        .filter(|passkey| *passkey <= 999_999)
+        .expect("CONFIG_PASSKEY must be up to 6 digits");
+
+    let mut device_mgr = DeviceTracker::new(pairing, config, clock::SystemClock);
+    if let Some(rules_store) = &rules_store {
+        device_mgr.set_rules(rules_store.load());
+    }
+    let (device_events_chan_tx, device_events_chan_rx) =
+        smol::channel::bounded(DEVICE_EVENTS_CHANNEL_SIZE);
+    device_mgr.subscribe(Box::new(tasks::DeviceEventChannel(device_events_chan_tx)));
+    let snapshots = device_mgr.publish_snapshots();
+    let device_mgr = Arc::new(Mutex::new(device_mgr));
+
+    let (light_controls_chan_tx, light_controls_chan_rx) =
+        smol::channel::bounded(LIGHT_CONTROLS_CHANNEL_SIZE);
+    let (tracker_chan_tx, tracker_chan_rx) = smol::channel::bounded(TRACKER_UPDATES_CHANNEL_SIZE);
 
-    let (light_controls_chan_tx, light_controls_chan_rx) = smol::channel::bounded(1);
+    // Separate thread so flash writes don't hold up the animation
+    let (settings_chan_tx, settings_chan_rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        tasks::settings_writer(
+            settings_chan_rx,
+            config_store,
+            rules_store,
+            pairing_store,
+            color_store,
+        )
+    });
 
-    // Separate thread for tighter timing
-    let led_animate_device_mgr = device_mgr.clone();
-    std::thread::spawn(move || tasks::led_animator(led_animate_device_mgr, light_controls_chan_rx));
+    // Separate thread for tighter timing, it never locks the tracker
+    std::thread::spawn(move || {
+        tasks::led_animator(
+            snapshots,
+            light_controls_chan_rx,
+            tracker_chan_tx,
+            nvs_partition,
+            settings_chan_tx.clone(),
+        )
+    });
 
     smol::block_on(async {
         info!("Starting BLE scanner task");
-        let ble_scan_task = smol::spawn(tasks::ble_scanner(device_mgr.clone()));
-        let ble_decayer_task = smol::spawn(tasks::ble_device_decayer(device_mgr.clone()));
+        let ble_scan_task = smol::spawn(tasks::ble_scanner(
+            device_mgr.clone(),
+            light_controls_chan_tx.clone(),
+            config_passkey,
+        ));
         let button_monitor_task = smol::spawn(tasks::button_monitor(light_controls_chan_tx));
+        let device_event_logger_task =
+            smol::spawn(tasks::device_event_logger(device_events_chan_rx));
+        let tracker_updater_task = smol::spawn(tasks::tracker_updater(
+            device_mgr.clone(),
+            tracker_chan_rx,
+            settings_chan_tx,
+        ));
 
-        futures::future::select_all([ble_scan_task, ble_decayer_task, button_monitor_task]).await;
+        futures::future::select_all([
+            ble_scan_task,
+            button_monitor_task,
+            device_event_logger_task,
+            tracker_updater_task,
+        ])
+        .await;
         error!("One of the tasks has exited unexpectedly. Exiting...")
     });
 
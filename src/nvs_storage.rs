@@ -0,0 +1,173 @@
+use bracer_core::{
+    ble_device_mgr::Pairing,
+    color_store::ColorStoreBackend,
+    config::{self, Config},
+    rules::{self, Rules},
+};
+use log::{info, warn};
+
+/// Saves device colors into the NVS partition so they survive a reboot
+pub struct NvsColorBackend {
+    nvs: esp_idf_svc::nvs::EspDefaultNvs,
+}
+
+impl NvsColorBackend {
+    const NAMESPACE: &'static str = "colors";
+    const KEY: &'static str = "hues";
+
+    pub fn new(
+        partition: esp_idf_svc::nvs::EspDefaultNvsPartition,
+    ) -> Result<Self, esp_idf_sys::EspError> {
+        Ok(Self {
+            nvs: esp_idf_svc::nvs::EspNvs::new(partition, Self::NAMESPACE, true)?,
+        })
+    }
+}
+
+impl ColorStoreBackend for NvsColorBackend {
+    fn load(&mut self, buf: &mut [u8]) -> Option<usize> {
+        match self.nvs.get_raw(Self::KEY, buf) {
+            Ok(data) => data.map(|data| data.len()),
+            Err(err) => {
+                warn!("Failed to load device colors: {}", err);
+                None
+            }
+        }
+    }
+
+    fn save(&mut self, data: &[u8]) {
+        use embedded_svc::storage::RawStorage;
+
+        if let Err(err) = self.nvs.set_raw(Self::KEY, data) {
+            warn!("Failed to save device colors: {}", err);
+        }
+    }
+}
+
+/// Tracker and display parameters, stored so tuning survives a reboot
+pub struct NvsConfigStore {
+    nvs: esp_idf_svc::nvs::EspDefaultNvs,
+}
+
+impl NvsConfigStore {
+    const NAMESPACE: &'static str = "config";
+    const KEY: &'static str = "config";
+
+    pub fn new(
+        partition: esp_idf_svc::nvs::EspDefaultNvsPartition,
+    ) -> Result<Self, esp_idf_sys::EspError> {
+        Ok(Self {
+            nvs: esp_idf_svc::nvs::EspNvs::new(partition, Self::NAMESPACE, true)?,
+        })
+    }
+
+    /// Stored config, or defaults if none is stored or it can't be used
+    pub fn load(&self) -> Config {
+        let mut buf = [0; config::SERIALIZED_LEN];
+        match self.nvs.get_raw(Self::KEY, &mut buf) {
+            Ok(Some(data)) => match Config::from_bytes(data) {
+                Ok(config) => return config,
+                Err(err) => warn!("Ignoring stored config: {}", err),
+            },
+            Ok(None) => info!("No stored config, using defaults"),
+            Err(err) => warn!("Failed to load config: {}", err),
+        }
+        Config::default()
+    }
+
+    pub fn save(&mut self, config: &Config) {
+        use embedded_svc::storage::RawStorage;
+
+        if let Err(err) = self.nvs.set_raw(Self::KEY, &config.to_bytes()) {
+            warn!("Failed to save config: {}", err);
+        }
+    }
+}
+
+/// User rules for scanned devices, stored so they survive a reboot
+pub struct NvsRulesStore {
+    nvs: esp_idf_svc::nvs::EspDefaultNvs,
+}
+
+impl NvsRulesStore {
+    const NAMESPACE: &'static str = "rules";
+    const KEY: &'static str = "rules";
+
+    pub fn new(
+        partition: esp_idf_svc::nvs::EspDefaultNvsPartition,
+    ) -> Result<Self, esp_idf_sys::EspError> {
+        Ok(Self {
+            nvs: esp_idf_svc::nvs::EspNvs::new(partition, Self::NAMESPACE, true)?,
+        })
+    }
+
+    /// Stored rules, or none if none are stored or they can't be used
+    pub fn load(&self) -> Rules {
+        let mut buf = [0; rules::MAX_SERIALIZED_LEN];
+        match self.nvs.get_raw(Self::KEY, &mut buf) {
+            Ok(Some(data)) => match Rules::from_bytes(data) {
+                Ok(rules) => return rules,
+                Err(err) => warn!("Ignoring stored rules: {}", err),
+            },
+            Ok(None) => info!("No stored rules"),
+            Err(err) => warn!("Failed to load rules: {}", err),
+        }
+        Rules::new()
+    }
+
+    pub fn save(&mut self, rules: &Rules) {
+        use embedded_svc::storage::RawStorage;
+
+        if let Err(err) = self.nvs.set_raw(Self::KEY, &rules.to_bytes()) {
+            warn!("Failed to save rules: {}", err);
+        }
+    }
+}
+
+/// Favorite pairing set over the configuration service, stored so it survives a reboot
+pub struct NvsPairingStore {
+    nvs: esp_idf_svc::nvs::EspDefaultNvs,
+}
+
+impl NvsPairingStore {
+    const NAMESPACE: &'static str = "pairing";
+    const KEY: &'static str = "pairing";
+
+    pub fn new(
+        partition: esp_idf_svc::nvs::EspDefaultNvsPartition,
+    ) -> Result<Self, esp_idf_sys::EspError> {
+        Ok(Self {
+            nvs: esp_idf_svc::nvs::EspNvs::new(partition, Self::NAMESPACE, true)?,
+        })
+    }
+
+    /// Stored pairing, `None` if none is stored or it can't be used
+    pub fn load(&self) -> Option<Pairing> {
+        let mut buf = [0; Pairing::MAX_SERIALIZED_LEN];
+        match self.nvs.get_raw(Self::KEY, &mut buf) {
+            Ok(Some(data)) => {
+                let pairing = Pairing::from_bytes(data);
+                if pairing.is_none() {
+                    warn!("Ignoring stored pairing");
+                }
+                pairing
+            }
+            Ok(None) => {
+                info!("No stored pairing");
+                None
+            }
+            Err(err) => {
+                warn!("Failed to load pairing: {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&mut self, pairing: &Pairing) {
+        use embedded_svc::storage::RawStorage;
+
+        if let Err(err) = self.nvs.set_raw(Self::KEY, &pairing.to_bytes()) {
+            warn!("Failed to save pairing: {}", err);
+        }
+    }
+}
@@ -5,25 +5,148 @@ use esp_idf_hal::{
     prelude::Peripherals,
 };
 use log::*;
-use smol::stream::StreamExt;
 use std::sync::Mutex;
 
 const DEBOUNCE_TIME_MS: u64 = 5;
 
+/// How soon after one brightness button the other must be pressed to count as both together
+const BUTTON_COMBO_WINDOW: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Name we advertise, never the pairing ID, which anyone in range could read and join our group with
+const ADVERTISED_NAME: &str = "Bracer";
+
+/// How many device colors to remember, least recently seen are forgotten first
+const COLOR_STORE_CAPACITY: usize = 64;
+
+/// How often the LED animator logs how steadily it is ticking
+const JITTER_REPORT_PERIOD: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// NimBLE keeps address bytes least significant first, the core keeps them in display order
 #[cfg(not(feature = "simulator"))]
-pub async fn ble_scanner(device_mgr: Arc<Mutex<crate::ble_device_mgr::DeviceTracker>>) {
-    use esp32_nimble::BLEDevice;
+fn to_core_address(
+    addr: &esp32_nimble::BLEAddress,
+) -> (
+    bracer_core::address::BleAddress,
+    bracer_core::address::AddressType,
+) {
+    use bracer_core::address::AddressType;
+    use esp32_nimble::BLEAddressType;
+
+    let mut bytes = addr.as_le_bytes();
+    bytes.reverse();
+    let addr_type = match addr.addr_type() {
+        BLEAddressType::Public | BLEAddressType::PublicID => AddressType::Public,
+        BLEAddressType::Random | BLEAddressType::RandomID => AddressType::Random,
+    };
+    (bracer_core::address::BleAddress(bytes), addr_type)
+}
+
+/// Characteristic values the configuration service shows for the tracker's current state
+#[cfg(not(feature = "simulator"))]
+fn config_service_values(
+    device_mgr: &crate::DeviceTracker,
+) -> Vec<(bracer_core::config_service::ConfigCharacteristic, Vec<u8>)> {
+    use bracer_core::{config_service::ConfigCharacteristic, messages::LightControls};
+
+    let (pairing, config, advertisement) = (
+        device_mgr.pairing(),
+        device_mgr.config(),
+        device_mgr.advertisement(),
+    );
+    [
+        LightControls::BrightnessChange(advertisement.brightness),
+        LightControls::ModeChange(advertisement.mode),
+        LightControls::SignalThresholdsChange {
+            ignore_below: config.signal_ignore_below as i8,
+            ignore_above: config.signal_ignore_above as i8,
+        },
+        LightControls::FavoriteIdChange(pairing.id),
+        LightControls::FavoriteHueChange(pairing.hue),
+        LightControls::ConfigChange(*config),
+    ]
+    .iter()
+    .filter_map(ConfigCharacteristic::encode)
+    .chain([(ConfigCharacteristic::Rules, device_mgr.rules().to_bytes())])
+    .collect()
+}
+
+/// Register the configuration service, writes are forwarded to the LED animator. Only clients that
+/// paired with our passkey can write, or read the favorite ID since it is our group's secret.
+/// Must happen before advertising starts, which also starts the GATT server.
+#[cfg(not(feature = "simulator"))]
+fn start_config_service(
+    ble_device: &esp32_nimble::BLEDevice,
+    light_controls_chan: smol::channel::Sender<bracer_core::messages::LightControls>,
+) -> Vec<(
+    bracer_core::config_service::ConfigCharacteristic,
+    Arc<esp32_nimble::utilities::mutex::Mutex<esp32_nimble::BLECharacteristic>>,
+)> {
+    use bracer_core::config_service::{ConfigCharacteristic, SERVICE_UUID};
+    use esp32_nimble::{utilities::BleUuid, NimbleProperties};
+
+    let service = ble_device
+        .get_server()
+        .create_service(BleUuid::from_uuid128(SERVICE_UUID.to_le_bytes()));
+
+    ConfigCharacteristic::ALL
+        .into_iter()
+        .map(|characteristic| {
+            let mut properties = NimbleProperties::READ
+                | NimbleProperties::NOTIFY
+                | NimbleProperties::WRITE
+                | NimbleProperties::WRITE_AUTHEN;
+            if characteristic == ConfigCharacteristic::FavoriteId {
+                properties |= NimbleProperties::READ_AUTHEN;
+            }
+            let ble_characteristic = service.lock().create_characteristic(
+                BleUuid::from_uuid128(characteristic.uuid().to_le_bytes()),
+                properties,
+            );
+
+            let light_controls_chan = light_controls_chan.clone();
+            ble_characteristic.lock().on_write(move |data, _| {
+                match characteristic.decode_write(data) {
+                    Ok(msg) => {
+                        if let Err(err) = light_controls_chan.try_send(msg) {
+                            warn!("Dropped {:?} config write: {}", characteristic, err);
+                        }
+                    }
+                    Err(err) => warn!("Rejected {:?} config write: {}", characteristic, err),
+                }
+            });
+            (characteristic, ble_characteristic)
+        })
+        .collect()
+}
+
+/// `config_passkey` is what a client must enter when pairing to change the configuration, we have
+/// no display to show a random one on
+#[cfg(not(feature = "simulator"))]
+pub async fn ble_scanner(
+    device_mgr: Arc<Mutex<crate::DeviceTracker>>,
+    light_controls_chan: smol::channel::Sender<bracer_core::messages::LightControls>,
+    config_passkey: u32,
+) {
+    use esp32_nimble::{enums::SecurityIOCap, BLEDevice};
 
     let ble_device = BLEDevice::take();
+    ble_device
+        .security()
+        .set_auth(true, true, true)
+        .set_passkey(config_passkey)
+        .set_io_cap(SecurityIOCap::DisplayOnly);
+
+    let mut advertised = device_mgr.lock().unwrap().advertisement();
 
-    // Set up advertising
+    // Set up the configuration service, its values are filled in by the scan loop
+    let config_characteristics = start_config_service(ble_device, light_controls_chan);
+    let mut config_values = Vec::new();
+
+    // Set up advertising, the name goes in the scan response if it doesn't fit
     ble_device
         .get_advertising()
-        .name(const_format::concatcp!(
-            crate::ble_device_mgr::FAVORITE_DEVICE_ID,
-            ':',
-            env!("FAVORITE_COLOR")
-        ))
+        .name(ADVERTISED_NAME)
+        .manufacturer_data(&advertised.encode())
         .scan_response(true)
         .start()
         .unwrap();
@@ -37,7 +160,43 @@ pub async fn ble_scanner(device_mgr: Arc<Mutex<crate::ble_device_mgr::DeviceTrac
         .filter_duplicates(true);
 
     loop {
-        let device_mgr = device_mgr.clone();
+        // re-advertise if the pairing, brightness or mode changed, and show clients what changed
+        let (advertisement, values) = {
+            let device_mgr = device_mgr.lock().unwrap();
+            (
+                device_mgr.advertisement(),
+                config_service_values(&device_mgr),
+            )
+        };
+        for (characteristic, value) in values
+            .iter()
+            .filter(|&value| !config_values.contains(value))
+        {
+            if let Some((_, ble_characteristic)) = config_characteristics
+                .iter()
+                .find(|(c, _)| c == characteristic)
+            {
+                ble_characteristic.lock().set_value(value).notify();
+            }
+        }
+        config_values = values;
+        if advertisement != advertised {
+            info!("Advertising {:?}", advertisement);
+            let advertising = ble_device.get_advertising();
+            if let Err(err) = advertising.stop() {
+                warn!("Failed to stop advertising: {:?}", err);
+            }
+            if let Err(err) = advertising
+                .name(ADVERTISED_NAME)
+                .manufacturer_data(&advertisement.encode())
+                .start()
+            {
+                warn!("Failed to restart advertising: {:?}", err);
+            }
+            advertised = advertisement;
+        }
+
+        let scan_device_mgr = device_mgr.clone();
 
         scanner
             .on_result(move |scan_result| {
@@ -48,9 +207,13 @@ pub async fn ble_scanner(device_mgr: Arc<Mutex<crate::ble_device_mgr::DeviceTrac
                     scan_result.addr(),
                     scan_result.rssi()
                 );
-                device_mgr.lock().unwrap().update(
-                    *scan_result.addr(),
-                    scan_result.name(),
+                // the raw advertising or scan response data, NimBLE's decoded fields leave out
+                // flags, TX power and appearance
+                let (addr, addr_type) = to_core_address(scan_result.addr());
+                scan_device_mgr.lock().unwrap().update(
+                    addr,
+                    addr_type,
+                    scan_result.payload(),
                     scan_result.rssi(),
                 );
             })
@@ -58,67 +221,314 @@ pub async fn ble_scanner(device_mgr: Arc<Mutex<crate::ble_device_mgr::DeviceTrac
             .await
             .unwrap();
         scanner.clear_results();
+        device_mgr.lock().unwrap().expire();
     }
 }
 
+/// No radio to serve the configuration service from, so `_light_controls_chan` and
+/// `_config_passkey` go unused
 #[cfg(feature = "simulator")]
-pub async fn ble_scanner(device_mgr: Arc<Mutex<crate::ble_device_mgr::DeviceTracker>>) {
-    use const_format::concatcp;
-
-    let mut first_update = true;
+pub async fn ble_scanner(
+    device_mgr: Arc<Mutex<crate::DeviceTracker>>,
+    _light_controls_chan: smol::channel::Sender<bracer_core::messages::LightControls>,
+    _config_passkey: u32,
+) {
+    let mut scanner = bracer_core::simulator::SimulatedScanner::new(0);
 
     loop {
-        let do_update = device_mgr.lock().unwrap().devices.is_empty();
-        if do_update {
-            if !first_update {
-                smol::Timer::after(std::time::Duration::from_millis(5000)).await;
-            }
-            first_update = false;
+        {
             let mut device_mgr = device_mgr.lock().unwrap();
-            // add fake devices
-            for i in 0..10 {
-                device_mgr.update(
-                    esp32_nimble::BLEAddress::new_from_addr([i, 0x22, 0x33, 0x44, 0x55, 0x66]),
-                    "",
-                    -50 - i as i32 * 2,
-                );
-            }
+            scanner.poll(&mut device_mgr);
+            device_mgr.expire();
+        }
+        smol::Timer::after(std::time::Duration::from_millis(100)).await;
+    }
+}
 
-            // add fake favorite device
-            device_mgr.update(
-                esp32_nimble::BLEAddress::new_from_addr([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]),
-                concatcp!(crate::ble_device_mgr::FAVORITE_DEVICE_ID, ":0"),
-                -50,
-            );
+/// Settings changed at runtime, for [`settings_writer`] to save
+pub enum SettingsWrite {
+    Config(bracer_core::config::Config),
+    Rules(bracer_core::rules::Rules),
+    Pairing(bracer_core::ble_device_mgr::Pairing),
+    /// The serialized color store
+    Colors(Vec<u8>),
+}
+
+/// Color store backend that hands writes to [`settings_writer`], so they don't stall a frame.
+/// Saved colors are still loaded through `loader`, once on startup.
+pub struct ColorStoreChannel {
+    loader: Option<crate::nvs_storage::NvsColorBackend>,
+    settings_chan: std::sync::mpsc::Sender<SettingsWrite>,
+}
+
+impl bracer_core::color_store::ColorStoreBackend for ColorStoreChannel {
+    fn load(&mut self, buf: &mut [u8]) -> Option<usize> {
+        bracer_core::color_store::ColorStoreBackend::load(&mut self.loader.take()?, buf)
+    }
+
+    fn save(&mut self, data: &[u8]) {
+        if self
+            .settings_chan
+            .send(SettingsWrite::Colors(data.to_vec()))
+            .is_err()
+        {
+            warn!("Settings writer has exited, device colors won't be saved");
         }
+    }
+}
 
-        smol::Timer::after(std::time::Duration::from_millis(100)).await;
+/// Saves settings to NVS on a thread of its own, flash writes take long enough to stall a frame
+pub fn settings_writer(
+    settings_chan: std::sync::mpsc::Receiver<SettingsWrite>,
+    mut config_store: Option<crate::nvs_storage::NvsConfigStore>,
+    mut rules_store: Option<crate::nvs_storage::NvsRulesStore>,
+    mut pairing_store: Option<crate::nvs_storage::NvsPairingStore>,
+    mut color_store: Option<crate::nvs_storage::NvsColorBackend>,
+) {
+    use bracer_core::color_store::ColorStoreBackend;
+
+    for write in settings_chan {
+        match write {
+            SettingsWrite::Config(config) => {
+                if let Some(config_store) = &mut config_store {
+                    config_store.save(&config);
+                }
+            }
+            SettingsWrite::Rules(rules) => {
+                if let Some(rules_store) = &mut rules_store {
+                    rules_store.save(&rules);
+                }
+            }
+            SettingsWrite::Pairing(pairing) => {
+                if let Some(pairing_store) = &mut pairing_store {
+                    pairing_store.save(&pairing);
+                }
+            }
+            SettingsWrite::Colors(data) => {
+                if let Some(color_store) = &mut color_store {
+                    color_store.save(&data);
+                }
+            }
+        }
+    }
+    info!("Settings channel closed, exiting");
+}
+
+/// Tracker changes from the LED animator, for [`tracker_updater`] to make so the animator never
+/// waits on the tracker's lock
+pub enum TrackerUpdate {
+    /// Brightness level and mode the strip now shows, for our favorites to see
+    DisplayState(u8, bracer_core::messages::DisplaySortMode),
+    /// A configuration service write for settings the tracker keeps
+    Controls(bracer_core::messages::LightControls),
+    /// Config with a newly calibrated favorite threshold
+    Calibration(bracer_core::config::Config),
+}
+
+/// Use `config` from now on and have it saved, unless it is invalid
+fn apply_config(
+    device_mgr: &Mutex<crate::DeviceTracker>,
+    settings_chan: &std::sync::mpsc::Sender<SettingsWrite>,
+    config: bracer_core::config::Config,
+) -> Result<(), bracer_core::config::ConfigError> {
+    config.validate()?;
+    device_mgr.lock().unwrap().set_config(config);
+    if settings_chan.send(SettingsWrite::Config(config)).is_err() {
+        warn!("Settings writer has exited, config won't be saved");
+    }
+    Ok(())
+}
+
+/// Makes the LED animator's tracker changes, saving the settings that change
+pub async fn tracker_updater(
+    device_mgr: Arc<Mutex<crate::DeviceTracker>>,
+    tracker_chan: smol::channel::Receiver<TrackerUpdate>,
+    settings_chan: std::sync::mpsc::Sender<SettingsWrite>,
+) {
+    use bracer_core::messages::LightControls;
+
+    while let Ok(update) = tracker_chan.recv().await {
+        let msg = match update {
+            TrackerUpdate::DisplayState(brightness, mode) => {
+                device_mgr
+                    .lock()
+                    .unwrap()
+                    .set_display_state(brightness, mode);
+                continue;
+            }
+            TrackerUpdate::Calibration(config) => {
+                if let Err(err) = apply_config(&device_mgr, &settings_chan, config) {
+                    warn!("Ignoring favorite calibration: {}", err);
+                }
+                continue;
+            }
+            TrackerUpdate::Controls(msg) => msg,
+        };
+        match msg {
+            LightControls::SignalThresholdsChange {
+                ignore_below,
+                ignore_above,
+            } => {
+                let config = bracer_core::config::Config {
+                    signal_ignore_below: ignore_below as i32,
+                    signal_ignore_above: ignore_above as i32,
+                    ..*device_mgr.lock().unwrap().config()
+                };
+                if let Err(err) = apply_config(&device_mgr, &settings_chan, config) {
+                    warn!("Ignoring signal thresholds: {}", err);
+                }
+            }
+            LightControls::ConfigChange(config) => {
+                if let Err(err) = apply_config(&device_mgr, &settings_chan, config) {
+                    warn!("Ignoring config: {}", err);
+                }
+            }
+            LightControls::FavoriteIdChange(id) => {
+                let mut device_mgr = device_mgr.lock().unwrap();
+                let pairing = bracer_core::ble_device_mgr::Pairing {
+                    id,
+                    ..*device_mgr.pairing()
+                };
+                device_mgr.set_pairing(pairing);
+                if settings_chan.send(SettingsWrite::Pairing(pairing)).is_err() {
+                    warn!("Settings writer has exited, pairing won't be saved");
+                }
+            }
+            LightControls::FavoriteHueChange(hue) => {
+                let mut device_mgr = device_mgr.lock().unwrap();
+                let pairing = bracer_core::ble_device_mgr::Pairing {
+                    hue,
+                    ..*device_mgr.pairing()
+                };
+                device_mgr.set_pairing(pairing);
+                if settings_chan.send(SettingsWrite::Pairing(pairing)).is_err() {
+                    warn!("Settings writer has exited, pairing won't be saved");
+                }
+            }
+            LightControls::RuleAdd(_)
+            | LightControls::RuleRemove(_)
+            | LightControls::RulesClear => {
+                let mut device_mgr = device_mgr.lock().unwrap();
+                let mut rules = device_mgr.rules().clone();
+                let edited = match msg {
+                    LightControls::RuleAdd(rule) => rules.push(rule),
+                    LightControls::RuleRemove(idx) => rules.remove(idx as usize).map(|_| ()),
+                    _ => {
+                        rules.clear();
+                        Ok(())
+                    }
+                };
+                match edited {
+                    Ok(()) => {
+                        device_mgr.set_rules(rules.clone());
+                        if settings_chan.send(SettingsWrite::Rules(rules)).is_err() {
+                            warn!("Settings writer has exited, rules won't be saved");
+                        }
+                    }
+                    Err(err) => warn!("Ignoring rule change: {}", err),
+                }
+            }
+            // the LED animator handles these itself
+            _ => warn!("Not a tracker setting: {:?}", msg),
+        }
     }
+    info!("Tracker updates channel closed, exiting");
 }
 
+/// Draws frames from the latest snapshot in `snapshots`, changes to the tracker go to
+/// `tracker_chan` so a busy scan callback can't hold up a frame
 pub fn led_animator(
-    device_mgr: Arc<Mutex<crate::ble_device_mgr::DeviceTracker>>,
-    light_controls_chan: smol::channel::Receiver<crate::messages::LightControls>,
+    mut snapshots: bracer_core::triple_buffer::Reader<bracer_core::ble_device_mgr::TrackerSnapshot>,
+    light_controls_chan: smol::channel::Receiver<bracer_core::messages::LightControls>,
+    tracker_chan: smol::channel::Sender<TrackerUpdate>,
+    nvs_partition: esp_idf_svc::nvs::EspDefaultNvsPartition,
+    settings_chan: std::sync::mpsc::Sender<SettingsWrite>,
 ) {
-    let mut light_manager =
-        crate::light_mgr::LightMgr::new(device_mgr, crate::messages::DisplaySortMode::Ordered);
+    let loader = match crate::nvs_storage::NvsColorBackend::new(nvs_partition) {
+        Ok(backend) => Some(backend),
+        Err(err) => {
+            warn!(
+                "Unable to open NVS, saved device colors won't load: {}",
+                err
+            );
+            None
+        }
+    };
+    let color_store_backend = ColorStoreChannel {
+        loader,
+        settings_chan,
+    };
+    let mut light_manager = bracer_core::light_mgr::LightMgr::new(
+        bracer_core::messages::DisplaySortMode::Ordered,
+        bracer_core::color_store::ColorStore::new(
+            Box::new(color_store_backend),
+            COLOR_STORE_CAPACITY,
+        ),
+        crate::led_strip::LedStrip::<{ bracer_core::light_mgr::NUM_LIGHTS }>::new(
+            esp_idf_sys::rmt_channel_t_RMT_CHANNEL_0,
+            esp_idf_sys::gpio_num_t_GPIO_NUM_14,
+        )
+        .unwrap(),
+        rand::random(),
+        crate::clock::SystemClock,
+    );
     let interval = light_manager.get_tick_interval();
+    let mut published_display_state = None;
+    let mut jitter = bracer_core::utils::JitterStats::new(interval);
+    let mut jitter_report = std::time::Instant::now();
 
     loop {
         let start = std::time::Instant::now();
+        let now = bracer_core::time::Clock::now(&crate::clock::SystemClock);
+        jitter.record(now);
+        if jitter_report.elapsed() >= JITTER_REPORT_PERIOD {
+            info!(
+                "LED tick jitter over {} ticks: mean {:?}, max {:?}, {} missed",
+                jitter.ticks(),
+                jitter.mean(),
+                jitter.max,
+                jitter.missed
+            );
+            jitter.reset();
+            jitter_report = start;
+        }
+
         match light_controls_chan.try_recv() {
             Ok(msg) => {
                 info!("Received control message: {:?}", msg);
                 match msg {
-                    crate::messages::LightControls::BrightnessIncrease => {
+                    // while calibrating, the brightness buttons confirm and cancel the steps
+                    bracer_core::messages::LightControls::BrightnessIncrease
+                        if light_manager.is_calibrating() =>
+                    {
+                        light_manager.confirm_calibration_step()
+                    }
+                    bracer_core::messages::LightControls::BrightnessDecrease
+                        if light_manager.is_calibrating() =>
+                    {
+                        light_manager.cancel_calibration()
+                    }
+                    bracer_core::messages::LightControls::BrightnessIncrease => {
                         light_manager.increase_brightness()
                     }
-                    crate::messages::LightControls::BrightnessDecrease => {
+                    bracer_core::messages::LightControls::BrightnessDecrease => {
                         light_manager.decrease_brightness()
                     }
-                    crate::messages::LightControls::ModeChange(new_mode) => {
+                    bracer_core::messages::LightControls::CalibrationStart => {
+                        light_manager.start_calibration()
+                    }
+                    bracer_core::messages::LightControls::ModeChange(new_mode) => {
                         light_manager.switch_mode(new_mode)
                     }
+                    bracer_core::messages::LightControls::BrightnessChange(level) => {
+                        light_manager.set_brightness_level(level)
+                    }
+                    // settings the tracker keeps
+                    msg => {
+                        if let Err(err) = tracker_chan.try_send(TrackerUpdate::Controls(msg)) {
+                            warn!("Dropped tracker update: {}", err);
+                        }
+                    }
                 }
             }
             Err(err) => match err {
@@ -129,25 +539,61 @@ pub fn led_animator(
                 }
             },
         }
+        let display_state = light_manager.display_state();
+        // retried next tick if the channel is full
+        if published_display_state != Some(display_state)
+            && tracker_chan
+                .try_send(TrackerUpdate::DisplayState(
+                    display_state.0,
+                    display_state.1,
+                ))
+                .is_ok()
+        {
+            published_display_state = Some(display_state);
+        }
+        light_manager.update_from_snapshot(snapshots.latest());
+        if let Some(Ok(config)) = light_manager.take_calibration_result() {
+            if let Err(err) = tracker_chan.try_send(TrackerUpdate::Calibration(config)) {
+                warn!("Dropped favorite calibration: {}", err);
+            }
+        }
         light_manager.tick();
         let wait_time = interval.saturating_sub(start.elapsed());
         std::thread::sleep(wait_time);
     }
 }
 
-pub async fn ble_device_decayer(device_mgr: Arc<Mutex<crate::ble_device_mgr::DeviceTracker>>) {
-    let mut update_timer = smol::Timer::interval(std::time::Duration::from_secs(1));
-    loop {
-        info!("Decaying devices");
-        device_mgr.lock().unwrap().decay_tick();
-        update_timer.next().await;
+/// Forwards device events to a task, dropping them if it falls behind rather than holding up the
+/// device tracker
+pub struct DeviceEventChannel(pub smol::channel::Sender<bracer_core::device_events::DeviceEvent>);
+
+impl bracer_core::device_events::DeviceEventSink for DeviceEventChannel {
+    fn send(&mut self, event: bracer_core::device_events::DeviceEvent) {
+        if let Err(err) = self.0.try_send(event) {
+            debug!("Dropped device event: {}", err);
+        }
+    }
+}
+
+pub async fn device_event_logger(
+    device_events_chan: smol::channel::Receiver<bracer_core::device_events::DeviceEvent>,
+) {
+    use bracer_core::device_events::DeviceEvent;
+
+    while let Ok(event) = device_events_chan.recv().await {
+        match event {
+            // every advertisement, too many to log normally
+            DeviceEvent::Updated { .. } => debug!("{:?}", event),
+            _ => info!("{:?}", event),
+        }
     }
+    info!("Device events channel closed, exiting");
 }
 
 /// Monitor for button presses and the toggle switches
 /// Using polling for now, but could be changed to interrupt based
 pub async fn button_monitor(
-    light_controls_chan: smol::channel::Sender<crate::messages::LightControls>,
+    light_controls_chan: smol::channel::Sender<bracer_core::messages::LightControls>,
 ) {
     async fn wait_stable_low<T: esp_idf_hal::gpio::Pin, MODE: esp_idf_hal::gpio::InputMode>(
         pin: PinDriver<'_, T, MODE>,
@@ -192,24 +638,47 @@ pub async fn button_monitor(
     let mut switch_display_mode = PinDriver::input(gpio_pins.gpio15).unwrap();
     switch_display_mode.set_pull(Pull::Down).unwrap();
 
-    // get switch initial state
-    let mut switch_display_last_position = if switch_display_mode.is_high() {
-        SwitchPosition::Right
+    // get switch initial state, and start in the mode it is set to
+    let (mut switch_display_last_position, initial_mode) = if switch_display_mode.is_high() {
+        (
+            SwitchPosition::Right,
+            bracer_core::messages::DisplaySortMode::Ordered,
+        )
     } else {
-        SwitchPosition::Left
+        (
+            SwitchPosition::Left,
+            bracer_core::messages::DisplaySortMode::Sticky,
+        )
     };
+    light_controls_chan
+        .send(bracer_core::messages::LightControls::ModeChange(
+            initial_mode,
+        ))
+        .await
+        .unwrap();
 
     loop {
-        // Read one button per loop
-        if btn_brightness_increase.is_high() {
+        // Read one button per loop, except both brightness buttons together start calibration so
+        // give the second a moment to follow
+        if btn_brightness_increase.is_high() || btn_brightness_decrease.is_high() {
+            smol::Timer::after(BUTTON_COMBO_WINDOW).await;
+        }
+        if btn_brightness_increase.is_high() && btn_brightness_decrease.is_high() {
+            light_controls_chan
+                .send(bracer_core::messages::LightControls::CalibrationStart)
+                .await
+                .unwrap();
+            btn_brightness_increase = wait_stable_low(btn_brightness_increase).await;
+            btn_brightness_decrease = wait_stable_low(btn_brightness_decrease).await;
+        } else if btn_brightness_increase.is_high() {
             light_controls_chan
-                .send(crate::messages::LightControls::BrightnessIncrease)
+                .send(bracer_core::messages::LightControls::BrightnessIncrease)
                 .await
                 .unwrap();
             btn_brightness_increase = wait_stable_low(btn_brightness_increase).await;
         } else if btn_brightness_decrease.is_high() {
             light_controls_chan
-                .send(crate::messages::LightControls::BrightnessDecrease)
+                .send(bracer_core::messages::LightControls::BrightnessDecrease)
                 .await
                 .unwrap();
             btn_brightness_decrease = wait_stable_low(btn_brightness_decrease).await;
@@ -218,8 +687,8 @@ pub async fn button_monitor(
         {
             switch_display_last_position = SwitchPosition::Right;
             light_controls_chan
-                .send(crate::messages::LightControls::ModeChange(
-                    crate::messages::DisplaySortMode::Ordered,
+                .send(bracer_core::messages::LightControls::ModeChange(
+                    bracer_core::messages::DisplaySortMode::Ordered,
                 ))
                 .await
                 .unwrap();
@@ -228,8 +697,8 @@ pub async fn button_monitor(
         {
             switch_display_last_position = SwitchPosition::Left;
             light_controls_chan
-                .send(crate::messages::LightControls::ModeChange(
-                    crate::messages::DisplaySortMode::Sticky,
+                .send(bracer_core::messages::LightControls::ModeChange(
+                    bracer_core::messages::DisplaySortMode::Sticky,
                 ))
                 .await
                 .unwrap();